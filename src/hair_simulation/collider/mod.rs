@@ -0,0 +1,93 @@
+pub mod sdf;
+pub mod shape;
+
+#[cfg(test)]
+mod tests;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::hair_simulation::data::{convert_to_na_quat, Head};
+
+use self::{sdf::SignedDistanceField, shape::ColliderShape};
+extern crate nalgebra as na;
+
+pub const DEFAULT_COLLIDER_THICKNESS: f64 = 0.002;
+pub const DEFAULT_STATIC_FRICTION: f64 = 0.4;
+pub const DEFAULT_KINETIC_FRICTION: f64 = 0.3;
+
+// A shape placed in the world by a rigid transform, which may change every
+// step. Baked fields are shared between the copies of the simulation data.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Collider {
+    pub shape: ColliderShape,
+    pub position: na::Vector3<f64>,
+    pub rotation: na::UnitQuaternion<f64>,
+    // Rigid motion over the last step, for the velocity of the surface
+    pub velocity: na::Vector3<f64>,
+    pub angular_velocity: na::Vector3<f64>,
+    // Take the head transform before every step, for a shape in the head frame
+    pub follow_head: bool,
+    // Coulomb coefficients between the hair and the surface, the hair sticks
+    // while the tangential impulse stays below the static bound
+    pub static_friction: f64,
+    pub kinetic_friction: f64,
+    // Skin around the surface the hair is kept out of
+    pub thickness: f64,
+}
+
+impl Collider {
+    pub fn new(shape: ColliderShape) -> Self {
+        Collider {
+            shape,
+            position: na::Vector3::zeros(),
+            rotation: na::UnitQuaternion::identity(),
+            velocity: na::Vector3::zeros(),
+            angular_velocity: na::Vector3::zeros(),
+            follow_head: false,
+            static_friction: DEFAULT_STATIC_FRICTION,
+            kinetic_friction: DEFAULT_KINETIC_FRICTION,
+            thickness: DEFAULT_COLLIDER_THICKNESS,
+        }
+    }
+
+    pub fn mesh(sdf: SignedDistanceField) -> Self {
+        Collider::new(ColliderShape::Mesh(Arc::new(sdf)))
+    }
+
+    pub fn with_transform(
+        mut self,
+        position: na::Vector3<f64>,
+        rotation: na::UnitQuaternion<f64>,
+    ) -> Self {
+        self.position = position;
+        self.rotation = rotation;
+        self
+    }
+
+    // Move to the head transform, taking the velocities of the move over `delta_time`
+    pub fn follow(&mut self, head: &Head, delta_time: f64) {
+        if !self.follow_head {
+            return;
+        }
+        let rotation = convert_to_na_quat(head.rotation);
+        if delta_time > 0.0 {
+            self.velocity = (head.position - self.position) / delta_time;
+            self.angular_velocity = (rotation * self.rotation.inverse()).scaled_axis() / delta_time;
+        }
+        self.position = head.position;
+        self.rotation = rotation;
+    }
+
+    // Velocity of the rigid motion at a world point
+    pub fn surface_velocity(&self, point: &na::Vector3<f64>) -> na::Vector3<f64> {
+        self.velocity + self.angular_velocity.cross(&(point - self.position))
+    }
+
+    // Signed distance at a world point and its gradient, none away from the shape
+    pub fn distance(&self, point: &na::Vector3<f64>) -> Option<(f64, na::Vector3<f64>)> {
+        let local = self.rotation.inverse() * (point - self.position);
+        let (distance, gradient) = self.shape.distance(&local)?;
+        Some((distance, self.rotation * gradient))
+    }
+}
@@ -0,0 +1,318 @@
+use bevy::render::mesh::Mesh;
+use serde::{Deserialize, Serialize};
+extern crate nalgebra as na;
+
+use crate::hair_simulation::data::convert_to_triangles;
+
+// Cells around each triangle that get the exact distance before sweeping
+pub const SDF_EXACT_BAND: usize = 2;
+pub const SDF_SWEEP_PASSES: usize = 2;
+
+// Signed distance to a closed triangle mesh sampled on a regular grid, negative
+// inside, in the frame of the mesh
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignedDistanceField {
+    pub origin: na::Vector3<f64>,
+    pub cell_size: f64,
+    pub dims: [usize; 3],
+    pub values: Vec<f64>,
+}
+
+pub fn closest_point_on_triangle(
+    p: &na::Vector3<f64>,
+    a: &na::Vector3<f64>,
+    b: &na::Vector3<f64>,
+    c: &na::Vector3<f64>,
+) -> na::Vector3<f64> {
+    // Voronoi regions of the triangle, see Ericson, Real-Time Collision Detection 5.1.5
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(&ap);
+    let d2 = ac.dot(&ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return *a;
+    }
+
+    let bp = p - b;
+    let d3 = ab.dot(&bp);
+    let d4 = ac.dot(&bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return *b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let d5 = ab.dot(&cp);
+    let d6 = ac.dot(&cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return *c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
+impl SignedDistanceField {
+    // Exact distances near the triangles, fast sweeping of the closest triangle
+    // everywhere else and the sign from the parity of ray crossings along x,
+    // after Bridson's makelevelset3. The grid covers the mesh plus `padding`.
+    pub fn bake(
+        positions: &[na::Vector3<f64>],
+        triangles: &[[usize; 3]],
+        cell_size: f64,
+        padding: f64,
+    ) -> Self {
+        let (min, max) = positions.iter().fold(
+            (
+                na::Vector3::repeat(f64::INFINITY),
+                na::Vector3::repeat(f64::NEG_INFINITY),
+            ),
+            |(min, max), p| (min.inf(p), max.sup(p)),
+        );
+        let origin = min - na::Vector3::repeat(padding);
+        let extent = max - min + na::Vector3::repeat(2.0 * padding);
+        // At least one cell along every axis, also for a flat mesh without padding
+        let dims = [0, 1, 2].map(|k| ((extent[k] / cell_size).ceil() as usize + 1).max(2));
+
+        let mut sdf = SignedDistanceField {
+            origin,
+            cell_size,
+            dims,
+            values: vec![f64::INFINITY; dims[0] * dims[1] * dims[2]],
+        };
+        let mut closest = vec![usize::MAX; sdf.values.len()];
+
+        let distance = |node: &na::Vector3<f64>, t: usize| -> f64 {
+            let [a, b, c] = triangles[t].map(|i| positions[i]);
+            (node - closest_point_on_triangle(node, &a, &b, &c)).norm()
+        };
+
+        // Exact distances in a band around every triangle
+        for (t, triangle) in triangles.iter().enumerate() {
+            let [a, b, c] = triangle.map(|i| positions[i]);
+            let [i_range, j_range, k_range] =
+                sdf.node_range(&a.inf(&b).inf(&c), &a.sup(&b).sup(&c), SDF_EXACT_BAND);
+            for k in k_range {
+                for j in j_range.clone() {
+                    for i in i_range.clone() {
+                        let index = sdf.index(i, j, k);
+                        let d = distance(&sdf.node(i, j, k), t);
+                        if d < sdf.values[index] {
+                            sdf.values[index] = d;
+                            closest[index] = t;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Carry the closest triangle to the rest of the grid, sweeping in
+        // every diagonal direction
+        for _ in 0..SDF_SWEEP_PASSES {
+            for direction in 0..8 {
+                let step = [0, 1, 2].map(|axis| if direction & (1 << axis) == 0 { 1 } else { -1 });
+                let range = |axis: usize| -> Vec<usize> {
+                    let mut range: Vec<usize> = (1..dims[axis]).collect();
+                    if step[axis] < 0 {
+                        range = (0..(dims[axis] - 1)).rev().collect();
+                    }
+                    range
+                };
+                for &k in range(2).iter() {
+                    for &j in range(1).iter() {
+                        for &i in range(0).iter() {
+                            let index = sdf.index(i, j, k);
+                            let node = sdf.node(i, j, k);
+                            for neighbour in 1..8 {
+                                let offset = |axis: usize| -> usize {
+                                    let cell = [i, j, k][axis];
+                                    if neighbour & (1 << axis) == 0 {
+                                        cell
+                                    } else {
+                                        (cell as i64 - step[axis]) as usize
+                                    }
+                                };
+                                let t = closest[sdf.index(offset(0), offset(1), offset(2))];
+                                if t == usize::MAX || t == closest[index] {
+                                    continue;
+                                }
+                                let d = distance(&node, t);
+                                if d < sdf.values[index] {
+                                    sdf.values[index] = d;
+                                    closest[index] = t;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // Crossings of the x-aligned grid lines, counted at the first node past them
+        let mut crossings = vec![0u32; sdf.values.len()];
+        for triangle in triangles.iter() {
+            let [a, b, c] = triangle.map(|i| positions[i]);
+            let [_, j_range, k_range] = sdf.node_range(&a.inf(&b).inf(&c), &a.sup(&b).sup(&c), 0);
+            let [a, b, c] = [a, b, c].map(|p| (p - origin) / cell_size);
+            for k in k_range {
+                for j in j_range.clone() {
+                    let Some(weights) = barycentric_yz(j as f64, k as f64, &a, &b, &c) else {
+                        continue;
+                    };
+                    let x = weights[0] * a.x + weights[1] * b.x + weights[2] * c.x;
+                    let i = x.ceil().max(0.0) as usize;
+                    if i < dims[0] {
+                        crossings[sdf.index(i, j, k)] += 1;
+                    }
+                }
+            }
+        }
+        for k in 0..dims[2] {
+            for j in 0..dims[1] {
+                let mut total = 0;
+                for i in 0..dims[0] {
+                    let index = sdf.index(i, j, k);
+                    total += crossings[index];
+                    if total % 2 == 1 {
+                        sdf.values[index] = -sdf.values[index];
+                    }
+                }
+            }
+        }
+
+        sdf
+    }
+
+    pub fn from_mesh(mesh: &Mesh, cell_size: f64, padding: f64) -> Option<Self> {
+        let (positions, triangles) = convert_to_triangles(mesh)?;
+        Some(SignedDistanceField::bake(
+            &positions, &triangles, cell_size, padding,
+        ))
+    }
+
+    fn index(&self, i: usize, j: usize, k: usize) -> usize {
+        i + self.dims[0] * (j + self.dims[1] * k)
+    }
+
+    fn node(&self, i: usize, j: usize, k: usize) -> na::Vector3<f64> {
+        self.origin + na::Vector3::new(i as f64, j as f64, k as f64) * self.cell_size
+    }
+
+    // Nodes of the box from `lo` to `hi` grown by `band` cells, kept on the grid
+    fn node_range(
+        &self,
+        lo: &na::Vector3<f64>,
+        hi: &na::Vector3<f64>,
+        band: usize,
+    ) -> [std::ops::RangeInclusive<usize>; 3] {
+        [0, 1, 2].map(|axis| {
+            let first = ((lo[axis] - self.origin[axis]) / self.cell_size).floor() as i64;
+            let last = ((hi[axis] - self.origin[axis]) / self.cell_size).ceil() as i64;
+            let clamp = |cell: i64| cell.clamp(0, self.dims[axis] as i64 - 1) as usize;
+            clamp(first - band as i64)..=clamp(last + band as i64)
+        })
+    }
+
+    // Trilinear distance and the unit normal along its gradient at a point of
+    // the mesh frame, none outside the grid or where the gradient vanishes
+    pub fn sample(&self, point: &na::Vector3<f64>) -> Option<(f64, na::Vector3<f64>)> {
+        let grid = (point - self.origin) / self.cell_size;
+        let mut base = [0; 3];
+        let mut fraction = [0.0; 3];
+        for axis in 0..3 {
+            // A deserialized field may have no cell to interpolate in
+            if self.dims[axis] < 2 {
+                return None;
+            }
+            let upper = (self.dims[axis] - 1) as f64;
+            if !(grid[axis] >= 0.0 && grid[axis] <= upper) {
+                return None;
+            }
+            base[axis] = (grid[axis].floor() as usize).min(self.dims[axis] - 2);
+            fraction[axis] = grid[axis] - base[axis] as f64;
+        }
+
+        let value = |di: usize, dj: usize, dk: usize| {
+            self.values[self.index(base[0] + di, base[1] + dj, base[2] + dk)]
+        };
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let [fx, fy, fz] = fraction;
+
+        // Interpolate along x first, keeping the x differences for the gradient
+        let mut face = [[0.0; 2]; 2];
+        let mut slope = [[0.0; 2]; 2];
+        for dj in 0..2 {
+            for dk in 0..2 {
+                let (v0, v1) = (value(0, dj, dk), value(1, dj, dk));
+                face[dj][dk] = lerp(v0, v1, fx);
+                slope[dj][dk] = v1 - v0;
+            }
+        }
+        let edge = [
+            lerp(face[0][0], face[1][0], fy),
+            lerp(face[0][1], face[1][1], fy),
+        ];
+        let distance = lerp(edge[0], edge[1], fz);
+
+        let gradient = na::Vector3::new(
+            lerp(
+                lerp(slope[0][0], slope[1][0], fy),
+                lerp(slope[0][1], slope[1][1], fy),
+                fz,
+            ),
+            lerp(face[1][0] - face[0][0], face[1][1] - face[0][1], fz),
+            edge[1] - edge[0],
+        ) / self.cell_size;
+
+        // Trilinear gradients are shorter than 1 away from the nodes
+        Some((distance, gradient.try_normalize(f64::EPSILON)?))
+    }
+}
+
+// Barycentric coordinates of the line (y, z) in the triangle projected along x,
+// none when the line misses it. A line through an edge or a vertex only hits
+// the triangles owning it, like the top-left rule of rasterisers, so that it
+// crosses a closed mesh there as often as anywhere else.
+fn barycentric_yz(
+    y: f64,
+    z: f64,
+    a: &na::Vector3<f64>,
+    b: &na::Vector3<f64>,
+    c: &na::Vector3<f64>,
+) -> Option<[f64; 3]> {
+    let edge = |p: &na::Vector3<f64>, q: &na::Vector3<f64>| {
+        (q.y - p.y) * (z - p.z) - (q.z - p.z) * (y - p.y)
+    };
+    let weights = [edge(b, c), edge(c, a), edge(a, b)];
+    let total: f64 = weights.iter().sum();
+    if total == 0.0 {
+        return None;
+    }
+    // Edges taken counter-clockwise in the projection, a neighbour walks the
+    // shared one the other way and so owns it when this triangle does not
+    let orientation = total.signum();
+    for (weight, (p, q)) in weights.iter().zip([(b, c), (c, a), (a, b)]) {
+        let (dy, dz) = (orientation * (q.y - p.y), orientation * (q.z - p.z));
+        let owned = dz > 0.0 || (dz == 0.0 && dy < 0.0);
+        let weight = orientation * weight;
+        if weight < 0.0 || (weight == 0.0 && !owned) {
+            return None;
+        }
+    }
+    Some(weights.map(|weight| weight / total))
+}
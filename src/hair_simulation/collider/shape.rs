@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use super::sdf::SignedDistanceField;
+extern crate nalgebra as na;
+
+// Geometry of a collider in its own frame
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ColliderShape {
+    // A baked mesh, see `SignedDistanceField`
+    Mesh(Arc<SignedDistanceField>),
+    Sphere { radius: f64 },
+    // Around the segment from -half_length to half_length on y
+    Capsule { radius: f64, half_length: f64 },
+    Box { half_extents: na::Vector3<f64> },
+    // The plane y = 0, solid below
+    Plane,
+    Ellipsoid { radii: na::Vector3<f64> },
+}
+
+impl ColliderShape {
+    // Signed distance at a point of the collider frame and its gradient, none
+    // where the shape does not say
+    pub fn distance(&self, point: &na::Vector3<f64>) -> Option<(f64, na::Vector3<f64>)> {
+        match self {
+            ColliderShape::Mesh(sdf) => sdf.sample(point),
+            ColliderShape::Sphere { radius } => {
+                let normal = point.try_normalize(0.0)?;
+                Some((point.norm() - radius, normal))
+            }
+            ColliderShape::Capsule {
+                radius,
+                half_length,
+            } => {
+                let axis = na::Vector3::new(0.0, point.y.clamp(-half_length, *half_length), 0.0);
+                let offset = point - axis;
+                Some((offset.norm() - radius, offset.try_normalize(0.0)?))
+            }
+            ColliderShape::Box { half_extents } => {
+                let q = point.abs() - half_extents;
+                let outside = q.sup(&na::Vector3::zeros());
+                if outside.norm() > 0.0 {
+                    let gradient = outside.normalize().component_mul(&point.map(f64::signum));
+                    return Some((outside.norm(), gradient));
+                }
+                // Inside, towards the nearest face
+                let axis = q.imax();
+                let mut gradient = na::Vector3::zeros();
+                gradient[axis] = point[axis].signum();
+                Some((q[axis], gradient))
+            }
+            ColliderShape::Plane => Some((point.y, na::Vector3::y())),
+            ColliderShape::Ellipsoid { radii } => {
+                // Bound d = k0 (k0 - 1) / k1 with k0 = |p / r| and k1 = |p / r^2|,
+                // exact on the surface
+                let u = point.component_div(radii);
+                let w = u.component_div(radii);
+                let k0 = u.norm();
+                let k1 = w.norm();
+                if k1 == 0.0 {
+                    return None;
+                }
+                let d_k0 = w / k0;
+                let d_k1 = w.component_div(radii).component_div(radii) / k1;
+                let distance = k0 * (k0 - 1.0) / k1;
+                let gradient = ((2.0 * k0 - 1.0) * d_k0 * k1 - k0 * (k0 - 1.0) * d_k1) / k1.powi(2);
+                // The bound grows slower or faster than the distance off the
+                // surface, its direction is still the normal
+                Some((distance, gradient.try_normalize(0.0)?))
+            }
+        }
+    }
+}
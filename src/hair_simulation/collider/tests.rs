@@ -0,0 +1,250 @@
+use bevy::{math::primitives::Sphere, render::mesh::Mesh};
+
+use super::{sdf::SignedDistanceField, shape::ColliderShape, Collider};
+extern crate nalgebra as na;
+
+const RADIUS: f64 = 0.1;
+const CELL_SIZE: f64 = 0.01;
+
+// Linear congruential generator, enough for reproducible sample points
+struct Lcg(u64);
+
+impl Lcg {
+    // Uniform in [-1, 1)
+    fn next(&mut self) -> f64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+
+    fn vector(&mut self) -> na::Vector3<f64> {
+        na::Vector3::new(self.next(), self.next(), self.next())
+    }
+}
+
+fn sphere_field() -> SignedDistanceField {
+    SignedDistanceField::from_mesh(&Mesh::from(Sphere::new(RADIUS as f32)), CELL_SIZE, 0.05)
+        .unwrap()
+}
+
+#[test]
+fn sphere_field_matches_analytic_distance() {
+    let sdf = sphere_field();
+    let mut rng = Lcg(7);
+    let mut worst: f64 = 0.0;
+    for _ in 0..2000 {
+        let point = 0.14 * rng.vector();
+        let (distance, _) = sdf.sample(&point).unwrap();
+        worst = worst.max((distance - (point.norm() - RADIUS)).abs());
+    }
+
+    println!("sphere field: worst distance error {:e}", worst);
+    assert!(worst < 0.25 * CELL_SIZE);
+}
+
+#[test]
+fn rays_through_edges_cross_once() {
+    // Octahedron with its vertices on grid nodes, so that rows of nodes run
+    // through its edges and vertices
+    let positions = vec![
+        na::Vector3::new(1.0, 0.0, 0.0),
+        na::Vector3::new(-1.0, 0.0, 0.0),
+        na::Vector3::new(0.0, 1.0, 0.0),
+        na::Vector3::new(0.0, -1.0, 0.0),
+        na::Vector3::new(0.0, 0.0, 1.0),
+        na::Vector3::new(0.0, 0.0, -1.0),
+    ];
+    let mut triangles = Vec::new();
+    for x in [0, 1] {
+        for y in [2, 3] {
+            for z in [4, 5] {
+                triangles.push([x, y, z]);
+            }
+        }
+    }
+    let sdf = SignedDistanceField::bake(&positions, &triangles, 0.25, 0.5);
+
+    for k in 0..sdf.dims[2] {
+        for j in 0..sdf.dims[1] {
+            for i in 0..sdf.dims[0] {
+                let node = sdf.origin + na::Vector3::new(i as f64, j as f64, k as f64) * 0.25;
+                let value = sdf.values[i + sdf.dims[0] * (j + sdf.dims[1] * k)];
+                let manhattan = node.abs().sum();
+                if manhattan < 1.0 {
+                    assert!(value < 0.0, "{:?}", node);
+                } else if manhattan > 1.0 {
+                    assert!(value > 0.0, "{:?}", node);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn field_normal_follows_finite_differences() {
+    let sdf = sphere_field();
+    let mut rng = Lcg(42);
+    let epsilon = 1e-7;
+    let mut worst: f64 = 0.0;
+    for _ in 0..200 {
+        let point = 0.14 * rng.vector();
+        let (_, normal) = sdf.sample(&point).unwrap();
+        let gradient = na::Vector3::from_fn(|k, _| {
+            let mut offset = na::Vector3::zeros();
+            offset[k] = epsilon;
+            let plus = sdf.sample(&(point + offset)).unwrap().0;
+            let minus = sdf.sample(&(point - offset)).unwrap().0;
+            (plus - minus) / (2.0 * epsilon)
+        });
+        assert!((normal.norm() - 1.0).abs() < 1e-12);
+        worst = worst.max((normal - gradient.normalize()).amax());
+    }
+
+    println!("sphere field: worst normal error {:e}", worst);
+    assert!(worst < 1e-5);
+}
+
+#[test]
+fn flat_field_has_no_normal() {
+    let sdf = SignedDistanceField {
+        origin: na::Vector3::zeros(),
+        cell_size: 1.0,
+        dims: [2, 2, 2],
+        values: vec![0.5; 8],
+    };
+    assert!(sdf.sample(&na::Vector3::repeat(0.5)).is_none());
+}
+
+#[test]
+fn flat_mesh_bakes_at_least_one_cell() {
+    let positions = [
+        na::Vector3::new(0.0, 0.0, 0.0),
+        na::Vector3::new(1.0, 0.0, 0.0),
+        na::Vector3::new(0.0, 1.0, 0.0),
+    ];
+    let sdf = SignedDistanceField::bake(&positions, &[[0, 1, 2]], 0.25, 0.0);
+    assert_eq!(sdf.dims[2], 2);
+    assert!(sdf.sample(&na::Vector3::new(0.2, 0.2, 0.0)).is_some());
+
+    let single = SignedDistanceField {
+        origin: na::Vector3::zeros(),
+        cell_size: 1.0,
+        dims: [1, 1, 1],
+        values: vec![0.0],
+    };
+    assert!(single.sample(&na::Vector3::zeros()).is_none());
+}
+
+#[test]
+fn collider_follows_its_transform() {
+    let collider = Collider::mesh(sphere_field()).with_transform(
+        na::Vector3::new(0.3, 2.0, -0.1),
+        na::UnitQuaternion::from_euler_angles(0.3, -1.2, 0.5),
+    );
+
+    let mut rng = Lcg(1234);
+    for _ in 0..100 {
+        let local = 0.14 * rng.vector();
+        let world = collider.rotation * local + collider.position;
+        let (distance, gradient) = collider.distance(&world).unwrap();
+        let (expected, local_gradient) = collider.shape.distance(&local).unwrap();
+        assert!((distance - expected).abs() < 1e-12);
+        assert!((gradient - collider.rotation * local_gradient).norm() < 1e-12);
+    }
+    assert!(collider
+        .distance(&(collider.position + na::Vector3::new(1.0, 0.0, 0.0)))
+        .is_none());
+}
+
+#[test]
+fn primitive_distances() {
+    let capsule = ColliderShape::Capsule {
+        radius: 0.1,
+        half_length: 0.5,
+    };
+    let cube = ColliderShape::Box {
+        half_extents: na::Vector3::new(0.1, 0.2, 0.3),
+    };
+    let ellipsoid = ColliderShape::Ellipsoid {
+        radii: na::Vector3::new(0.1, 0.2, 0.3),
+    };
+    let cases = [
+        (&capsule, na::Vector3::new(0.3, 0.2, 0.0), 0.2),
+        (&capsule, na::Vector3::new(0.0, 0.8, 0.0), 0.2),
+        (&capsule, na::Vector3::new(0.05, -0.4, 0.0), -0.05),
+        (&cube, na::Vector3::new(0.4, 0.0, 0.0), 0.3),
+        (&cube, na::Vector3::new(0.4, 0.6, 0.0), 0.5),
+        (&cube, na::Vector3::new(0.0, 0.15, 0.0), -0.05),
+        (&ellipsoid, na::Vector3::new(0.0, 0.0, 0.3), 0.0),
+        (&ellipsoid, na::Vector3::new(0.0, 0.25, 0.0), 0.05),
+        (
+            &ColliderShape::Plane,
+            na::Vector3::new(3.0, -0.2, 1.0),
+            -0.2,
+        ),
+        (
+            &ColliderShape::Sphere { radius: 0.1 },
+            na::Vector3::new(0.0, 0.3, 0.4),
+            0.4,
+        ),
+    ];
+    for (shape, point, expected) in cases {
+        let (distance, _) = shape.distance(&point).unwrap();
+        assert!(
+            (distance - expected).abs() < 1e-12,
+            "{:?} at {:?}: {} instead of {}",
+            shape,
+            point,
+            distance,
+            expected
+        );
+    }
+}
+
+#[test]
+fn primitive_gradients_match_finite_differences() {
+    let shapes = [
+        ColliderShape::Sphere { radius: 0.1 },
+        ColliderShape::Capsule {
+            radius: 0.1,
+            half_length: 0.2,
+        },
+        ColliderShape::Box {
+            half_extents: na::Vector3::new(0.1, 0.2, 0.15),
+        },
+        ColliderShape::Plane,
+        ColliderShape::Ellipsoid {
+            radii: na::Vector3::new(0.1, 0.2, 0.15),
+        },
+    ];
+    let epsilon = 1e-7;
+    for shape in shapes.iter() {
+        let mut rng = Lcg(1);
+        let mut worst: f64 = 0.0;
+        for _ in 0..500 {
+            let point = 0.4 * rng.vector();
+            let (_, gradient) = shape.distance(&point).unwrap();
+            // Unit normals, also where the distance is only a bound
+            assert!(
+                (gradient.norm() - 1.0).abs() < 1e-12,
+                "{:?} at {:?}",
+                shape,
+                point
+            );
+            let mut difference = na::Vector3::zeros();
+            for k in 0..3 {
+                let mut offset = na::Vector3::zeros();
+                offset[k] = epsilon;
+                let plus = shape.distance(&(point + offset)).unwrap().0;
+                let minus = shape.distance(&(point - offset)).unwrap().0;
+                difference[k] = (plus - minus) / (2.0 * epsilon);
+            }
+            worst = worst.max((gradient - difference.normalize()).norm());
+        }
+
+        println!("{:?}: worst gradient error {:e}", shape, worst);
+        assert!(worst < 1e-5, "{:?} gradient", shape);
+    }
+}
@@ -0,0 +1,276 @@
+use serde::{Deserialize, Serialize};
+
+use crate::hair_simulation::{data::StrandShape, scalp::RootSampling};
+
+// Switches and parameters of the solver, carried along with the simulation data.
+// Fields left out of a serialized config take their defaults.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimulationConfig {
+    pub energies: EnergyTerms,
+    pub damping: Damping,
+    pub inextensibility: Inextensibility,
+    pub contact_response: ContactResponse,
+    pub hair_collision: HairCollision,
+    // Hold the root material frame to the follicle orientation of the head
+    pub clamp_root_frame: bool,
+    pub time_stepping: TimeStepping,
+    pub integrator: Integrator,
+    pub nonlinear_solver: NonlinearSolver,
+    pub linear_solver: LinearSolver,
+    pub failure_policy: FailurePolicy,
+}
+
+impl SimulationConfig {
+    // Settings that have no effect in combination with the others
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.damping.stiffness != 0.0 && !self.integrator.needs_hessian() {
+            warnings.push(format!(
+                "damping.stiffness is ignored by {:?}, which assembles no Hessian",
+                self.integrator
+            ));
+        }
+        warnings
+    }
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            energies: Default::default(),
+            damping: Default::default(),
+            inextensibility: Default::default(),
+            contact_response: Default::default(),
+            hair_collision: Default::default(),
+            clamp_root_frame: true,
+            time_stepping: Default::default(),
+            integrator: Default::default(),
+            nonlinear_solver: Default::default(),
+            linear_solver: Default::default(),
+            failure_policy: Default::default(),
+        }
+    }
+}
+
+// How `default_scene` grows its hair. Fields left out of a serialized scene
+// config take their defaults.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SceneConfig {
+    // Rest shape of every strand, e.g. `Curly(radius: 0.01, pitch: 0.03, phase: 0.0)`
+    pub shape: StrandShape,
+    // Simulated strands sampled over the scalp, one at every scalp vertex when None
+    pub guide_num: Option<usize>,
+    // How the sampled guides and the drawn strands spread over the scalp
+    pub root_sampling: RootSampling,
+    pub seed: u64,
+    // Relative density and strand length scale at each scalp vertex, uniform
+    // and 1 when empty
+    pub density_map: Vec<f64>,
+    pub length_map: Vec<f64>,
+}
+
+// Damping forces of the DER step, all off by default.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Damping {
+    // Rayleigh damping C = mass * M + stiffness * K, in 1/s and s. The mass
+    // part also slows rigid motion, the stiffness part only deformation. K is
+    // the Hessian of the step, so stiffness does nothing under SymplecticEuler.
+    pub mass: f64,
+    pub stiffness: f64,
+    // Quadratic drag on the normal velocity of each segment, rho * C_d / 2 in
+    // kg/m^3, e.g. 0.6 for a cylinder in air
+    pub air_drag: f64,
+}
+
+// Correction of the edge lengths after the solve, off by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Inextensibility {
+    #[default]
+    Off,
+    // Gauss-Seidel sweeps clamping the strain of every edge to +-max_strain.
+    // Along a strand the strains only approach the limit sweep by sweep, a few
+    // tens of sweeps reach it for the stretch of one step.
+    StrainLimiting {
+        max_strain: f64,
+        iterations: usize,
+    },
+    // Fast projection (Goldenthal et al. 2007), mass-weighted steps on all edge
+    // constraints at once until every strain is below tolerance
+    FastProjection {
+        tolerance: f64,
+        max_iterations: usize,
+    },
+}
+
+// Contacts with the colliders resolved on the velocities after the solve: the
+// inbound normal velocity is removed and Coulomb friction applied, with the
+// static and kinetic coefficients of each collider.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContactResponse {
+    pub enabled: bool,
+    // Part of the inbound normal velocity returned, 0 for no bounce
+    pub restitution: f64,
+}
+
+impl Default for ContactResponse {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            restitution: 0.0,
+        }
+    }
+}
+
+// Repulsion between the segments of different strands, found through a
+// spatial hash after every step. Off by default, it is the most expensive part
+// of a step with many strands.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HairCollision {
+    pub enabled: bool,
+    // Distance between the centerlines below which two segments touch
+    pub distance: f64,
+    // Part of the overlap below the distance removed from the positions at
+    // every step
+    pub repulsion: f64,
+    // Coulomb coefficient between strands
+    pub friction: f64,
+}
+
+impl Default for HairCollision {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            distance: 0.002,
+            repulsion: 0.5,
+            friction: 0.2,
+        }
+    }
+}
+
+// How one scheduler frame is split into solver steps.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeStepping {
+    // Simulated time advanced by every frame
+    pub frame_time: f64,
+    // Use frame_time / min_substeps without looking at the strands when false
+    pub adaptive: bool,
+    pub min_substeps: usize,
+    pub max_substeps: usize,
+    // Largest vertex displacement of a substep, relative to the shortest segment
+    pub max_displacement: f64,
+    // Largest change of the strain of an edge over a substep
+    pub max_strain_change: f64,
+    // Relative growth of the total energy over a substep that rejects it
+    pub max_energy_increase: f64,
+}
+
+impl Default for TimeStepping {
+    fn default() -> Self {
+        Self {
+            frame_time: 0.005,
+            adaptive: true,
+            min_substeps: 1,
+            max_substeps: 16,
+            max_displacement: 0.5,
+            max_strain_change: 0.01,
+            max_energy_increase: 0.1,
+        }
+    }
+}
+
+// Time stepping scheme of the DER step.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Integrator {
+    // Linearised backward Euler, one solve per step, strongly damped
+    #[default]
+    ImplicitEuler,
+    // Linearised second order backward differences, one solve per step, less
+    // numerical damping. Needs the previous step, starts with backward Euler,
+    // and weighs it by the ratio of the step sizes when substeps vary.
+    Bdf2,
+    // Explicit velocity update then position update, no Hessian and no
+    // factorization but only stable for small steps
+    SymplecticEuler,
+}
+
+// How many linear solves a step of an implicit integrator takes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum NonlinearSolver {
+    // A single solve with the Hessian of the start of the step
+    #[default]
+    Linearized,
+    // Newton iterations on the incremental potential with a backtracking line
+    // search, until an iteration moves no dof by more than `tolerance`
+    Newton {
+        max_iterations: usize,
+        tolerance: f64,
+    },
+}
+
+// How the per-strand linear system is factorized.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LinearSolver {
+    // Banded LDL^T, linear in the number of vertices
+    #[default]
+    BandedLdlt,
+    // Dense LU, cubic in the number of vertices. Kept as a reference.
+    DenseLu,
+}
+
+// What to do with a strand whose linear system cannot be solved.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FailurePolicy {
+    // Retry with a shifted diagonal, revert the strand if that fails too
+    #[default]
+    Regularize,
+    // Restore the strand to the start of the step and stop it
+    Revert,
+    // Revert and exclude the strand from all later steps
+    Freeze,
+}
+
+// Toggle the terms assembled by the DER step, e.g. disable bend to debug.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnergyTerms {
+    pub stretch: bool,
+    pub bend: bool,
+    pub twist: bool,
+    pub gravity: bool,
+    pub head_contact: bool,
+    pub collider_contact: bool,
+    pub rod_model: RodModel,
+}
+
+impl Default for EnergyTerms {
+    fn default() -> Self {
+        Self {
+            stretch: true,
+            bend: true,
+            twist: true,
+            gravity: true,
+            head_contact: true,
+            // Replaced by `ContactResponse`, which also applies friction
+            collider_contact: false,
+            rod_model: Default::default(),
+        }
+    }
+}
+
+// How the stretch, bend and gravity terms are discretized.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RodModel {
+    // The forces of the do_der loop the terms were split from, kept as they
+    // were. Bend is not the gradient of its energy, see `methods::original`.
+    Original,
+    // Gradients and Gauss-Newton Hessians of the discrete energies. Internal
+    // forces sum to zero and rigid motions cost nothing.
+    #[default]
+    Consistent,
+}
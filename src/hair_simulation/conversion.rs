@@ -16,8 +16,15 @@ use bevy::{
 
 use crate::{
     hair_simulation::{
-        data::{convert_to_na_vec3, generate_batch_hair_strands},
-        HeadMarker, HAIR_SEG_LENGTH, HAIR_THICKNESS,
+        collider::{sdf::SignedDistanceField, shape::ColliderShape, Collider},
+        config::SceneConfig,
+        data::{
+            convert_to_na_quat, convert_to_na_vec3, generate_rooted_hair_strands, Head,
+            SimulationData, StrandParams,
+        },
+        interpolation::RenderHairs,
+        scalp::ScalpMesh,
+        HeadMarker, HAIR_SEG_LENGTH, HAIR_THICKNESS, HEAD_SDF_CELL_SIZE, RENDER_HAIR_NUM,
     },
     physic_simulation::scheduler::PhsicaSimulationScheduler,
     plugins::instanced_mesh::{InstanceData, InstanceMaterialData},
@@ -25,6 +32,22 @@ use crate::{
 
 use super::{data::convert_to_vec3, HairsMarker};
 
+// Drawn strands, several interpolated ones per guide when there are render hairs
+fn hair_instance_data(
+    data: &SimulationData,
+    render_hairs: Option<&RenderHairs>,
+) -> Vec<InstanceData> {
+    match render_hairs {
+        Some(render_hairs) => render_hairs.to_instance_data(data),
+        None => data
+            .hairs
+            .strands
+            .iter()
+            .flat_map(|hair| hair.to_instance_data())
+            .collect(),
+    }
+}
+
 pub fn reset_simulation(
     scheduler: &mut PhsicaSimulationScheduler,
     commands: &mut Commands,
@@ -38,6 +61,101 @@ pub fn reset_simulation(
     scheduler.entities.clear();
 }
 
+// The head with hair grown on its cap, colliding through its baked mesh and
+// with the ground, grown as the scene config asks. Also gives the drawn
+// strands and the mesh of the head.
+pub fn default_scene(scene: &SceneConfig) -> Result<(SimulationData, RenderHairs, Mesh), String> {
+    let head_position = Vec3::new(0., 2., 0.);
+    let head_radius = 0.1;
+
+    let head_mesh = Mesh::from(Sphere::new(head_radius as f32));
+
+    // Grow the hair on the cap of the head within 45 degrees of the top
+    let mut scalp = ScalpMesh::from_mesh(&head_mesh)
+        .expect("head mesh is a triangle list")
+        .region(|p| p.y > head_radius * f64::cos(PI / 4.0));
+    for (name, map) in [
+        ("density", &scene.density_map),
+        ("length", &scene.length_map),
+    ] {
+        if !map.is_empty() && map.len() != scalp.positions.len() {
+            return Err(format!(
+                "{} map has {} values for {} scalp vertices",
+                name,
+                map.len(),
+                scalp.positions.len()
+            ));
+        }
+    }
+    if !scene.density_map.is_empty() {
+        scalp = scalp.with_density_map(scene.density_map.clone());
+    }
+    if !scene.length_map.is_empty() {
+        scalp = scalp.with_length_map(scene.length_map.clone());
+    }
+
+    // Guide strands are simulated, the drawn ones follow them
+    let roots = match scene.guide_num {
+        Some(guide_num) => scalp.sample_roots(scene.root_sampling, guide_num, scene.seed),
+        None => scalp.vertex_roots(),
+    };
+
+    let mut simulation_data = generate_rooted_hair_strands(
+        Head {
+            position: convert_to_na_vec3(head_position),
+            radius: head_radius,
+            ..Default::default()
+        },
+        &roots,
+        0.5,
+        10e-6,
+        &StrandParams {
+            shape: scene.shape,
+            ..Default::default()
+        },
+    );
+
+    // The head collides through its baked mesh instead of the analytic sphere
+    let head_sdf = SignedDistanceField::from_mesh(&head_mesh, HEAD_SDF_CELL_SIZE, 0.05)
+        .expect("head mesh is a triangle list");
+    let head = &simulation_data.head;
+    let mut head_collider =
+        Collider::mesh(head_sdf).with_transform(head.position, convert_to_na_quat(head.rotation));
+    head_collider.follow_head = true;
+    simulation_data.colliders.push(head_collider);
+
+    // The ground plane spawned with the scene
+    simulation_data
+        .colliders
+        .push(Collider::new(ColliderShape::Plane));
+    simulation_data.config.energies.head_contact = false;
+
+    // Drawn strands between the guides of the triangle they grow on, or near
+    // the sampled guides
+    let render_hairs = match scene.guide_num {
+        Some(_) => RenderHairs::nearest_guides(
+            &scalp.sample_roots(
+                scene.root_sampling,
+                RENDER_HAIR_NUM,
+                scene.seed.wrapping_add(1),
+            ),
+            &simulation_data,
+            3,
+            0.3,
+        ),
+        None => RenderHairs::barycentric(
+            &scalp,
+            &(0..roots.len()).collect::<Vec<_>>(),
+            scene.root_sampling,
+            RENDER_HAIR_NUM,
+            scene.seed,
+            0.3,
+        ),
+    };
+
+    Ok((simulation_data, render_hairs, head_mesh))
+}
+
 pub fn init_simulation(
     scheduler: &mut PhsicaSimulationScheduler,
     commands: &mut Commands,
@@ -46,30 +164,12 @@ pub fn init_simulation(
 ) {
     info!("init_simulation");
 
-    let head_position = Vec3::new(0., 2., 0.);
-    let head_radius = 0.1;
-
-    scheduler.simulation_data = generate_batch_hair_strands(
-        convert_to_na_vec3(head_position.clone()),
-        head_radius,
-        PI / 4.0,
-        5,
-        0.5,
-        30,
-        1.9e10,
-        7.1e9,
-        10e-6,
-        0.0001,
-        0,
-    );
+    let (simulation_data, render_hairs, head_mesh) =
+        default_scene(&SceneConfig::default()).expect("the default scene config is valid");
+    scheduler.simulation_data = simulation_data;
+    let head_position = convert_to_vec3(scheduler.simulation_data.head.position);
 
-    let hair_data: Vec<InstanceData> = scheduler
-        .simulation_data
-        .hairs
-        .strands
-        .iter()
-        .flat_map(|hair| hair.to_instance_data())
-        .collect();
+    let hair_data = hair_instance_data(&scheduler.simulation_data, Some(&render_hairs));
 
     scheduler.entities.insert(
         "hairs".to_string(),
@@ -79,6 +179,7 @@ pub fn init_simulation(
                 HairsMarker,
                 SpatialBundle::INHERITED_IDENTITY,
                 InstanceMaterialData(hair_data),
+                render_hairs,
                 NoFrustumCulling,
             ))
             .id(),
@@ -88,7 +189,7 @@ pub fn init_simulation(
         "head".to_string(),
         commands
             .spawn(PbrBundle {
-                mesh: meshes.add(Sphere::new(head_radius as f32)),
+                mesh: meshes.add(head_mesh),
                 material: materials.add(StandardMaterial {
                     base_color: Color::rgba(1.0, 0.8, 0.6, 0.5),
                     alpha_mode: AlphaMode::Mask(0.5),
@@ -104,7 +205,11 @@ pub fn init_simulation(
 
 pub fn do_apply(
     mut scheduler_query: Query<&mut PhsicaSimulationScheduler>,
-    mut hairs_query: Query<(&HairsMarker, &mut InstanceMaterialData)>,
+    mut hairs_query: Query<(
+        &HairsMarker,
+        &mut InstanceMaterialData,
+        Option<&RenderHairs>,
+    )>,
     mut head_query: Query<(&HeadMarker, &mut Transform)>,
 ) {
     match scheduler_query.get_single_mut() {
@@ -114,9 +219,9 @@ pub fn do_apply(
 
                 match head_query.get_single_mut() {
                     Ok((_, mut head_transform)) => {
-                        head_transform.translation =
-                            convert_to_vec3(scheduler.simulation_data.head.position);
-                        head_transform.rotation = scheduler.simulation_data.head.rotation;
+                        let head = &scheduler.displayed_data().head;
+                        head_transform.translation = convert_to_vec3(head.position);
+                        head_transform.rotation = head.rotation;
                     }
                     Err(_) => {
                         info!("head not found");
@@ -124,16 +229,9 @@ pub fn do_apply(
                 }
 
                 match hairs_query.get_single_mut() {
-                    Ok((_, mut instance_material_data)) => {
-                        let hair_data: Vec<InstanceData> = scheduler
-                            .simulation_data
-                            .hairs
-                            .strands
-                            .iter()
-                            .flat_map(|hair| hair.to_instance_data())
-                            .collect();
-
-                        instance_material_data.0 = hair_data;
+                    Ok((_, mut instance_material_data, render_hairs)) => {
+                        instance_material_data.0 =
+                            hair_instance_data(scheduler.displayed_data(), render_hairs);
                     }
                     Err(_) => {
                         info!("hairs not found");
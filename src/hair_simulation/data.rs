@@ -3,21 +3,38 @@ use std::f64::consts::PI;
 use bevy::{
     log::info,
     math::{Quat, Vec3},
+    render::{
+        mesh::{Indices, Mesh, VertexAttributeValues},
+        render_resource::PrimitiveTopology,
+    },
     utils::{info, label},
 };
+use serde::{Deserialize, Serialize};
 
 use crate::plugins::instanced_mesh::InstanceData;
 extern crate nalgebra as na;
-use super::{pipeline::der::utils::parallel_transport, HAIR_SEG_LENGTH};
+use super::{
+    collider::Collider,
+    config::SimulationConfig,
+    pipeline::der::{
+        methods::twist::{calc_twist, wrap_angle},
+        state::StrandState,
+        utils::{parallel_transport, transport_frame},
+    },
+    scalp::HairRoot,
+    HAIR_SEG_LENGTH,
+};
 
 //  Add anything necessary during the simulation HERE.
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct SimulationData {
     pub head: Head,
     pub hairs: Hairs,
+    pub colliders: Vec<Collider>,
+    pub config: SimulationConfig,
 }
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct Head {
     pub position: na::Vector3<f64>,
     pub radius: f64,
@@ -25,23 +42,34 @@ pub struct Head {
     pub attachments: Vec<na::Vector3<f64>>,
 }
 
-#[derive(Default, Clone)]
+impl Head {
+    pub fn to_world(&self, local: &na::Vector3<f64>) -> na::Vector3<f64> {
+        convert_to_na_quat(self.rotation) * local + self.position
+    }
+
+    pub fn to_local(&self, world: &na::Vector3<f64>) -> na::Vector3<f64> {
+        convert_to_na_quat(self.rotation).inverse() * (world - self.position)
+    }
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct Hairs {
     pub strands: Vec<HairStrand>,
 }
 
-#[derive(Default, Clone, Debug)]
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct Frame {
     pub b: na::Vector3<f64>,
     pub n: na::Vector3<f64>,
     pub t: na::Vector3<f64>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct HairStrand {
     // Attachment Reference to Head
     pub attachment: usize,
-    pub last_pin: usize,
+    // Excluded from the solve after an unrecoverable failure
+    pub frozen: bool,
 
     // Basic properties
     pub radius: f64,
@@ -53,6 +81,14 @@ pub struct HairStrand {
     pub v_mass: Vec<f64>,
     pub v_position: Vec<na::Vector3<f64>>,
     pub v_velocity: Vec<na::Vector3<f64>>,
+    // Pinned vertices follow the head instead of being solved
+    pub v_pinned: Vec<bool>,
+    // Vertices in the head frame, empty until `attach_to_head`
+    pub v_head_position: Vec<na::Vector3<f64>>,
+    // Twist of the reference frame across each vertex, zero at both ends
+    pub v_reference_twist: Vec<f64>,
+    // Material twist across each vertex at rest, zero at both ends
+    pub v_rest_twist: Vec<f64>,
 
     // Lines
     pub l_num: usize,
@@ -60,10 +96,22 @@ pub struct HairStrand {
     pub l_rest_length: Vec<f64>,
     pub l_twist: Vec<f64>,
     pub l_angular: Vec<f64>,
-    pub l_initial_kappa: Vec<na::Matrix4x1<f64>>,
+    // Material curvature at rest, by vertex like `StrandState::kappa`
+    pub l_rest_kappa: Vec<na::Matrix4x1<f64>>,
 
     // Reference Frame
     pub reference_frame: Vec<Frame>,
+    // Material direction m1 of the root edge in the head frame, the follicle
+    // orientation the root twist is clamped to
+    pub root_director: Option<na::Vector3<f64>>,
+
+    // State of the previous step, empty until the first step ran
+    pub v_previous_position: Vec<na::Vector3<f64>>,
+    pub v_previous_velocity: Vec<na::Vector3<f64>>,
+    pub l_previous_twist: Vec<f64>,
+    pub l_previous_angular: Vec<f64>,
+    // Step that led from the previous state to the current one
+    pub previous_delta_time: f64,
 }
 
 pub fn convert_to_vec3(v: na::Vector3<f64>) -> Vec3 {
@@ -74,32 +122,73 @@ pub fn convert_to_na_vec3(v: Vec3) -> na::Vector3<f64> {
     na::Vector3::new(v.x as f64, v.y as f64, v.z as f64)
 }
 
+pub fn convert_to_na_quat(q: Quat) -> na::UnitQuaternion<f64> {
+    na::UnitQuaternion::from_quaternion(na::Quaternion::new(
+        q.w as f64, q.x as f64, q.y as f64, q.z as f64,
+    ))
+}
+
+// One instance per segment of a strand drawn through `positions`
+pub fn segment_instance_data(positions: &[na::Vector3<f64>]) -> Vec<InstanceData> {
+    let mut instance_data = Vec::new();
+    for i in 0..(positions.len() - 2) {
+        let from_pos = positions[i];
+        let to_pos = positions[i + 1];
+
+        let strand_length = (to_pos - from_pos).norm();
+        let strand_translation = (from_pos + to_pos) / 2.0;
+        let strand_rotation =
+            Quat::from_rotation_arc(Vec3::Y, convert_to_vec3((to_pos - from_pos).normalize()));
+        // let strand_rotation = Vec3::new(0.0, 1.0, 0.0);
+
+        instance_data.push(InstanceData {
+            rotation: strand_rotation.into(),
+            translation: [
+                strand_translation.x as f32,
+                strand_translation.y as f32,
+                strand_translation.z as f32,
+            ],
+            scale: [1.0, (strand_length / HAIR_SEG_LENGTH) as f32, 1.0],
+            color: [0.27, 0.1, 0.07, 1.0],
+        });
+    }
+    // info!("instance_data: {:?}", &instance_data);
+    instance_data
+}
+
+// Vertex positions and the vertex indices of every triangle
+pub type TriangleMesh = (Vec<na::Vector3<f64>>, Vec<[usize; 3]>);
+
+// Positions and triangles of a triangle list mesh
+pub fn convert_to_triangles(mesh: &Mesh) -> Option<TriangleMesh> {
+    if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+        return None;
+    }
+    let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+    else {
+        return None;
+    };
+    let positions: Vec<na::Vector3<f64>> = positions
+        .iter()
+        .map(|p| na::Vector3::new(p[0] as f64, p[1] as f64, p[2] as f64))
+        .collect();
+
+    let indices: Vec<usize> = match mesh.indices() {
+        Some(Indices::U16(indices)) => indices.iter().map(|&i| i as usize).collect(),
+        Some(Indices::U32(indices)) => indices.iter().map(|&i| i as usize).collect(),
+        None => (0..positions.len()).collect(),
+    };
+    let triangles = indices
+        .chunks_exact(3)
+        .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+        .collect();
+    Some((positions, triangles))
+}
+
 impl HairStrand {
     pub fn to_instance_data(&self) -> Vec<InstanceData> {
-        let mut instance_data = Vec::new();
-        for i in 0..(self.v_num - 2) {
-            let from_pos = self.v_position[i as usize];
-            let to_pos = self.v_position[(i + 1) as usize];
-
-            let strand_length = (to_pos - from_pos).norm();
-            let strand_translation = (from_pos + to_pos) / 2.0;
-            let strand_rotation =
-                Quat::from_rotation_arc(Vec3::Y, convert_to_vec3((to_pos - from_pos).normalize()));
-            // let strand_rotation = Vec3::new(0.0, 1.0, 0.0);
-
-            instance_data.push(InstanceData {
-                rotation: strand_rotation.into(),
-                translation: [
-                    strand_translation.x as f32,
-                    strand_translation.y as f32,
-                    strand_translation.z as f32,
-                ],
-                scale: [1.0, (strand_length / HAIR_SEG_LENGTH) as f32, 1.0],
-                color: [0.27, 0.1, 0.07, 1.0],
-            });
-        }
-        // info!("instance_data: {:?}", &instance_data);
-        instance_data
+        segment_instance_data(&self.v_position)
     }
 
     pub fn get_strand_length(&self, index: usize) -> f64 {
@@ -108,6 +197,221 @@ impl HairStrand {
         }
         (self.v_position[index + 1] - self.v_position[index]).norm()
     }
+
+    // Rest length of the Voronoi region around an interior vertex
+    pub fn get_voronoi_length(&self, index: usize) -> f64 {
+        (self.l_rest_length[index - 1] + self.l_rest_length[index]) / 2.0
+    }
+
+    // Record the vertices and the root material frame in the head frame, the
+    // pinned vertices follow them from then on
+    pub fn attach_to_head(&mut self, head: &Head) {
+        self.v_head_position = self.v_position.iter().map(|p| head.to_local(p)).collect();
+
+        let root = &self.reference_frame[0];
+        let m1 = root.n * self.l_twist[0].cos() + root.b * self.l_twist[0].sin();
+        self.root_director = Some(convert_to_na_quat(head.rotation).inverse() * m1);
+    }
+
+    // Move the pinned vertices to the head, with the velocity that takes them
+    // there over delta_time
+    pub fn follow_head(&mut self, head: &Head, delta_time: f64) {
+        if self.v_head_position.len() != self.v_num {
+            return;
+        }
+        for i in 0..self.v_num {
+            if self.v_pinned[i] {
+                let target = head.to_world(&self.v_head_position[i]);
+                self.v_velocity[i] = (target - self.v_position[i]) / delta_time;
+                self.v_position[i] = target;
+            }
+        }
+    }
+
+    // Take the current shape as the rest shape the elastic energies pull back to
+    pub fn capture_rest_shape(&mut self) {
+        let state = StrandState::new(self);
+        state.commit(self);
+
+        self.l_rest_length = (0..self.l_num).map(|i| self.get_strand_length(i)).collect();
+        self.l_rest_kappa = state.kappa.clone();
+        self.v_rest_twist = (0..self.v_num)
+            .map(|i| {
+                if i == 0 || i == self.v_num - 1 {
+                    0.0
+                } else {
+                    calc_twist(self, &state, i)
+                }
+            })
+            .collect();
+    }
+
+    pub fn has_history(&self) -> bool {
+        self.v_previous_position.len() == self.v_num
+    }
+
+    // Keep the current state as the previous one of a step of delta_time
+    pub fn record_history(&mut self, delta_time: f64) {
+        self.previous_delta_time = delta_time;
+        self.v_previous_position = self.v_position.clone();
+        self.v_previous_velocity = self.v_velocity.clone();
+        self.l_previous_twist = self.l_twist.clone();
+        self.l_previous_angular = self.l_angular.clone();
+    }
+
+    pub fn clear_history(&mut self) {
+        self.v_previous_position.clear();
+        self.v_previous_velocity.clear();
+        self.l_previous_twist.clear();
+        self.l_previous_angular.clear();
+    }
+}
+
+// Rest shape of a generated strand, along the line from its root to its tip
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum StrandShape {
+    #[default]
+    Straight,
+    // Helix of the given radius, advancing `pitch` along the line per turn. The
+    // material frame follows the curl, which gives the strand its natural twist.
+    Curly {
+        radius: f64,
+        pitch: f64,
+        phase: f64,
+    },
+    // Planar sine wave across the line
+    Wavy {
+        amplitude: f64,
+        wavelength: f64,
+        phase: f64,
+    },
+}
+
+// Two unit vectors spanning the plane normal to `direction`
+fn perpendicular_basis(direction: na::Vector3<f64>) -> (na::Vector3<f64>, na::Vector3<f64>) {
+    let up = if direction.y.abs() < 0.9 {
+        na::Vector3::new(0.0, 1.0, 0.0)
+    } else {
+        na::Vector3::new(1.0, 0.0, 0.0)
+    };
+    let u = (up - direction * direction.dot(&up)).normalize();
+    (u, direction.cross(&u))
+}
+
+// Vertices at equal arc length along a curve parameterised by the distance
+// travelled along its axis
+fn sample_by_arc_length(
+    curve: impl Fn(f64) -> na::Vector3<f64>,
+    length: f64,
+    seg_num: usize,
+) -> Vec<na::Vector3<f64>> {
+    let seg_length = length / seg_num as f64;
+    let step = seg_length / 64.0;
+
+    let mut positions = vec![curve(0.0)];
+    let mut previous = positions[0];
+    let mut arc_length = 0.0;
+    let mut z = 0.0;
+    while positions.len() <= seg_num {
+        z += step;
+        let point = curve(z);
+        let chord = (point - previous).norm();
+        while positions.len() <= seg_num
+            && arc_length + chord >= positions.len() as f64 * seg_length
+        {
+            let target = positions.len() as f64 * seg_length;
+            positions.push(previous + (point - previous) * ((target - arc_length) / chord));
+        }
+        arc_length += chord;
+        previous = point;
+    }
+    positions
+}
+
+impl StrandShape {
+    // Vertices of a strand as long as the line from `from_pos` to `to_pos`,
+    // with the material direction m1 of every edge where the shape fixes one
+    fn vertices(
+        &self,
+        seg_num: usize,
+        from_pos: na::Vector3<f64>,
+        to_pos: na::Vector3<f64>,
+    ) -> (Vec<na::Vector3<f64>>, Option<Vec<na::Vector3<f64>>>) {
+        let length = (to_pos - from_pos).norm();
+        let direction = (to_pos - from_pos) / length;
+        let (u, w) = perpendicular_basis(direction);
+
+        match *self {
+            StrandShape::Straight => {
+                let seg_length = (to_pos - from_pos) / seg_num as f64;
+                let positions = (0..(seg_num + 1))
+                    .map(|i| from_pos + seg_length * i as f64)
+                    .collect();
+                (positions, None)
+            }
+            StrandShape::Curly {
+                radius,
+                pitch,
+                phase,
+            } => {
+                // Equal angles give equal chords on a helix
+                let rise = pitch / (2.0 * PI);
+                let angle = length / (radius.powi(2) + rise.powi(2)).sqrt();
+                let radial = |phi: f64| u * f64::cos(phi + phase) + w * f64::sin(phi + phase);
+                let point = |phi: f64| {
+                    from_pos + radius * (radial(phi) - radial(0.0)) + direction * rise * phi
+                };
+
+                let phis: Vec<f64> = (0..(seg_num + 1))
+                    .map(|i| angle * i as f64 / seg_num as f64)
+                    .collect();
+                let positions = phis.iter().map(|&phi| point(phi)).collect();
+                // Towards the axis, the principal normal of the helix
+                let directors = phis
+                    .windows(2)
+                    .map(|phi| -radial((phi[0] + phi[1]) / 2.0))
+                    .collect();
+                (positions, Some(directors))
+            }
+            StrandShape::Wavy {
+                amplitude,
+                wavelength,
+                phase,
+            } => {
+                let point = |z: f64| {
+                    let offset = f64::sin(2.0 * PI * z / wavelength + phase) - f64::sin(phase);
+                    from_pos + direction * z + u * amplitude * offset
+                };
+                (sample_by_arc_length(point, length, seg_num), None)
+            }
+        }
+    }
+}
+
+// Discretization and material of generated strands. The defaults are those of
+// the strands grown on a scalp mesh.
+#[derive(Clone, Copy, Debug)]
+pub struct StrandParams {
+    pub shape: StrandShape,
+    pub seg_num: usize,
+    pub youngs: f64,
+    pub shear: f64,
+    pub radius: f64,
+    // Vertices up to this one are pinned
+    pub last_pin: usize,
+}
+
+impl Default for StrandParams {
+    fn default() -> Self {
+        StrandParams {
+            shape: StrandShape::Straight,
+            seg_num: 30,
+            youngs: 1.9e10,
+            shear: 7.1e9,
+            radius: 1e-4,
+            last_pin: 1,
+        }
+    }
 }
 
 pub fn generate_straight_hair_strand(
@@ -120,9 +424,52 @@ pub fn generate_straight_hair_strand(
     strand_radius: f64,
     last_pin: usize,
 ) -> HairStrand {
+    let params = StrandParams {
+        shape: StrandShape::Straight,
+        seg_num,
+        youngs,
+        shear,
+        radius: strand_radius,
+        last_pin,
+    };
+    generate_hair_strand(&params, mass, from_pos, to_pos)
+}
+
+// A strand of the given shape with vertices of the given mass, at rest as
+// generated
+pub fn generate_hair_strand(
+    params: &StrandParams,
+    mass: f64,
+    from_pos: na::Vector3<f64>,
+    to_pos: na::Vector3<f64>,
+) -> HairStrand {
+    let (positions, directors) = params.shape.vertices(params.seg_num, from_pos, to_pos);
+    hair_strand_from_vertices(
+        &positions,
+        directors.as_deref(),
+        mass,
+        params.youngs,
+        params.shear,
+        params.radius,
+        params.last_pin,
+    )
+}
+
+// A strand through the given vertices, at rest there, with the material
+// direction m1 of every edge when given
+pub fn hair_strand_from_vertices(
+    positions: &[na::Vector3<f64>],
+    directors: Option<&[na::Vector3<f64>]>,
+    mass: f64,
+    youngs: f64,
+    shear: f64,
+    strand_radius: f64,
+    last_pin: usize,
+) -> HairStrand {
+    let seg_num = positions.len() - 1;
     let mut hair_strand = HairStrand {
         attachment: 0,
-        last_pin,
+        frozen: false,
         radius: strand_radius,
         youngs,
         shear,
@@ -130,56 +477,69 @@ pub fn generate_straight_hair_strand(
         v_mass: Vec::new(),
         v_position: Vec::new(),
         v_velocity: Vec::new(),
+        v_pinned: Vec::new(),
+        v_head_position: Vec::new(),
+        v_reference_twist: Vec::new(),
+        v_rest_twist: Vec::new(),
         l_num: seg_num,
         l_momemtum: Vec::new(),
         l_twist: Vec::new(),
         l_angular: Vec::new(),
         l_rest_length: Vec::new(),
-        l_initial_kappa: Vec::new(),
+        l_rest_kappa: Vec::new(),
         reference_frame: Vec::new(),
+        root_director: None,
+        v_previous_position: Vec::new(),
+        v_previous_velocity: Vec::new(),
+        l_previous_twist: Vec::new(),
+        l_previous_angular: Vec::new(),
+        previous_delta_time: 0.0,
     };
 
-    let seg_length = (to_pos - from_pos) / seg_num as f64;
-
     for i in 0..(seg_num + 1) {
         hair_strand.v_mass.push(mass);
-        hair_strand
-            .v_position
-            .push(from_pos + seg_length * i as f64);
+        hair_strand.v_position.push(positions[i]);
         hair_strand.v_velocity.push(na::Vector3::zeros());
+        hair_strand.v_pinned.push(i <= last_pin);
+        hair_strand.v_reference_twist.push(0.0);
     }
 
     for i in 0..(seg_num as usize) {
         hair_strand.l_momemtum.push(0.1);
 
-        // Initialize initial length
-        hair_strand
-            .l_rest_length
-            .push(hair_strand.get_strand_length(i));
-
-        // Initialize twist
-        hair_strand.l_twist.push(0.0);
-
         // Initialize angular
         hair_strand.l_angular.push(0.0);
 
-        // Initialize reference frames
-        let t0;
+        // Initialize reference frames, space-parallel transported from the root
         let t1 = (hair_strand.v_position[i + 1] - hair_strand.v_position[i]).normalize();
 
         if i == 0 {
-            t0 = na::Vector3::new(0.0, 1.0, 0.0);
+            let (b, _, n1) = parallel_transport(na::Vector3::new(0.0, 1.0, 0.0), t1);
+            hair_strand.reference_frame.push(Frame { b, n: n1, t: t1 })
         } else {
-            t0 = hair_strand.reference_frame[i - 1].t;
+            let frame = transport_frame(&hair_strand.reference_frame[i - 1], t1);
+            hair_strand.reference_frame.push(frame)
         }
 
-        let (b, _, n1) = parallel_transport(t0, t1);
-        hair_strand.reference_frame.push(Frame { b, n: n1, t: t1 })
+        // Initialize twist, turning m1 = n cos(theta) + b sin(theta) onto the
+        // director of the shape
+        let twist = match directors {
+            Some(directors) => {
+                let frame = &hair_strand.reference_frame[i];
+                let angle = f64::atan2(directors[i].dot(&frame.b), directors[i].dot(&frame.n));
+                let previous = hair_strand.l_twist.last().copied().unwrap_or(0.0);
+                previous + wrap_angle(angle - previous)
+            }
+            None => 0.0,
+        };
+        hair_strand.l_twist.push(twist);
     }
 
+    hair_strand.capture_rest_shape();
     hair_strand
 }
 
+// Strands on latitude rings of the head sphere, growing along its normal
 pub fn generate_batch_hair_strands(
     center: na::Vector3<f64>,
     radius: f64,
@@ -192,9 +552,9 @@ pub fn generate_batch_hair_strands(
     mass: f64,
     strand_radius: f64,
     last_pin: usize,
+    shape: StrandShape,
 ) -> SimulationData {
-    let mut hair_strands = Vec::new();
-    let mut head = Head {
+    let head = Head {
         position: center,
         radius,
         rotation: Quat::IDENTITY,
@@ -204,8 +564,7 @@ pub fn generate_batch_hair_strands(
     let angle_interval = angle / (group_num - 1) as f64;
     let strand_interval = radius * angle_interval as f64;
 
-    let mass_per_vertex = mass / (strand_seg_num + 1) as f64;
-
+    let mut roots = Vec::new();
     for i in 2..group_num {
         let group_angle = i as f64 * angle_interval;
         let mut num: i32 = (2.0 * PI * radius * f64::sin(group_angle) / strand_interval) as i32;
@@ -214,30 +573,55 @@ pub fn generate_batch_hair_strands(
         }
         let new_angle_interval = 2.0 * PI / num as f64;
         for j in 0..num {
-            let from_strand_pos = na::Vector3::<f64>::new(
-                center.x + radius * f64::sin(group_angle) * f64::cos(j as f64 * new_angle_interval),
-                center.y + f64::cos(group_angle) * radius,
-                center.z + radius * f64::sin(group_angle) * f64::sin(j as f64 * new_angle_interval),
-            );
-            let to_strand_pos = from_strand_pos + (from_strand_pos - center).normalize() * length;
-            let mut hair_strand = generate_straight_hair_strand(
-                mass_per_vertex,
-                strand_seg_num,
-                from_strand_pos,
-                to_strand_pos,
-                youngs,
-                shear,
-                strand_radius,
-                last_pin,
+            let position = na::Vector3::<f64>::new(
+                radius * f64::sin(group_angle) * f64::cos(j as f64 * new_angle_interval),
+                f64::cos(group_angle) * radius,
+                radius * f64::sin(group_angle) * f64::sin(j as f64 * new_angle_interval),
             );
-
-            hair_strand.attachment = head.attachments.len();
-            head.attachments.push(from_strand_pos - center);
-            hair_strands.push(hair_strand);
+            roots.push(HairRoot {
+                position,
+                direction: position.normalize(),
+                length: 1.0,
+            });
         }
         info!("num: {:?}", num);
     }
 
+    let params = StrandParams {
+        shape,
+        seg_num: strand_seg_num,
+        youngs,
+        shear,
+        radius: strand_radius,
+        last_pin,
+    };
+    generate_rooted_hair_strands(head, &roots, length, mass, &params)
+}
+
+// One strand per root, `length` and `mass` long and heavy before the length
+// scale of the root. The roots become the attachments of the head.
+pub fn generate_rooted_hair_strands(
+    mut head: Head,
+    roots: &[HairRoot],
+    length: f64,
+    mass: f64,
+    params: &StrandParams,
+) -> SimulationData {
+    let rotation = convert_to_na_quat(head.rotation);
+    let mut hair_strands = Vec::new();
+    for root in roots {
+        let from_strand_pos = head.to_world(&root.position);
+        let to_strand_pos = from_strand_pos + rotation * root.direction * length * root.length;
+        let mass_per_vertex = mass * root.length / (params.seg_num + 1) as f64;
+        let mut hair_strand =
+            generate_hair_strand(params, mass_per_vertex, from_strand_pos, to_strand_pos);
+
+        hair_strand.attachment = head.attachments.len();
+        head.attachments.push(root.position);
+        hair_strand.attach_to_head(&head);
+        hair_strands.push(hair_strand);
+    }
+
     info!("hair_strands: {:?}", &hair_strands.len());
 
     SimulationData {
@@ -245,5 +629,7 @@ pub fn generate_batch_hair_strands(
             strands: hair_strands,
         },
         head,
+        colliders: Vec::new(),
+        config: SimulationConfig::default(),
     }
 }
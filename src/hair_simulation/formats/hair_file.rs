@@ -0,0 +1,309 @@
+use std::{fmt, fs, io, path::Path};
+
+use bevy::log::info;
+
+use crate::hair_simulation::{
+    config::SimulationConfig,
+    data::{hair_strand_from_vertices, Hairs, Head, SimulationData},
+};
+extern crate nalgebra as na;
+
+pub const HAIR_FILE_SIGNATURE: &[u8; 4] = b"HAIR";
+pub const HAIR_FILE_HEADER_SIZE: usize = 128;
+
+// Bits of the header telling which arrays follow it
+pub const HAIR_FILE_HAS_SEGMENTS: u32 = 1 << 0;
+pub const HAIR_FILE_HAS_POINTS: u32 = 1 << 1;
+pub const HAIR_FILE_HAS_THICKNESS: u32 = 1 << 2;
+pub const HAIR_FILE_HAS_TRANSPARENCY: u32 = 1 << 3;
+pub const HAIR_FILE_HAS_COLOR: u32 = 1 << 4;
+
+#[derive(Debug)]
+pub enum HairFileError {
+    Io(io::Error),
+    // Not starting with "HAIR"
+    Signature,
+    // Shorter than its header and arrays
+    Truncated,
+    // No point array, nothing to grow strands from
+    MissingPoints,
+    // The segment counts do not add up to the points of the header
+    PointCount { expected: usize, found: usize },
+}
+
+impl fmt::Display for HairFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HairFileError::Io(error) => write!(f, "{}", error),
+            HairFileError::Signature => write!(f, "not a .hair file"),
+            HairFileError::Truncated => write!(f, "file ends before its arrays"),
+            HairFileError::MissingPoints => write!(f, "file has no points"),
+            HairFileError::PointCount { expected, found } => {
+                write!(
+                    f,
+                    "{} points in the header, {} in the strands",
+                    expected, found
+                )
+            }
+        }
+    }
+}
+
+impl From<io::Error> for HairFileError {
+    fn from(error: io::Error) -> Self {
+        HairFileError::Io(error)
+    }
+}
+
+// A strand of the file, with a thickness per point when the file has them
+#[derive(Clone, Debug, Default)]
+pub struct HairFileStrand {
+    pub points: Vec<na::Vector3<f64>>,
+    pub thickness: Option<Vec<f64>>,
+}
+
+// Geometry of a Cem Yuksel .hair file, in the units and axes of the file. The
+// transparency and color are left out, the hair is drawn in one color.
+#[derive(Clone, Debug, Default)]
+pub struct HairFile {
+    pub strands: Vec<HairFileStrand>,
+    pub default_thickness: f64,
+    pub info: String,
+}
+
+// Little endian reads over the bytes of the file
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, size: usize) -> Result<&'a [u8], HairFileError> {
+        let end = self.offset + size;
+        let bytes = self
+            .bytes
+            .get(self.offset..end)
+            .ok_or(HairFileError::Truncated)?;
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, HairFileError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, HairFileError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f64, HairFileError> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()) as f64)
+    }
+
+    fn vector(&mut self) -> Result<na::Vector3<f64>, HairFileError> {
+        Ok(na::Vector3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+}
+
+impl HairFile {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, HairFileError> {
+        HairFile::parse(&fs::read(path)?)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, HairFileError> {
+        let mut reader = Reader { bytes, offset: 0 };
+        if reader.take(4)? != HAIR_FILE_SIGNATURE {
+            return Err(HairFileError::Signature);
+        }
+        let strand_num = reader.u32()? as usize;
+        let point_num = reader.u32()? as usize;
+        let arrays = reader.u32()?;
+        let default_segments = reader.u32()? as usize;
+        let default_thickness = reader.f32()?;
+        // Default transparency and color
+        reader.take(16)?;
+        let info = reader.take(HAIR_FILE_HEADER_SIZE - reader.offset)?;
+        let info = String::from_utf8_lossy(info)
+            .trim_end_matches('\0')
+            .to_string();
+
+        if arrays & HAIR_FILE_HAS_POINTS == 0 {
+            return Err(HairFileError::MissingPoints);
+        }
+        let segments = if arrays & HAIR_FILE_HAS_SEGMENTS != 0 {
+            (0..strand_num)
+                .map(|_| reader.u16().map(|segments| segments as usize))
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            vec![default_segments; strand_num]
+        };
+        let found = segments.iter().map(|segments| segments + 1).sum();
+        if found != point_num {
+            return Err(HairFileError::PointCount {
+                expected: point_num,
+                found,
+            });
+        }
+
+        let points = (0..point_num)
+            .map(|_| reader.vector())
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut scalars = |bit: u32| -> Result<Option<Vec<f64>>, HairFileError> {
+            (arrays & bit != 0)
+                .then(|| (0..point_num).map(|_| reader.f32()).collect())
+                .transpose()
+        };
+        let thickness = scalars(HAIR_FILE_HAS_THICKNESS)?;
+        // Skipped, but a file too short for them is still truncated
+        if arrays & HAIR_FILE_HAS_TRANSPARENCY != 0 {
+            reader.take(4 * point_num)?;
+        }
+        if arrays & HAIR_FILE_HAS_COLOR != 0 {
+            reader.take(12 * point_num)?;
+        }
+
+        let mut strands = Vec::with_capacity(strand_num);
+        let mut start = 0;
+        for segments in segments {
+            let range = start..(start + segments + 1);
+            strands.push(HairFileStrand {
+                points: points[range.clone()].to_vec(),
+                thickness: thickness.as_ref().map(|v| v[range.clone()].to_vec()),
+            });
+            start = range.end;
+        }
+
+        Ok(HairFile {
+            strands,
+            default_thickness,
+            info,
+        })
+    }
+
+    // Simulated strands through the strands of the file, rooted on the head at
+    // their first point
+    pub fn to_simulation_data(&self, mut head: Head, import: &HairImport) -> SimulationData {
+        let mut strands = Vec::new();
+        for strand in self.strands.iter() {
+            let local: Vec<na::Vector3<f64>> = strand
+                .points
+                .iter()
+                .map(|p| import.rotation * (import.scale * p) + import.translation)
+                .collect();
+            let length = polyline_length(&local);
+            if local.len() < 2 || length < import.min_length {
+                continue;
+            }
+
+            let seg_num = import.segments.unwrap_or(strand.points.len() - 1).max(1);
+            let positions: Vec<na::Vector3<f64>> = resample_polyline(&local, seg_num)
+                .iter()
+                .map(|p| head.to_world(p))
+                .collect();
+            let thickness = match strand.thickness.as_ref() {
+                Some(thickness) => thickness.iter().sum::<f64>() / thickness.len() as f64,
+                None => self.default_thickness,
+            };
+            let radius = import.radius.unwrap_or(thickness * import.scale / 2.0);
+
+            let mut hair_strand = hair_strand_from_vertices(
+                &positions,
+                None,
+                import.linear_density * length / (seg_num + 1) as f64,
+                import.youngs,
+                import.shear,
+                radius,
+                import.last_pin,
+            );
+            hair_strand.attachment = head.attachments.len();
+            head.attachments.push(local[0]);
+            hair_strand.attach_to_head(&head);
+            strands.push(hair_strand);
+        }
+
+        info!(
+            "hair file {:?}: {} of {} strands imported",
+            self.info,
+            strands.len(),
+            self.strands.len()
+        );
+
+        SimulationData {
+            hairs: Hairs { strands },
+            head,
+            colliders: Vec::new(),
+            config: SimulationConfig::default(),
+        }
+    }
+}
+
+// How the strands of a file become simulated strands
+#[derive(Clone, Debug)]
+pub struct HairImport {
+    // From the coordinates of the file to the local space of the head,
+    // e.g. a scale of 0.01 for a file in centimetres
+    pub scale: f64,
+    pub rotation: na::UnitQuaternion<f64>,
+    pub translation: na::Vector3<f64>,
+    // Segments of every strand, resampled at equal arc length. The segments of
+    // each strand in the file when none.
+    pub segments: Option<usize>,
+    // Strands shorter than this after scaling are left out
+    pub min_length: f64,
+    // Mass per unit length, kg/m
+    pub linear_density: f64,
+    pub youngs: f64,
+    pub shear: f64,
+    // Strand radius, half the thickness of the file when none
+    pub radius: Option<f64>,
+    pub last_pin: usize,
+}
+
+impl Default for HairImport {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            rotation: na::UnitQuaternion::identity(),
+            translation: na::Vector3::zeros(),
+            segments: None,
+            min_length: 1e-4,
+            linear_density: 6.5e-6,
+            youngs: 1.9e10,
+            shear: 7.1e9,
+            radius: None,
+            last_pin: 1,
+        }
+    }
+}
+
+fn polyline_length(points: &[na::Vector3<f64>]) -> f64 {
+    points
+        .windows(2)
+        .map(|pair| (pair[1] - pair[0]).norm())
+        .sum()
+}
+
+// `seg_num` + 1 points at equal arc length along a polyline, from its first
+// point to its last
+pub fn resample_polyline(points: &[na::Vector3<f64>], seg_num: usize) -> Vec<na::Vector3<f64>> {
+    let seg_length = polyline_length(points) / seg_num as f64;
+
+    let mut resampled = vec![points[0]];
+    let mut arc_length = 0.0;
+    for pair in points.windows(2) {
+        let chord = (pair[1] - pair[0]).norm();
+        // Repeated points, nothing to place between them
+        if chord == 0.0 {
+            continue;
+        }
+        while resampled.len() < seg_num && arc_length + chord >= resampled.len() as f64 * seg_length
+        {
+            let target = resampled.len() as f64 * seg_length;
+            resampled.push(pair[0].lerp(&pair[1], (target - arc_length) / chord));
+        }
+        arc_length += chord;
+    }
+    // The last point, also filling in for a strand without length
+    resampled.resize(seg_num + 1, points[points.len() - 1]);
+    resampled
+}
@@ -0,0 +1,7 @@
+pub mod hair_file;
+pub mod polylines;
+pub mod recording;
+pub mod snapshot;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,187 @@
+use std::{
+    fs,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::hair_simulation::data::{HairStrand, Hairs};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PolylineFormat {
+    // ASCII PLY, vertices and the edges between them, with the attributes
+    #[default]
+    Ply,
+    // Wavefront OBJ, `v` records and one `l` record per strand. Positions only.
+    Obj,
+}
+
+impl PolylineFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PolylineFormat::Ply => "ply",
+            PolylineFormat::Obj => "obj",
+        }
+    }
+}
+
+// Values written along with the positions, where the format has room for them
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExportAttributes {
+    // Vertex velocity
+    pub velocity: bool,
+    // Twist of the material frame, on the edges
+    pub twist: bool,
+    // Curvature |kb| over the Voronoi length of the vertex, 0 at the ends
+    pub curvature: bool,
+}
+
+// Writes the strands of every iteration to a file of its own in `directory`
+#[derive(Clone, Debug)]
+pub struct FrameExporter {
+    pub directory: PathBuf,
+    pub format: PolylineFormat,
+    pub attributes: ExportAttributes,
+}
+
+impl FrameExporter {
+    pub fn new(directory: impl Into<PathBuf>, format: PolylineFormat) -> Self {
+        FrameExporter {
+            directory: directory.into(),
+            format,
+            attributes: ExportAttributes::default(),
+        }
+    }
+
+    pub fn frame_path(&self, iteration: u64) -> PathBuf {
+        self.directory.join(format!(
+            "frame_{:06}.{}",
+            iteration,
+            self.format.extension()
+        ))
+    }
+
+    pub fn export(&self, hairs: &Hairs, iteration: u64) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.directory)?;
+        let path = self.frame_path(iteration);
+        write_polylines(&path, hairs, self.format, self.attributes)?;
+        Ok(path)
+    }
+}
+
+pub fn write_polylines(
+    path: &Path,
+    hairs: &Hairs,
+    format: PolylineFormat,
+    attributes: ExportAttributes,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(fs::File::create(path)?);
+    match format {
+        PolylineFormat::Ply => write_ply(&mut writer, hairs, attributes)?,
+        PolylineFormat::Obj => write_obj(&mut writer, hairs)?,
+    }
+    writer.flush()
+}
+
+// Curvature at every vertex, from the curvature binormal of its two edges
+pub fn vertex_curvature(strand: &HairStrand) -> Vec<f64> {
+    (0..strand.v_num)
+        .map(|i| {
+            if i == 0 || i + 1 >= strand.v_num {
+                return 0.0;
+            }
+            let e0 = strand.v_position[i] - strand.v_position[i - 1];
+            let e1 = strand.v_position[i + 1] - strand.v_position[i];
+            let (l0, l1) = (e0.norm(), e1.norm());
+            let kb = 2.0 * e0.cross(&e1) / (l0 * l1 + e0.dot(&e1));
+            kb.norm() / ((l0 + l1) / 2.0)
+        })
+        .collect()
+}
+
+pub fn write_ply(
+    writer: &mut impl Write,
+    hairs: &Hairs,
+    attributes: ExportAttributes,
+) -> io::Result<()> {
+    let strands = &hairs.strands;
+    let vertex_num: usize = strands.iter().map(|strand| strand.v_num).sum();
+    let edge_num: usize = strands.iter().map(|strand| strand.l_num).sum();
+
+    writeln!(writer, "ply")?;
+    writeln!(writer, "format ascii 1.0")?;
+    writeln!(writer, "comment {} strands", strands.len())?;
+    writeln!(writer, "element vertex {}", vertex_num)?;
+    let mut vertex_properties = vec!["x", "y", "z"];
+    if attributes.velocity {
+        vertex_properties.extend(["vx", "vy", "vz"]);
+    }
+    if attributes.curvature {
+        vertex_properties.push("curvature");
+    }
+    vertex_properties.push("strand");
+    for property in vertex_properties {
+        let kind = if property == "strand" {
+            "int"
+        } else {
+            "double"
+        };
+        writeln!(writer, "property {} {}", kind, property)?;
+    }
+    writeln!(writer, "element edge {}", edge_num)?;
+    writeln!(writer, "property int vertex1")?;
+    writeln!(writer, "property int vertex2")?;
+    if attributes.twist {
+        writeln!(writer, "property double twist")?;
+    }
+    writeln!(writer, "end_header")?;
+
+    for (index, strand) in strands.iter().enumerate() {
+        let curvature = attributes.curvature.then(|| vertex_curvature(strand));
+        for i in 0..strand.v_num {
+            let p = strand.v_position[i];
+            write!(writer, "{} {} {}", p.x, p.y, p.z)?;
+            if attributes.velocity {
+                let v = strand.v_velocity[i];
+                write!(writer, " {} {} {}", v.x, v.y, v.z)?;
+            }
+            if let Some(curvature) = curvature.as_ref() {
+                write!(writer, " {}", curvature[i])?;
+            }
+            writeln!(writer, " {}", index)?;
+        }
+    }
+
+    let mut first = 0;
+    for strand in strands.iter() {
+        for j in 0..strand.l_num {
+            write!(writer, "{} {}", first + j, first + j + 1)?;
+            if attributes.twist {
+                write!(writer, " {}", strand.l_twist[j])?;
+            }
+            writeln!(writer)?;
+        }
+        first += strand.v_num;
+    }
+    Ok(())
+}
+
+pub fn write_obj(writer: &mut impl Write, hairs: &Hairs) -> io::Result<()> {
+    writeln!(writer, "# {} strands", hairs.strands.len())?;
+    for strand in hairs.strands.iter() {
+        for p in strand.v_position.iter() {
+            writeln!(writer, "v {} {} {}", p.x, p.y, p.z)?;
+        }
+    }
+
+    // Indices start at 1
+    let mut first = 1;
+    for strand in hairs.strands.iter() {
+        write!(writer, "l")?;
+        for i in 0..strand.v_num {
+            write!(writer, " {}", first + i)?;
+        }
+        writeln!(writer)?;
+        first += strand.v_num;
+    }
+    Ok(())
+}
@@ -0,0 +1,311 @@
+use std::{
+    fmt, fs,
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use bevy::math::Quat;
+use instant::Duration;
+
+use crate::hair_simulation::data::SimulationData;
+extern crate nalgebra as na;
+
+pub const RECORDING_SIGNATURE: &[u8; 4] = b"HREC";
+pub const RECORDING_VERSION: u32 = 1;
+
+// Iteration, elapsed nanoseconds, substeps and retries, head position and
+// rotation, before the vertices of a frame
+const FRAME_HEADER_SIZE: usize = 8 + 8 + 4 + 4 + 3 * 8 + 4 * 4;
+
+#[derive(Debug)]
+pub enum RecordingError {
+    Io(io::Error),
+    // Not starting with "HREC"
+    Signature,
+    Version { found: u32 },
+    // The strands have other vertex counts than the ones recorded
+    Layout,
+    Empty,
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordingError::Io(error) => write!(f, "{}", error),
+            RecordingError::Signature => write!(f, "not a recording"),
+            RecordingError::Version { found } => write!(
+                f,
+                "recording version {}, expected {}",
+                found, RECORDING_VERSION
+            ),
+            RecordingError::Layout => write!(f, "strands differ from the recording"),
+            RecordingError::Empty => write!(f, "recording has no frames"),
+        }
+    }
+}
+
+impl From<io::Error> for RecordingError {
+    fn from(error: io::Error) -> Self {
+        RecordingError::Io(error)
+    }
+}
+
+// Vertex count of every recorded strand, which fixes the size of the frames
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RecordingLayout {
+    pub v_nums: Vec<usize>,
+}
+
+impl RecordingLayout {
+    pub fn of(data: &SimulationData) -> Self {
+        RecordingLayout {
+            v_nums: data
+                .hairs
+                .strands
+                .iter()
+                .map(|strand| strand.v_num)
+                .collect(),
+        }
+    }
+
+    pub fn vertex_num(&self) -> usize {
+        self.v_nums.iter().sum()
+    }
+
+    pub fn header_size(&self) -> usize {
+        4 + 4 + 4 + 4 * self.v_nums.len()
+    }
+
+    pub fn frame_size(&self) -> usize {
+        FRAME_HEADER_SIZE + 3 * 4 * self.vertex_num()
+    }
+
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(RECORDING_SIGNATURE)?;
+        writer.write_all(&RECORDING_VERSION.to_le_bytes())?;
+        writer.write_all(&(self.v_nums.len() as u32).to_le_bytes())?;
+        for v_num in self.v_nums.iter() {
+            writer.write_all(&(*v_num as u32).to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn read(reader: &mut impl Read) -> Result<Self, RecordingError> {
+        if &read_bytes(reader)? != RECORDING_SIGNATURE {
+            return Err(RecordingError::Signature);
+        }
+        let found = read_u32(reader)?;
+        if found != RECORDING_VERSION {
+            return Err(RecordingError::Version { found });
+        }
+        let strand_num = read_u32(reader)? as usize;
+        let v_nums = (0..strand_num)
+            .map(|_| read_u32(reader).map(|v_num| v_num as usize))
+            .collect::<io::Result<_>>()?;
+        Ok(RecordingLayout { v_nums })
+    }
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    Ok(u64::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_f32(reader: &mut impl Read) -> io::Result<f32> {
+    Ok(f32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_f64(reader: &mut impl Read) -> io::Result<f64> {
+    Ok(f64::from_le_bytes(read_bytes(reader)?))
+}
+
+// What is drawn of a completed step, and how long it took
+#[derive(Clone, Debug, Default)]
+pub struct RecordedFrame {
+    pub iteration_cnt: u64,
+    pub elapsed: Duration,
+    pub substeps: usize,
+    pub substep_retries: usize,
+    pub head_position: na::Vector3<f64>,
+    pub head_rotation: Quat,
+    // Vertices of every strand one after the other, in single precision
+    pub positions: Vec<na::Vector3<f32>>,
+}
+
+impl RecordedFrame {
+    pub fn from_data(
+        iteration_cnt: u64,
+        elapsed: Duration,
+        substeps: usize,
+        substep_retries: usize,
+        data: &SimulationData,
+    ) -> Self {
+        RecordedFrame {
+            iteration_cnt,
+            elapsed,
+            substeps,
+            substep_retries,
+            head_position: data.head.position,
+            head_rotation: data.head.rotation,
+            positions: data
+                .hairs
+                .strands
+                .iter()
+                .flat_map(|strand| strand.v_position.iter().map(|p| p.cast::<f32>()))
+                .collect(),
+        }
+    }
+
+    // Move the head and the vertices of data, laid out as recorded, to the frame
+    pub fn apply(&self, data: &mut SimulationData) {
+        data.head.position = self.head_position;
+        data.head.rotation = self.head_rotation;
+        let mut positions = self.positions.iter();
+        for strand in data.hairs.strands.iter_mut() {
+            for (p, recorded) in strand.v_position.iter_mut().zip(positions.by_ref()) {
+                *p = recorded.cast::<f64>();
+            }
+        }
+    }
+
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.iteration_cnt.to_le_bytes())?;
+        writer.write_all(&(self.elapsed.as_nanos() as u64).to_le_bytes())?;
+        writer.write_all(&(self.substeps as u32).to_le_bytes())?;
+        writer.write_all(&(self.substep_retries as u32).to_le_bytes())?;
+        for x in self.head_position.iter() {
+            writer.write_all(&x.to_le_bytes())?;
+        }
+        for x in self.head_rotation.to_array() {
+            writer.write_all(&x.to_le_bytes())?;
+        }
+        for p in self.positions.iter() {
+            for x in p.iter() {
+                writer.write_all(&x.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read(reader: &mut impl Read, layout: &RecordingLayout) -> io::Result<Self> {
+        let iteration_cnt = read_u64(reader)?;
+        let elapsed = Duration::from_nanos(read_u64(reader)?);
+        let substeps = read_u32(reader)? as usize;
+        let substep_retries = read_u32(reader)? as usize;
+        let head_position =
+            na::Vector3::new(read_f64(reader)?, read_f64(reader)?, read_f64(reader)?);
+        let head_rotation = Quat::from_xyzw(
+            read_f32(reader)?,
+            read_f32(reader)?,
+            read_f32(reader)?,
+            read_f32(reader)?,
+        );
+        let positions = (0..layout.vertex_num())
+            .map(|_| {
+                Ok(na::Vector3::new(
+                    read_f32(reader)?,
+                    read_f32(reader)?,
+                    read_f32(reader)?,
+                ))
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(RecordedFrame {
+            iteration_cnt,
+            elapsed,
+            substeps,
+            substep_retries,
+            head_position,
+            head_rotation,
+            positions,
+        })
+    }
+}
+
+// Appends every completed step to a recording
+pub struct Recorder<W: Write = BufWriter<fs::File>> {
+    writer: W,
+    pub layout: RecordingLayout,
+    pub frame_num: usize,
+}
+
+impl Recorder {
+    pub fn create(path: impl AsRef<Path>, data: &SimulationData) -> Result<Self, RecordingError> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Recorder::new(BufWriter::new(fs::File::create(path)?), data)
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(mut writer: W, data: &SimulationData) -> Result<Self, RecordingError> {
+        let layout = RecordingLayout::of(data);
+        layout.write(&mut writer)?;
+        writer.flush()?;
+        Ok(Recorder {
+            writer,
+            layout,
+            frame_num: 0,
+        })
+    }
+
+    pub fn record(&mut self, frame: &RecordedFrame) -> Result<(), RecordingError> {
+        if frame.positions.len() != self.layout.vertex_num() {
+            return Err(RecordingError::Layout);
+        }
+        frame.write(&mut self.writer)?;
+        // Whole frames only, a playback may read the file while it grows
+        self.writer.flush()?;
+        self.frame_num += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), RecordingError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// Random access to the frames of a recording
+pub struct Recording<R: Read + Seek = fs::File> {
+    reader: R,
+    pub layout: RecordingLayout,
+}
+
+impl Recording {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+        Recording::new(fs::File::open(path)?)
+    }
+}
+
+impl<R: Read + Seek> Recording<R> {
+    pub fn new(mut reader: R) -> Result<Self, RecordingError> {
+        let layout = RecordingLayout::read(&mut reader)?;
+        Ok(Recording { reader, layout })
+    }
+
+    // Complete frames so far, a partly written last one is left out
+    pub fn frame_num(&mut self) -> Result<usize, RecordingError> {
+        let length = self.reader.seek(SeekFrom::End(0))? as usize;
+        Ok(length.saturating_sub(self.layout.header_size()) / self.layout.frame_size())
+    }
+
+    pub fn frame(&mut self, index: usize) -> Result<RecordedFrame, RecordingError> {
+        let offset = self.layout.header_size() + index * self.layout.frame_size();
+        self.reader.seek(SeekFrom::Start(offset as u64))?;
+        // One read for the frame, then decoded from memory
+        let mut bytes = vec![0; self.layout.frame_size()];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(RecordedFrame::read(&mut bytes.as_slice(), &self.layout)?)
+    }
+}
@@ -0,0 +1,227 @@
+use std::{fmt, fs, io, path::Path};
+
+use serde::{
+    de::{self, IgnoredAny, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+
+use crate::hair_simulation::data::SimulationData;
+
+pub const SNAPSHOT_SIGNATURE: &[u8; 4] = b"HSNP";
+// Bumped whenever the serialized simulation data changes shape
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    // Signature and version, then the bincode of the snapshot
+    #[default]
+    Binary,
+    // Human readable, for reading and editing bug reports
+    Ron,
+}
+
+impl SnapshotFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SnapshotFormat::Binary => "bin",
+            SnapshotFormat::Ron => "ron",
+        }
+    }
+
+    // RON for a .ron file, binary for anything else
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ron") => SnapshotFormat::Ron,
+            _ => SnapshotFormat::Binary,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    // Not starting with the snapshot signature
+    Signature,
+    // Written by another layout of the simulation data
+    Version { found: u32 },
+    Encode(String),
+    Decode(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(error) => write!(f, "{}", error),
+            SnapshotError::Signature => write!(f, "not a snapshot"),
+            SnapshotError::Version { found } => write!(
+                f,
+                "snapshot version {}, expected {}",
+                found, SNAPSHOT_VERSION
+            ),
+            SnapshotError::Encode(error) => write!(f, "cannot encode snapshot: {}", error),
+            SnapshotError::Decode(error) => write!(f, "cannot decode snapshot: {}", error),
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> Self {
+        SnapshotError::Io(error)
+    }
+}
+
+// The complete state of a simulation, enough to continue it exactly where it
+// was taken
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    // Kept first, `from_ron` checks it before decoding the rest
+    pub version: u32,
+    pub iteration_cnt: u64,
+    pub data: SimulationData,
+}
+
+impl Snapshot {
+    pub fn new(iteration_cnt: u64, data: SimulationData) -> Self {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            iteration_cnt,
+            data,
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let bytes = match SnapshotFormat::from_path(path) {
+            SnapshotFormat::Binary => self.to_bytes()?,
+            SnapshotFormat::Ron => self.to_ron()?.into_bytes(),
+        };
+        fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        let path = path.as_ref();
+        match SnapshotFormat::from_path(path) {
+            SnapshotFormat::Binary => Snapshot::from_bytes(&fs::read(path)?),
+            SnapshotFormat::Ron => Snapshot::from_ron(&fs::read_to_string(path)?),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SnapshotError> {
+        let mut bytes = SNAPSHOT_SIGNATURE.to_vec();
+        bytes.extend(self.version.to_le_bytes());
+        let payload =
+            bincode::serialize(self).map_err(|error| SnapshotError::Encode(error.to_string()))?;
+        bytes.extend(payload);
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        if bytes.len() < 8 || &bytes[..4] != SNAPSHOT_SIGNATURE {
+            return Err(SnapshotError::Signature);
+        }
+        // Checked before the payload, whose layout depends on the version
+        let found = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        if found != SNAPSHOT_VERSION {
+            return Err(SnapshotError::Version { found });
+        }
+        bincode::deserialize(&bytes[8..]).map_err(|error| SnapshotError::Decode(error.to_string()))
+    }
+
+    pub fn to_ron(&self) -> Result<String, SnapshotError> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|error| SnapshotError::Encode(error.to_string()))
+    }
+
+    pub fn from_ron(text: &str) -> Result<Self, SnapshotError> {
+        match ron::Options::default().from_str::<RonSnapshot>(text) {
+            Ok(RonSnapshot::Current(snapshot)) => Ok(*snapshot),
+            Ok(RonSnapshot::Other { found }) => Err(SnapshotError::Version { found }),
+            Ok(RonSnapshot::Unversioned) => Err(SnapshotError::Signature),
+            Err(error) => Err(SnapshotError::Decode(error.to_string())),
+        }
+    }
+}
+
+// A RON snapshot, a struct whose first field is its version. The version is
+// checked before anything else is decoded, the fields of another layout are
+// skipped unread.
+enum RonSnapshot {
+    Current(Box<Snapshot>),
+    Other { found: u32 },
+    // Not starting with a version
+    Unversioned,
+}
+
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "snake_case")]
+enum SnapshotField {
+    Version,
+    IterationCnt,
+    Data,
+    #[serde(other)]
+    Other,
+}
+
+const SNAPSHOT_FIELDS: &[&str] = &["version", "iteration_cnt", "data"];
+
+struct RonSnapshotVisitor;
+
+impl<'de> Visitor<'de> for RonSnapshotVisitor {
+    type Value = RonSnapshot;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a snapshot starting with its version")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<RonSnapshot, A::Error> {
+        let skip_rest = |map: &mut A| -> Result<(), A::Error> {
+            while map.next_key::<SnapshotField>()?.is_some() {
+                map.next_value::<IgnoredAny>()?;
+            }
+            Ok(())
+        };
+
+        let found = match map.next_key::<SnapshotField>()? {
+            Some(SnapshotField::Version) => map.next_value::<u32>()?,
+            Some(_) => {
+                map.next_value::<IgnoredAny>()?;
+                skip_rest(&mut map)?;
+                return Ok(RonSnapshot::Unversioned);
+            }
+            None => return Ok(RonSnapshot::Unversioned),
+        };
+        if found != SNAPSHOT_VERSION {
+            skip_rest(&mut map)?;
+            return Ok(RonSnapshot::Other { found });
+        }
+
+        let mut iteration_cnt = None;
+        let mut data = None;
+        while let Some(field) = map.next_key::<SnapshotField>()? {
+            match field {
+                SnapshotField::IterationCnt => iteration_cnt = Some(map.next_value()?),
+                SnapshotField::Data => data = Some(map.next_value()?),
+                SnapshotField::Version => return Err(de::Error::duplicate_field("version")),
+                SnapshotField::Other => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(RonSnapshot::Current(Box::new(Snapshot {
+            version: found,
+            iteration_cnt: iteration_cnt
+                .ok_or_else(|| de::Error::missing_field("iteration_cnt"))?,
+            data: data.ok_or_else(|| de::Error::missing_field("data"))?,
+        })))
+    }
+}
+
+impl<'de> Deserialize<'de> for RonSnapshot {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct("Snapshot", SNAPSHOT_FIELDS, RonSnapshotVisitor)
+    }
+}
@@ -0,0 +1,446 @@
+use std::{io::Cursor, sync::Arc};
+
+use bevy::math::Quat;
+use instant::Duration;
+
+use crate::hair_simulation::{
+    collider::{sdf::SignedDistanceField, shape::ColliderShape, Collider},
+    data::{hair_strand_from_vertices, Hairs, Head, SimulationData},
+    formats::{
+        hair_file::{
+            resample_polyline, HairFile, HairFileError, HairImport, HAIR_FILE_HAS_COLOR,
+            HAIR_FILE_HAS_POINTS, HAIR_FILE_HAS_SEGMENTS, HAIR_FILE_HAS_THICKNESS,
+            HAIR_FILE_HEADER_SIZE,
+        },
+        polylines::{vertex_curvature, write_obj, write_ply, ExportAttributes},
+        recording::{RecordedFrame, Recorder, Recording, RecordingError},
+        snapshot::{Snapshot, SnapshotError, SNAPSHOT_VERSION},
+    },
+};
+extern crate nalgebra as na;
+
+// A .hair file with the given strands and optional per point arrays
+fn hair_bytes(strands: &[Vec<[f32; 3]>], arrays: u32, default_segments: u32) -> Vec<u8> {
+    let point_num: usize = strands.iter().map(|strand| strand.len()).sum();
+    let mut bytes = b"HAIR".to_vec();
+    bytes.extend((strands.len() as u32).to_le_bytes());
+    bytes.extend((point_num as u32).to_le_bytes());
+    bytes.extend(arrays.to_le_bytes());
+    bytes.extend(default_segments.to_le_bytes());
+    bytes.extend(0.5f32.to_le_bytes());
+    bytes.extend(1.0f32.to_le_bytes());
+    for channel in [0.2f32, 0.1, 0.05] {
+        bytes.extend(channel.to_le_bytes());
+    }
+    bytes.extend(b"test groom");
+    bytes.resize(HAIR_FILE_HEADER_SIZE, 0);
+
+    if arrays & HAIR_FILE_HAS_SEGMENTS != 0 {
+        for strand in strands {
+            bytes.extend(((strand.len() - 1) as u16).to_le_bytes());
+        }
+    }
+    let points = strands.iter().flatten();
+    for point in points.clone() {
+        bytes.extend(point.iter().flat_map(|x| x.to_le_bytes()));
+    }
+    if arrays & HAIR_FILE_HAS_THICKNESS != 0 {
+        for (i, _) in points.clone().enumerate() {
+            bytes.extend((i as f32 * 0.1).to_le_bytes());
+        }
+    }
+    if arrays & HAIR_FILE_HAS_COLOR != 0 {
+        for point in points {
+            bytes.extend(point.iter().flat_map(|x| (x / 10.0).to_le_bytes()));
+        }
+    }
+    bytes
+}
+
+fn groom() -> Vec<Vec<[f32; 3]>> {
+    vec![
+        vec![[0.0, 10.0, 0.0], [0.0, 12.0, 0.0], [1.0, 14.0, 0.0]],
+        vec![
+            [1.0, 10.0, 0.0],
+            [1.0, 11.0, 1.0],
+            [1.0, 12.0, 2.0],
+            [1.0, 13.0, 3.0],
+        ],
+    ]
+}
+
+#[test]
+fn parses_header_and_arrays() {
+    let arrays = HAIR_FILE_HAS_SEGMENTS
+        | HAIR_FILE_HAS_POINTS
+        | HAIR_FILE_HAS_THICKNESS
+        | HAIR_FILE_HAS_COLOR;
+    let file = HairFile::parse(&hair_bytes(&groom(), arrays, 7)).unwrap();
+
+    assert_eq!(file.info, "test groom");
+    assert_eq!(file.default_thickness, 0.5);
+    assert_eq!(file.strands.len(), 2);
+    assert_eq!(file.strands[0].points.len(), 3);
+    assert_eq!(file.strands[1].points[3], na::Vector3::new(1.0, 13.0, 3.0));
+
+    // Per point arrays continue across the strands
+    let thickness = file.strands[1].thickness.as_ref().unwrap();
+    assert!((thickness[0] - 0.3).abs() < 1e-6);
+
+    // The colors are skipped but still have to be there
+    let bytes = hair_bytes(&groom(), arrays, 7);
+    assert!(matches!(
+        HairFile::parse(&bytes[..bytes.len() - 4]),
+        Err(HairFileError::Truncated)
+    ));
+}
+
+#[test]
+fn default_segments_without_segment_array() {
+    let strands = vec![vec![[0.0, 0.0, 0.0]; 3]; 4];
+    let file = HairFile::parse(&hair_bytes(&strands, HAIR_FILE_HAS_POINTS, 2)).unwrap();
+    assert_eq!(file.strands.len(), 4);
+    assert!(file.strands.iter().all(|strand| strand.points.len() == 3));
+
+    // The header disagrees with the strands
+    assert!(matches!(
+        HairFile::parse(&hair_bytes(&strands, HAIR_FILE_HAS_POINTS, 3)),
+        Err(HairFileError::PointCount {
+            expected: 12,
+            found: 16
+        })
+    ));
+}
+
+#[test]
+fn rejects_broken_files() {
+    let arrays = HAIR_FILE_HAS_SEGMENTS | HAIR_FILE_HAS_POINTS;
+    let bytes = hair_bytes(&groom(), arrays, 0);
+
+    assert!(matches!(
+        HairFile::parse(&bytes[..bytes.len() - 1]),
+        Err(HairFileError::Truncated)
+    ));
+    assert!(matches!(
+        HairFile::parse(b"NOPE"),
+        Err(HairFileError::Signature)
+    ));
+    assert!(matches!(
+        HairFile::parse(&hair_bytes(&groom(), HAIR_FILE_HAS_SEGMENTS, 0)),
+        Err(HairFileError::MissingPoints)
+    ));
+}
+
+#[test]
+fn resamples_at_equal_arc_length() {
+    let points = [
+        na::Vector3::new(0.0, 0.0, 0.0),
+        na::Vector3::new(1.0, 0.0, 0.0),
+        na::Vector3::new(1.0, 2.0, 0.0),
+    ];
+    let resampled = resample_polyline(&points, 6);
+    assert_eq!(resampled.len(), 7);
+    for pair in resampled.windows(2) {
+        assert!(((pair[1] - pair[0]).norm() - 0.5).abs() < 1e-12);
+    }
+    assert_eq!(resampled[6], points[2]);
+
+    // Repeated points are stepped over
+    let points = [points[0], points[1], points[1], points[2]];
+    assert_eq!(resample_polyline(&points, 6), resampled);
+    let resampled = resample_polyline(&[points[1]; 3], 4);
+    assert_eq!(resampled, vec![points[1]; 5]);
+}
+
+#[test]
+fn imports_strands_rooted_on_the_head() {
+    let arrays = HAIR_FILE_HAS_SEGMENTS | HAIR_FILE_HAS_POINTS | HAIR_FILE_HAS_THICKNESS;
+    let file = HairFile::parse(&hair_bytes(&groom(), arrays, 5)).unwrap();
+    let head = Head {
+        position: na::Vector3::new(0.0, 2.0, 0.0),
+        radius: 0.1,
+        ..Default::default()
+    };
+    let import = HairImport {
+        scale: 0.01,
+        segments: Some(5),
+        ..Default::default()
+    };
+    let data = file.to_simulation_data(head, &import);
+
+    assert_eq!(data.hairs.strands.len(), 2);
+    assert_eq!(data.head.attachments.len(), 2);
+    for (strand, source) in data.hairs.strands.iter().zip(groom()) {
+        // The segment count of the import, over the length of the strand
+        assert_eq!(strand.l_num, 5);
+        let length: f64 = source
+            .windows(2)
+            .map(|pair| {
+                let [a, b] = [pair[0], pair[1]].map(|p| na::Vector3::new(p[0], p[1], p[2]));
+                (b - a).norm() as f64 * 0.01
+            })
+            .sum();
+        // Chords across the corners of the file strand are a little shorter
+        let rest_length: f64 = strand.l_rest_length.iter().sum();
+        assert!(rest_length <= length * (1.0 + 1e-6) && rest_length > 0.98 * length);
+
+        let root = na::Vector3::new(source[0][0], source[0][1], source[0][2]).cast::<f64>();
+        assert!((strand.v_position[0] - (data.head.position + root * 0.01)).norm() < 1e-6);
+        assert_eq!(strand.v_rest_twist.len(), strand.v_num);
+    }
+}
+
+#[test]
+fn imports_the_segments_of_each_strand() {
+    // A segment array and no default segment count in the header
+    let arrays = HAIR_FILE_HAS_SEGMENTS | HAIR_FILE_HAS_POINTS;
+    let file = HairFile::parse(&hair_bytes(&groom(), arrays, 0)).unwrap();
+    let data = file.to_simulation_data(Head::default(), &HairImport::default());
+
+    assert_eq!(data.hairs.strands.len(), 2);
+    for (strand, source) in data.hairs.strands.iter().zip(groom()) {
+        assert_eq!(strand.l_num, source.len() - 1);
+        assert!(strand
+            .v_position
+            .iter()
+            .all(|p| p.iter().all(|x| x.is_finite())));
+    }
+
+    // Unless the import asks for a count
+    let import = HairImport {
+        segments: Some(4),
+        ..Default::default()
+    };
+    let data = file.to_simulation_data(Head::default(), &import);
+    assert!(data.hairs.strands.iter().all(|strand| strand.l_num == 4));
+}
+
+// An L shaped strand and a straight one
+fn bent_hairs() -> Hairs {
+    let strands = [
+        vec![
+            na::Vector3::new(0.0, 0.0, 0.0),
+            na::Vector3::new(0.0, -0.1, 0.0),
+            na::Vector3::new(0.1, -0.1, 0.0),
+        ],
+        vec![
+            na::Vector3::new(1.0, 0.0, 0.0),
+            na::Vector3::new(1.0, -0.1, 0.0),
+            na::Vector3::new(1.0, -0.2, 0.0),
+            na::Vector3::new(1.0, -0.3, 0.0),
+        ],
+    ]
+    .iter()
+    .map(|positions| hair_strand_from_vertices(positions, None, 1e-6, 1e9, 1e9, 1e-4, 1))
+    .collect();
+    Hairs { strands }
+}
+
+#[test]
+fn writes_ply_vertices_and_edges() {
+    let mut hairs = bent_hairs();
+    hairs.strands[1].v_velocity[3] = na::Vector3::new(0.0, 0.0, 2.0);
+    hairs.strands[1].l_twist[2] = 0.25;
+    let attributes = ExportAttributes {
+        velocity: true,
+        twist: true,
+        curvature: true,
+    };
+    let mut bytes = Vec::new();
+    write_ply(&mut bytes, &hairs, attributes).unwrap();
+    let text = String::from_utf8(bytes).unwrap();
+
+    let (header, body) = text.split_once("end_header\n").unwrap();
+    assert!(header.starts_with("ply\nformat ascii 1.0\n"));
+    assert!(header.contains("element vertex 7\n"));
+    assert!(header.contains("element edge 5\n"));
+    assert!(header.contains("property double curvature\n"));
+
+    let lines: Vec<Vec<f64>> = body
+        .lines()
+        .map(|line| line.split(' ').map(|x| x.parse().unwrap()).collect())
+        .collect();
+    assert_eq!(lines.len(), 7 + 5);
+    // x y z vx vy vz curvature strand
+    assert_eq!(lines[6], vec![1.0, -0.3, 0.0, 0.0, 0.0, 2.0, 0.0, 1.0]);
+    // The edges of the second strand continue the vertex indices
+    assert_eq!(lines[7 + 2], vec![3.0, 4.0, 0.0]);
+    assert_eq!(lines[7 + 4], vec![5.0, 6.0, 0.25]);
+}
+
+#[test]
+fn writes_obj_lines() {
+    let mut bytes = Vec::new();
+    write_obj(&mut bytes, &bent_hairs()).unwrap();
+    let text = String::from_utf8(bytes).unwrap();
+
+    assert_eq!(
+        text.lines().filter(|line| line.starts_with("v ")).count(),
+        7
+    );
+    let records: Vec<&str> = text.lines().filter(|line| line.starts_with("l ")).collect();
+    assert_eq!(records, vec!["l 1 2 3", "l 4 5 6 7"]);
+}
+
+#[test]
+fn curvature_of_a_right_angle() {
+    let hairs = bent_hairs();
+    // 2 tan(45°) over a Voronoi length of 0.1
+    let curvature = vertex_curvature(&hairs.strands[0]);
+    assert_eq!(curvature[0], 0.0);
+    assert!((curvature[1] - 20.0).abs() < 1e-9);
+    assert_eq!(curvature[2], 0.0);
+    assert!(vertex_curvature(&hairs.strands[1])
+        .iter()
+        .all(|kappa| kappa.abs() < 1e-12));
+}
+
+fn snapshot() -> Snapshot {
+    let mut hairs = bent_hairs();
+    hairs.strands[0].v_velocity[2] = na::Vector3::new(0.1, -1.0 / 3.0, 1e-17);
+    hairs.strands[1].l_twist[1] = std::f64::consts::PI / 7.0;
+    let sdf = SignedDistanceField {
+        origin: na::Vector3::new(-1.0, -1.0, -1.0),
+        cell_size: 1.0,
+        dims: [2, 2, 2],
+        values: vec![0.5, -0.25, 0.125, 1.0, 2.0, -3.0, 0.1, 0.2],
+    };
+    let mut data = SimulationData {
+        hairs,
+        head: Head {
+            position: na::Vector3::new(0.0, 2.0, 0.0),
+            radius: 0.1,
+            ..Default::default()
+        },
+        colliders: vec![
+            Collider::new(ColliderShape::Mesh(Arc::new(sdf))),
+            Collider::new(ColliderShape::Capsule {
+                radius: 0.1,
+                half_length: 0.2,
+            }),
+        ],
+        ..Default::default()
+    };
+    data.config.hair_collision.enabled = true;
+    Snapshot::new(42, data)
+}
+
+#[test]
+fn snapshots_round_trip_exactly() {
+    let snapshot = snapshot();
+    let bytes = snapshot.to_bytes().unwrap();
+
+    let binary = Snapshot::from_bytes(&bytes).unwrap();
+    assert_eq!(binary.iteration_cnt, 42);
+    assert_eq!(binary.to_bytes().unwrap(), bytes);
+
+    // Floats are written in full, the text restores the same bits
+    let ron = Snapshot::from_ron(&snapshot.to_ron().unwrap()).unwrap();
+    assert_eq!(ron.to_bytes().unwrap(), bytes);
+    let strand = &ron.data.hairs.strands[0];
+    assert_eq!(strand.v_velocity[2].y, -1.0 / 3.0);
+    assert_eq!(
+        strand.l_rest_kappa,
+        snapshot.data.hairs.strands[0].l_rest_kappa
+    );
+    assert!(matches!(
+        ron.data.colliders[0].shape,
+        ColliderShape::Mesh(ref sdf) if sdf.values[5] == -3.0
+    ));
+}
+
+#[test]
+fn rejects_other_snapshot_versions() {
+    let mut snapshot = snapshot();
+    snapshot.version = SNAPSHOT_VERSION + 1;
+
+    let mut bytes = snapshot.to_bytes().unwrap();
+    assert!(matches!(
+        Snapshot::from_bytes(&bytes),
+        Err(SnapshotError::Version { found }) if found == SNAPSHOT_VERSION + 1
+    ));
+    bytes[0] = b'X';
+    assert!(matches!(
+        Snapshot::from_bytes(&bytes),
+        Err(SnapshotError::Signature)
+    ));
+    assert!(matches!(
+        Snapshot::from_ron(&snapshot.to_ron().unwrap()),
+        Err(SnapshotError::Version { .. })
+    ));
+}
+
+#[test]
+fn rejects_old_ron_snapshots() {
+    // Of another layout, its fields are skipped rather than decoded
+    let old = "(version: 1, iteration_cnt: 3, hairs: (strands: []), head: (radius: 0.1))";
+    assert!(matches!(
+        Snapshot::from_ron(old),
+        Err(SnapshotError::Version { found: 1 })
+    ));
+    assert!(matches!(
+        Snapshot::from_ron("(iteration_cnt: 3, version: 2)"),
+        Err(SnapshotError::Signature)
+    ));
+}
+
+#[test]
+fn recordings_seek_to_any_frame() {
+    let mut data = snapshot().data;
+    let mut bytes = Vec::new();
+    let mut recorder = Recorder::new(&mut bytes, &data).unwrap();
+    for i in 0..5 {
+        data.head.position.x = i as f64;
+        data.head.rotation = Quat::from_rotation_y(i as f32);
+        data.hairs.strands[1].v_position[3].z = 0.5 * i as f64;
+        let elapsed = Duration::from_micros(100 * i);
+        let frame = RecordedFrame::from_data(10 + i, elapsed, 2, i as usize, &data);
+        recorder.record(&frame).unwrap();
+    }
+    assert_eq!(recorder.frame_num, 5);
+
+    // A frame of other strands is refused
+    let mut other = data.clone();
+    other.hairs.strands.pop();
+    let frame = RecordedFrame::from_data(0, Duration::ZERO, 1, 0, &other);
+    assert!(matches!(
+        recorder.record(&frame),
+        Err(RecordingError::Layout)
+    ));
+
+    // A frame cut short while it was written is not counted
+    drop(recorder);
+    bytes.extend([0; 7]);
+    let mut recording = Recording::new(Cursor::new(bytes)).unwrap();
+    assert_eq!(recording.frame_num().unwrap(), 5);
+
+    for i in [3, 0, 4] {
+        let frame = recording.frame(i).unwrap();
+        assert_eq!(frame.iteration_cnt, 10 + i as u64);
+        assert_eq!(frame.elapsed, Duration::from_micros(100 * i as u64));
+        assert_eq!(frame.substep_retries, i);
+
+        let mut shown = snapshot().data;
+        frame.apply(&mut shown);
+        assert_eq!(shown.head.position.x, i as f64);
+        assert_eq!(shown.head.rotation, Quat::from_rotation_y(i as f32));
+        assert_eq!(shown.hairs.strands[1].v_position[3].z, 0.5 * i as f64);
+        assert_eq!(shown.hairs.strands[0].v_position[1].y, -0.1f32 as f64);
+    }
+}
+
+#[test]
+fn rejects_broken_recordings() {
+    assert!(matches!(
+        Recording::new(Cursor::new(b"HSNP\x01\0\0\0".to_vec())),
+        Err(RecordingError::Signature)
+    ));
+    assert!(matches!(
+        Recording::new(Cursor::new(b"HREC\x07\0\0\0".to_vec())),
+        Err(RecordingError::Version { found: 7 })
+    ));
+    assert!(matches!(
+        Recording::new(Cursor::new(b"HREC\x01\0\0\0\x02\0".to_vec())),
+        Err(RecordingError::Io(_))
+    ));
+}
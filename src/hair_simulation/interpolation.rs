@@ -0,0 +1,161 @@
+use bevy::{
+    ecs::component::Component,
+    tasks::{ComputeTaskPool, ParallelSlice, TaskPool},
+};
+extern crate nalgebra as na;
+
+use crate::{
+    hair_simulation::{
+        data::{segment_instance_data, SimulationData},
+        scalp::{HairRoot, RootSampling, ScalpMesh},
+    },
+    plugins::instanced_mesh::InstanceData,
+};
+
+pub const RENDER_CHUNK_SIZE: usize = 256;
+
+// A drawn strand that follows the simulated guide strands
+#[derive(Clone, Debug)]
+pub struct RenderStrand {
+    // Root in the head frame
+    pub root: na::Vector3<f64>,
+    // Guide strand indices and their weights, summing to one
+    pub guides: Vec<(usize, f64)>,
+}
+
+// Render strands interpolated from the guides when the hair is drawn, on the
+// hairs entity. Without it the guides themselves are drawn.
+#[derive(Component, Clone, Debug, Default)]
+pub struct RenderHairs {
+    pub strands: Vec<RenderStrand>,
+    // Pull of the heaviest guide on the strand, growing from nothing at the
+    // root to `clumping` at the tip so that the strands gather around it
+    pub clumping: f64,
+}
+
+impl RenderHairs {
+    // Strands over the scalp weighted by the barycentric coordinates of their
+    // triangle, `vertex_guides[i]` being the guide strand grown at vertex i of
+    // the scalp, e.g. `0..n` for the strands of `ScalpMesh::vertex_roots`
+    pub fn barycentric(
+        scalp: &ScalpMesh,
+        vertex_guides: &[usize],
+        sampling: RootSampling,
+        count: usize,
+        seed: u64,
+        clumping: f64,
+    ) -> Self {
+        let strands = scalp
+            .sample_points(sampling, count, seed)
+            .into_iter()
+            .map(|(triangle, weights)| RenderStrand {
+                root: (0..3)
+                    .map(|k| weights[k] * scalp.positions[triangle[k]])
+                    .sum(),
+                guides: (0..3)
+                    .map(|k| (vertex_guides[triangle[k]], weights[k]))
+                    .collect(),
+            })
+            .collect();
+
+        RenderHairs { strands, clumping }
+    }
+
+    // Strands at the given roots weighted by the inverse squared distance to
+    // their `k` nearest guide roots
+    pub fn nearest_guides(
+        roots: &[HairRoot],
+        data: &SimulationData,
+        k: usize,
+        clumping: f64,
+    ) -> Self {
+        let guide_roots: Vec<na::Vector3<f64>> = data
+            .hairs
+            .strands
+            .iter()
+            .map(|strand| data.head.attachments[strand.attachment])
+            .collect();
+
+        let strands = roots
+            .iter()
+            .map(|root| {
+                let mut nearest: Vec<(usize, f64)> = guide_roots
+                    .iter()
+                    .enumerate()
+                    .map(|(i, guide)| (i, (guide - root.position).norm_squared()))
+                    .collect();
+                nearest.sort_by(|a, b| a.1.total_cmp(&b.1));
+                nearest.truncate(k.max(1));
+
+                // A root on a guide follows it exactly
+                let mut guides: Vec<(usize, f64)> = match nearest.first() {
+                    Some(&(i, distance)) if distance < 1e-12 => vec![(i, 1.0)],
+                    _ => nearest.iter().map(|&(i, d)| (i, 1.0 / d)).collect(),
+                };
+                let total: f64 = guides.iter().map(|(_, weight)| weight).sum();
+                for (_, weight) in guides.iter_mut() {
+                    *weight /= total;
+                }
+
+                RenderStrand {
+                    root: root.position,
+                    guides,
+                }
+            })
+            .collect();
+
+        RenderHairs { strands, clumping }
+    }
+
+    // Vertices of a render strand: its root moved along with the blended
+    // guides, then pulled towards the heaviest guide by the clumping. None of
+    // them when one of its guides is not in the data.
+    pub fn positions(&self, strand: &RenderStrand, data: &SimulationData) -> Vec<na::Vector3<f64>> {
+        let Some(guides) = strand
+            .guides
+            .iter()
+            .map(|&(g, weight)| data.hairs.strands.get(g).map(|guide| (guide, weight)))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return Vec::new();
+        };
+        let Some(&(clump, _)) = guides.iter().max_by(|a, b| a.1.total_cmp(&b.1)) else {
+            return Vec::new();
+        };
+        let v_num = guides
+            .iter()
+            .map(|(guide, _)| guide.v_num)
+            .min()
+            .unwrap_or(0);
+        let root = data.head.to_world(&strand.root);
+
+        (0..v_num)
+            .map(|j| {
+                let mut position = root;
+                for &(guide, weight) in guides.iter() {
+                    position += weight * (guide.v_position[j] - guide.v_position[0]);
+                }
+                let pull = self.clumping * j as f64 / (v_num - 1).max(1) as f64;
+                position * (1.0 - pull) + clump.v_position[j] * pull
+            })
+            .collect()
+    }
+
+    pub fn to_instance_data(&self, data: &SimulationData) -> Vec<InstanceData> {
+        let task_pool = ComputeTaskPool::get_or_init(TaskPool::default);
+        self.strands
+            .par_chunk_map(task_pool, RENDER_CHUNK_SIZE, |strands| {
+                strands
+                    .iter()
+                    .filter_map(|strand| {
+                        let positions = self.positions(strand, data);
+                        (positions.len() > 2).then(|| segment_instance_data(&positions))
+                    })
+                    .flatten()
+                    .collect::<Vec<_>>()
+            })
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+}
@@ -5,11 +5,19 @@ use bevy::{
 
 use self::conversion::do_apply;
 
+pub mod collider;
+pub mod config;
 pub mod conversion;
 pub mod data;
+pub mod formats;
+pub mod interpolation;
 pub mod pipeline;
+pub mod scalp;
 pub mod simulation;
 
+#[cfg(test)]
+mod tests;
+
 // Marker
 #[derive(Component)]
 pub struct HairsMarker;
@@ -27,3 +35,7 @@ impl Plugin for HairSimulationPlugin {
 
 const HAIR_THICKNESS: f64 = 0.001;
 const HAIR_SEG_LENGTH: f64 = 0.1;
+// Resolution of the signed distance field baked from the head mesh
+const HEAD_SDF_CELL_SIZE: f64 = 0.005;
+// Strands drawn by interpolating the simulated guide strands
+const RENDER_HAIR_NUM: usize = 4096;
@@ -0,0 +1,83 @@
+use crate::{
+    hair_simulation::{
+        collider::Collider, config::ContactResponse, data::HairStrand,
+        pipeline::der::state::StrandState,
+    },
+    physic_simulation::interfaces::ContactCounts,
+};
+extern crate nalgebra as na;
+
+// Resolve the contacts of a solved step with the colliders, one vertex and
+// collider at a time. Velocity changes move the positions by `factor` times as
+// much, as in `apply_inextensibility`, and whatever penetration is left is
+// projected out without touching the velocity.
+pub fn apply_contact_response(
+    settings: ContactResponse,
+    strand: &HairStrand,
+    state: &StrandState,
+    colliders: &[Collider],
+    factor: f64,
+    position: &mut na::DVector<f64>,
+    velocity: &mut na::DVector<f64>,
+) -> ContactCounts {
+    let mut counts = ContactCounts::default();
+    if !settings.enabled {
+        return counts;
+    }
+
+    for i in (0..strand.v_num).filter(|&i| !strand.v_pinned[i]) {
+        let dof = state.vertex_dof(i);
+        for collider in colliders.iter() {
+            let x: na::Vector3<f64> = position.fixed_rows::<3>(dof).into();
+            let Some((distance, normal)) = collider.distance(&x) else {
+                continue;
+            };
+            if distance >= collider.thickness {
+                continue;
+            }
+            counts.total += 1;
+
+            let v: na::Vector3<f64> = velocity.fixed_rows::<3>(dof).into();
+            let relative = v - collider.surface_velocity(&x);
+            let normal_speed = relative.dot(&normal);
+
+            // Normal impulse per unit mass removing the inbound velocity, and
+            // the one the projection out of the skin stands for, less what the
+            // vertex leaves with anyway. A resting vertex only has the latter.
+            let impulse = (-(1.0 + settings.restitution) * normal_speed).max(0.0);
+            let push_out = if factor > 0.0 {
+                (collider.thickness - distance) / factor - normal_speed.max(0.0)
+            } else {
+                0.0
+            };
+            let bound = impulse.max(push_out);
+            if bound > 0.0 {
+                // Coulomb friction bounded by the normal impulse
+                let tangential = relative - normal_speed * normal;
+                let slip = tangential.norm();
+                let friction = if slip <= collider.static_friction * bound {
+                    counts.sticking += 1;
+                    -tangential
+                } else {
+                    counts.sliding += 1;
+                    -tangential * (collider.kinetic_friction * bound / slip).min(1.0)
+                };
+
+                let delta = impulse * normal + friction;
+                let mut v = velocity.fixed_rows_mut::<3>(dof);
+                v += delta;
+                let mut x = position.fixed_rows_mut::<3>(dof);
+                x += factor * delta;
+            }
+
+            let x: na::Vector3<f64> = position.fixed_rows::<3>(dof).into();
+            if let Some((distance, normal)) = collider.distance(&x) {
+                if distance < collider.thickness {
+                    let mut x = position.fixed_rows_mut::<3>(dof);
+                    x += (collider.thickness - distance) * normal;
+                }
+            }
+        }
+    }
+    counts
+}
@@ -0,0 +1,56 @@
+use crate::hair_simulation::{
+    config::Damping,
+    data::HairStrand,
+    pipeline::{der::state::StrandState, utils::band_matrix::SymmetricBandMatrix},
+};
+
+use super::utils::add_local_to_matrix;
+extern crate nalgebra as na;
+
+// Linear damping of one step, the force is -D v_(n+1). The air drag uses the
+// speed at the start of the step so that D stays constant while solving.
+pub fn damping_matrix(
+    damping: &Damping,
+    strand: &HairStrand,
+    state: &StrandState,
+    mass: &na::DVector<f64>,
+    hessian: Option<&SymmetricBandMatrix>,
+) -> Option<SymmetricBandMatrix> {
+    if damping.mass == 0.0 && damping.stiffness == 0.0 && damping.air_drag == 0.0 {
+        return None;
+    }
+
+    let mut matrix = SymmetricBandMatrix::zeros(state.dof_num(), state.bandwidth());
+    matrix.add_diagonal(&(damping.mass * mass));
+    // Without a Hessian (symplectic Euler) there is no stiffness to scale
+    if let Some(hessian) = hessian {
+        matrix.add_scaled(hessian, damping.stiffness);
+    }
+
+    if damping.air_drag > 0.0 {
+        for i in 0..strand.l_num {
+            let t = state.reference_frame[i].t;
+            let normal = na::Matrix3::identity() - t * t.transpose();
+            let velocity = (strand.v_velocity[i] + strand.v_velocity[i + 1]) / 2.0;
+            let speed = (normal * velocity).norm();
+
+            // F = -rho C_d / 2 * 2 r l * |u_n| u_n on the segment midpoint u,
+            // shared by both vertices
+            let drag = damping.air_drag * 2.0 * strand.radius * state.length_vec[i] * speed;
+            let block = drag / 4.0 * normal;
+            let mut local = na::SMatrix::<f64, 6, 6>::zeros();
+            for (r, c) in [(0, 0), (0, 3), (3, 0), (3, 3)] {
+                local.fixed_view_mut::<3, 3>(r, c).copy_from(&block);
+            }
+
+            let mut dofs = [0; 6];
+            for k in 0..3 {
+                dofs[k] = state.vertex_dof(i) + k;
+                dofs[3 + k] = state.vertex_dof(i + 1) + k;
+            }
+            add_local_to_matrix(&mut matrix, &dofs, &local);
+        }
+    }
+
+    Some(matrix)
+}
@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+
+use bevy::tasks::{ComputeTaskPool, ParallelSlice, TaskPool};
+
+use crate::hair_simulation::{config::HairCollision, data::HairStrand};
+extern crate nalgebra as na;
+
+// Segments handed to one task of the compute pool
+pub const SEGMENT_CHUNK_SIZE: usize = 256;
+
+type Cell = (i64, i64, i64);
+
+// A segment of a strand, edge `edge` between vertices edge and edge + 1
+#[derive(Clone, Copy)]
+struct Segment {
+    strand: usize,
+    edge: usize,
+}
+
+// Segments of different strands within the collision distance, with the
+// closest points at parameters `s` and `t` along them
+struct Proximity {
+    a: Segment,
+    b: Segment,
+    s: f64,
+    t: f64,
+}
+
+// Changes of the two vertices of a segment
+struct VertexDeltas {
+    velocity: [na::Vector3<f64>; 2],
+    position: [na::Vector3<f64>; 2],
+}
+
+// Uniform grid of cells hashed by their integer coordinates, each holding the
+// segments whose padded bounding box overlaps it
+struct SpatialHash {
+    cell_size: f64,
+    cells: HashMap<Cell, Vec<usize>>,
+}
+
+impl SpatialHash {
+    fn cell(&self, point: &na::Vector3<f64>) -> Cell {
+        let cell = point.map(|x| (x / self.cell_size).floor() as i64);
+        (cell.x, cell.y, cell.z)
+    }
+
+    fn cells_of(
+        &self,
+        bounds: &(na::Vector3<f64>, na::Vector3<f64>),
+    ) -> impl Iterator<Item = Cell> {
+        let (min, max) = (self.cell(&bounds.0), self.cell(&bounds.1));
+        (min.0..=max.0).flat_map(move |x| {
+            (min.1..=max.1).flat_map(move |y| (min.2..=max.2).map(move |z| (x, y, z)))
+        })
+    }
+}
+
+fn endpoints(strands: &[HairStrand], segment: Segment) -> (na::Vector3<f64>, na::Vector3<f64>) {
+    let strand = &strands[segment.strand];
+    (
+        strand.v_position[segment.edge],
+        strand.v_position[segment.edge + 1],
+    )
+}
+
+fn bounds(
+    strands: &[HairStrand],
+    segment: Segment,
+    padding: f64,
+) -> (na::Vector3<f64>, na::Vector3<f64>) {
+    let (p, q) = endpoints(strands, segment);
+    let pad = na::Vector3::repeat(padding);
+    (p.inf(&q) - pad, p.sup(&q) + pad)
+}
+
+// Parameters of the closest points of the segments p0 p1 and q0 q1, both
+// clamped to [0, 1]
+pub fn closest_segment_parameters(
+    p0: &na::Vector3<f64>,
+    p1: &na::Vector3<f64>,
+    q0: &na::Vector3<f64>,
+    q1: &na::Vector3<f64>,
+) -> (f64, f64) {
+    let d1 = p1 - p0;
+    let d2 = q1 - q0;
+    let r = p0 - q0;
+    let a = d1.norm_squared();
+    let e = d2.norm_squared();
+    let f = d2.dot(&r);
+    let c = d1.dot(&r);
+    let b = d1.dot(&d2);
+
+    // Parallel segments have no unique pair, start from p0
+    let denominator = a * e - b * b;
+    let mut s = if denominator > 1e-12 * a * e {
+        ((b * f - c * e) / denominator).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let mut t = (b * s + f) / e;
+    if t < 0.0 {
+        t = 0.0;
+        s = (-c / a).clamp(0.0, 1.0);
+    } else if t > 1.0 {
+        t = 1.0;
+        s = ((b - c) / a).clamp(0.0, 1.0);
+    }
+    (s, t)
+}
+
+// Pairs of segments of different strands closer than the collision distance,
+// found through a spatial hash with cells as large as the longest segment
+fn find_proximities(strands: &[HairStrand], distance: f64) -> Vec<Proximity> {
+    let segments: Vec<Segment> = strands
+        .iter()
+        .enumerate()
+        .filter(|(_, strand)| !strand.frozen)
+        .flat_map(|(strand, hair)| {
+            // Segments at a pinned vertex follow the head, and would need an
+            // unbounded velocity change at their free end near the pin
+            (0..hair.l_num)
+                .filter(|&edge| !hair.v_pinned[edge] && !hair.v_pinned[edge + 1])
+                .map(move |edge| Segment { strand, edge })
+        })
+        .collect();
+    let longest = segments
+        .iter()
+        .map(|&segment| {
+            let (p, q) = endpoints(strands, segment);
+            (q - p).norm()
+        })
+        .fold(distance, f64::max);
+
+    let mut hash = SpatialHash {
+        cell_size: longest,
+        cells: HashMap::new(),
+    };
+    for (index, &segment) in segments.iter().enumerate() {
+        let bounds = bounds(strands, segment, distance / 2.0);
+        for cell in hash.cells_of(&bounds).collect::<Vec<_>>() {
+            hash.cells.entry(cell).or_default().push(index);
+        }
+    }
+
+    let task_pool = ComputeTaskPool::get_or_init(TaskPool::default);
+    let indices: Vec<usize> = (0..segments.len()).collect();
+    indices
+        .par_chunk_map(task_pool, SEGMENT_CHUNK_SIZE, |indices| {
+            let mut proximities = Vec::new();
+            let mut candidates: Vec<usize> = Vec::new();
+            for &index in indices {
+                let a = segments[index];
+                candidates.clear();
+                for cell in hash.cells_of(&bounds(strands, a, distance / 2.0)) {
+                    if let Some(others) = hash.cells.get(&cell) {
+                        // Each pair once, from the segment of the lower strand
+                        candidates.extend(
+                            others
+                                .iter()
+                                .filter(|&&other| segments[other].strand > a.strand),
+                        );
+                    }
+                }
+                candidates.sort_unstable();
+                candidates.dedup();
+
+                let (p0, p1) = endpoints(strands, a);
+                for &other in candidates.iter() {
+                    let b = segments[other];
+                    let (q0, q1) = endpoints(strands, b);
+                    let (s, t) = closest_segment_parameters(&p0, &p1, &q0, &q1);
+                    let gap = (p0.lerp(&p1, s) - q0.lerp(&q1, t)).norm();
+                    if gap < distance && gap > 0.0 {
+                        proximities.push(Proximity { a, b, s, t });
+                    }
+                }
+            }
+            proximities
+        })
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+// Velocity and position changes of the vertices of both segments of a close
+// pair. An inelastic normal impulse with Coulomb friction bounded by it stops
+// the approach, and part of the overlap is removed from the positions alone so
+// that the repulsion adds no energy. Velocity changes move the positions of a
+// strand by its factor times as much.
+fn pair_response(
+    settings: &HairCollision,
+    strands: &[HairStrand],
+    factors: &[f64],
+    proximity: &Proximity,
+) -> [(Segment, VertexDeltas); 2] {
+    let (a, b) = (proximity.a, proximity.b);
+    let (p0, p1) = endpoints(strands, a);
+    let (q0, q1) = endpoints(strands, b);
+    let offset = p0.lerp(&p1, proximity.s) - q0.lerp(&q1, proximity.t);
+    let gap = offset.norm();
+    let normal = offset / gap;
+
+    let va = &strands[a.strand].v_velocity;
+    let vb = &strands[b.strand].v_velocity;
+    let relative = va[a.edge].lerp(&va[a.edge + 1], proximity.s)
+        - vb[b.edge].lerp(&vb[b.edge + 1], proximity.t);
+    let normal_speed = relative.dot(&normal);
+
+    let mut change = na::Vector3::zeros();
+    if normal_speed < 0.0 {
+        let tangential = relative - normal_speed * normal;
+        let slip = tangential.norm();
+        change -= normal_speed * normal;
+        if slip > 0.0 {
+            change -= tangential * (-settings.friction * normal_speed / slip).min(1.0);
+        }
+    }
+    let push = settings.repulsion * (settings.distance - gap) * normal;
+
+    let inverse_mass =
+        |segment: Segment, k: usize| 1.0 / strands[segment.strand].v_mass[segment.edge + k];
+    let weights = [
+        [1.0 - proximity.s, proximity.s],
+        [1.0 - proximity.t, proximity.t],
+    ];
+    let compliance: f64 = (0..2)
+        .map(|k| {
+            weights[0][k].powi(2) * inverse_mass(a, k) + weights[1][k].powi(2) * inverse_mass(b, k)
+        })
+        .sum();
+
+    // Impulses giving the relative changes at the closest points
+    let impulse = change / compliance;
+    let push = push / compliance;
+    let deltas = |segment: Segment, weights: [f64; 2], sign: f64| VertexDeltas {
+        velocity: [0, 1].map(|k| sign * weights[k] * inverse_mass(segment, k) * impulse),
+        position: [0, 1].map(|k| {
+            sign * weights[k]
+                * inverse_mass(segment, k)
+                * (factors[segment.strand] * impulse + push)
+        }),
+    };
+    [
+        (a, deltas(a, weights[0], 1.0)),
+        (b, deltas(b, weights[1], -1.0)),
+    ]
+}
+
+// Push apart segments of different strands closer than the collision
+// distance. The responses of all close pairs are computed from the same state
+// and summed. `factors` holds the position factor of the step of each strand,
+// as in `apply_contact_response`, zero for the strands that did not step.
+// Returns the number of close pairs.
+pub fn apply_hair_collisions(
+    settings: HairCollision,
+    strands: &mut [HairStrand],
+    factors: &[f64],
+) -> usize {
+    if !settings.enabled || factors.iter().all(|&factor| factor <= 0.0) {
+        return 0;
+    }
+
+    let proximities = find_proximities(strands, settings.distance);
+    let task_pool = ComputeTaskPool::get_or_init(TaskPool::default);
+    let shared: &[HairStrand] = strands;
+    let responses = proximities.par_chunk_map(task_pool, SEGMENT_CHUNK_SIZE, |proximities| {
+        proximities
+            .iter()
+            .flat_map(|proximity| pair_response(&settings, shared, factors, proximity))
+            .collect::<Vec<_>>()
+    });
+
+    for (segment, deltas) in responses.into_iter().flatten() {
+        let strand = &mut strands[segment.strand];
+        for k in 0..2 {
+            strand.v_velocity[segment.edge + k] += deltas.velocity[k];
+            strand.v_position[segment.edge + k] += deltas.position[k];
+        }
+    }
+    proximities.len()
+}
@@ -0,0 +1,153 @@
+use crate::hair_simulation::{
+    config::Inextensibility,
+    data::HairStrand,
+    pipeline::{der::state::StrandState, utils::band_matrix::SymmetricBandMatrix},
+};
+extern crate nalgebra as na;
+
+// Move the vertices of a solved step back towards the rest edge lengths. The
+// same correction is applied to the velocities, v = (x - base) / factor, so
+// that both stay consistent with the integrator.
+pub fn apply_inextensibility(
+    settings: Inextensibility,
+    strand: &HairStrand,
+    state: &StrandState,
+    factor: f64,
+    position: &mut na::DVector<f64>,
+    velocity: &mut na::DVector<f64>,
+) {
+    if settings == Inextensibility::Off {
+        return;
+    }
+
+    let start: Vec<na::Vector3<f64>> = (0..strand.v_num)
+        .map(|i| position.fixed_rows::<3>(state.vertex_dof(i)).into())
+        .collect();
+    // Pinned vertices do not move
+    let inverse_mass: Vec<f64> = (0..strand.v_num)
+        .map(|i| {
+            if strand.v_pinned[i] {
+                0.0
+            } else {
+                1.0 / strand.v_mass[i]
+            }
+        })
+        .collect();
+
+    let mut vertices = start.clone();
+    match settings {
+        Inextensibility::Off => {}
+        Inextensibility::StrainLimiting {
+            max_strain,
+            iterations,
+        } => limit_strain(strand, &inverse_mass, max_strain, iterations, &mut vertices),
+        Inextensibility::FastProjection {
+            tolerance,
+            max_iterations,
+        } => fast_projection(
+            strand,
+            &inverse_mass,
+            tolerance,
+            max_iterations,
+            &mut vertices,
+        ),
+    }
+
+    for i in 0..strand.v_num {
+        let delta = vertices[i] - start[i];
+        let dof = state.vertex_dof(i);
+        let mut x = position.fixed_rows_mut::<3>(dof);
+        x += delta;
+        let mut v = velocity.fixed_rows_mut::<3>(dof);
+        v += delta / factor;
+    }
+}
+
+fn strain(strand: &HairStrand, vertices: &[na::Vector3<f64>], index: usize) -> f64 {
+    (vertices[index + 1] - vertices[index]).norm() / strand.l_rest_length[index] - 1.0
+}
+
+fn limit_strain(
+    strand: &HairStrand,
+    inverse_mass: &[f64],
+    max_strain: f64,
+    iterations: usize,
+    vertices: &mut [na::Vector3<f64>],
+) {
+    for _ in 0..iterations {
+        let mut clamped = false;
+        for j in 0..strand.l_num {
+            let weight = inverse_mass[j] + inverse_mass[j + 1];
+            let edge_strain = strain(strand, vertices, j);
+            if weight == 0.0 || edge_strain.abs() <= max_strain {
+                continue;
+            }
+            clamped = true;
+
+            let e = vertices[j + 1] - vertices[j];
+            let target =
+                strand.l_rest_length[j] * (1.0 + edge_strain.clamp(-max_strain, max_strain));
+            let correction = (e.norm() - target) * e.normalize() / weight;
+            vertices[j] += inverse_mass[j] * correction;
+            vertices[j + 1] -= inverse_mass[j + 1] * correction;
+        }
+        if !clamped {
+            break;
+        }
+    }
+}
+
+// Constraints C_j = |e_j|^2 / l_j - l_j, each iteration solves
+// (grad C W grad C^T) dlambda = C, a tridiagonal system, and moves
+// x -= W grad C^T dlambda.
+fn fast_projection(
+    strand: &HairStrand,
+    inverse_mass: &[f64],
+    tolerance: f64,
+    max_iterations: usize,
+    vertices: &mut [na::Vector3<f64>],
+) {
+    let l_num = strand.l_num;
+    for _ in 0..max_iterations {
+        let max_strain = (0..l_num)
+            .filter(|&j| inverse_mass[j] + inverse_mass[j + 1] > 0.0)
+            .map(|j| strain(strand, vertices, j).abs())
+            .fold(0.0, f64::max);
+        if max_strain < tolerance {
+            break;
+        }
+
+        let e: Vec<na::Vector3<f64>> = (0..l_num).map(|j| vertices[j + 1] - vertices[j]).collect();
+        let rest = &strand.l_rest_length;
+
+        let mut system = SymmetricBandMatrix::zeros(l_num, 1);
+        let mut constraint = na::DVector::<f64>::zeros(l_num);
+        for j in 0..l_num {
+            let weight = inverse_mass[j] + inverse_mass[j + 1];
+            if weight == 0.0 {
+                // Both ends pinned, nothing can move
+                system.add(j, j, 1.0);
+                continue;
+            }
+            constraint[j] = e[j].norm_squared() / rest[j] - rest[j];
+            system.add(j, j, 4.0 * e[j].norm_squared() / rest[j].powi(2) * weight);
+            if j + 1 < l_num {
+                let coupling =
+                    -4.0 * inverse_mass[j + 1] * e[j].dot(&e[j + 1]) / (rest[j] * rest[j + 1]);
+                system.add(j + 1, j, coupling);
+            }
+        }
+
+        let Some(ldlt) = system.ldlt() else {
+            return;
+        };
+        let dlambda = ldlt.solve(&constraint);
+
+        for j in 0..l_num {
+            // grad_(j+1) C_j = -grad_j C_j = 2 e_j / l_j
+            let gradient = 2.0 * e[j] / rest[j] * dlambda[j];
+            vertices[j] += inverse_mass[j] * gradient;
+            vertices[j + 1] -= inverse_mass[j + 1] * gradient;
+        }
+    }
+}
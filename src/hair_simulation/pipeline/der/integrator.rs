@@ -0,0 +1,128 @@
+use crate::hair_simulation::{
+    config::Integrator, pipeline::utils::band_matrix::SymmetricBandMatrix,
+};
+extern crate nalgebra as na;
+
+// Positions and velocities of a strand, in dof layout
+pub struct DofState {
+    pub position: na::DVector<f64>,
+    pub velocity: na::DVector<f64>,
+}
+
+// Every scheme advances with x_(n+1) = base + factor * v_(n+1), where v_(n+1)
+// minimises the incremental potential
+//   Phi(v) = 1/2 (v - target)^T M (v - target) + E(base + factor * v)
+//            + factor / 2 v^T D v
+// with D the damping matrix, if any.
+pub struct StepScheme {
+    pub target: na::DVector<f64>,
+    pub base: na::DVector<f64>,
+    pub factor: f64,
+    pub damping: Option<SymmetricBandMatrix>,
+}
+
+impl Integrator {
+    pub fn needs_hessian(&self) -> bool {
+        *self != Integrator::SymplecticEuler
+    }
+}
+
+pub fn step_scheme(
+    integrator: Integrator,
+    delta_time: f64,
+    current: &DofState,
+    // The state before current, and the step between the two
+    previous: Option<(DofState, f64)>,
+) -> StepScheme {
+    match (integrator, previous) {
+        (Integrator::Bdf2, Some((previous, previous_delta_time))) if previous_delta_time > 0.0 => {
+            // Variable step BDF2, with w = h_n / h_(n-1) the ratio of the steps
+            //   x_(n+1) = ((1 + w)^2 x_n - w^2 x_(n-1)) / (1 + 2w) + h (1 + w) / (1 + 2w) v_(n+1)
+            // and the velocities alike. Equal steps give the usual
+            //   x_(n+1) = (4 x_n - x_(n-1)) / 3 + 2h/3 v_(n+1)
+            let w = delta_time / previous_delta_time;
+            let current_weight = (1.0 + w).powi(2) / (1.0 + 2.0 * w);
+            let previous_weight = w.powi(2) / (1.0 + 2.0 * w);
+            StepScheme {
+                target: current_weight * &current.velocity - previous_weight * previous.velocity,
+                base: current_weight * &current.position - previous_weight * previous.position,
+                factor: delta_time * (1.0 + w) / (1.0 + 2.0 * w),
+                damping: None,
+            }
+        }
+        // Backward Euler, BDF2 without a previous step starts with it. Symplectic
+        // Euler shares the scheme but only takes the explicit first step.
+        _ => StepScheme {
+            target: current.velocity.clone(),
+            base: current.position.clone(),
+            factor: delta_time,
+            damping: None,
+        },
+    }
+}
+
+impl StepScheme {
+    // The velocity that leaves the strand at x_n, where the first system is assembled
+    pub fn initial_velocity(&self, position: &na::DVector<f64>) -> na::DVector<f64> {
+        (position - &self.base) / self.factor
+    }
+
+    pub fn position(&self, velocity: &na::DVector<f64>) -> na::DVector<f64> {
+        &self.base + self.factor * velocity
+    }
+
+    pub fn objective(
+        &self,
+        mass: &na::DVector<f64>,
+        velocity: &na::DVector<f64>,
+        energy: f64,
+    ) -> f64 {
+        let inertia = velocity - &self.target;
+        let dissipation = self.damping.as_ref().map_or(0.0, |damping| {
+            0.5 * self.factor * velocity.dot(&damping.mul_vector(velocity))
+        });
+        0.5 * inertia.dot(&mass.component_mul(&inertia)) + energy + dissipation
+    }
+
+    pub fn objective_gradient(
+        &self,
+        mass: &na::DVector<f64>,
+        velocity: &na::DVector<f64>,
+        gradient: &na::DVector<f64>,
+    ) -> na::DVector<f64> {
+        let mut objective_gradient =
+            mass.component_mul(&(velocity - &self.target)) + self.factor * gradient;
+        if let Some(damping) = self.damping.as_ref() {
+            objective_gradient += self.factor * damping.mul_vector(velocity);
+        }
+        objective_gradient
+    }
+
+    // Newton system (M + factor D + factor^2 H) dv = -dPhi/dv at the given velocity.
+    // Without a Hessian it is the explicit update of symplectic Euler, with the
+    // damping still implicit.
+    pub fn newton_system(
+        &self,
+        mass: &na::DVector<f64>,
+        velocity: &na::DVector<f64>,
+        gradient: &na::DVector<f64>,
+        hessian: Option<SymmetricBandMatrix>,
+    ) -> (SymmetricBandMatrix, na::DVector<f64>) {
+        let mut matrix = match hessian {
+            Some(mut hessian) => {
+                hessian.scale(self.factor.powi(2));
+                hessian
+            }
+            None => {
+                let bandwidth = self.damping.as_ref().map_or(0, |d| d.bandwidth());
+                SymmetricBandMatrix::zeros(mass.len(), bandwidth)
+            }
+        };
+        matrix.add_diagonal(mass);
+        if let Some(damping) = self.damping.as_ref() {
+            matrix.add_scaled(damping, self.factor);
+        }
+
+        (matrix, -self.objective_gradient(mass, velocity, gradient))
+    }
+}
@@ -0,0 +1,103 @@
+use std::f64::consts::PI;
+
+use crate::hair_simulation::{
+    data::HairStrand,
+    pipeline::{
+        der::{
+            state::StrandState,
+            utils::{add_local_to_matrix, add_local_to_vector},
+        },
+        utils::band_matrix::SymmetricBandMatrix,
+    },
+};
+
+use super::{ElasticEnergy, EnergyContext};
+extern crate nalgebra as na;
+
+pub struct Bend;
+
+pub fn bend_factor(strand: &HairStrand) -> f64 {
+    PI * strand.radius.powi(4) * strand.youngs / 8.0
+}
+
+fn rest_kappa(strand: &HairStrand, index: usize) -> na::Matrix4x1<f64> {
+    strand
+        .l_rest_kappa
+        .get(index)
+        .cloned()
+        .unwrap_or_else(na::Matrix4x1::zeros)
+}
+
+// Jacobian of kappa_i over the stencil of vertex i, see `StrandState::stencil_dofs`
+pub fn calc_kappa_jacobian(state: &StrandState, index: usize) -> na::SMatrix<f64, 4, 11> {
+    let kappa = state.kappa[index];
+    let mut jacobian = na::SMatrix::<f64, 4, 11>::zeros();
+
+    jacobian
+        .fixed_view_mut::<4, 3>(0, 0)
+        .copy_from(&state.nabla_kappa[index][0]);
+    jacobian
+        .fixed_view_mut::<4, 3>(0, 4)
+        .copy_from(&state.nabla_kappa[index][1]);
+    jacobian
+        .fixed_view_mut::<4, 3>(0, 8)
+        .copy_from(&state.nabla_kappa[index][2]);
+
+    // Twisting an edge rotates its material frame: dm1 = m2, dm2 = -m1
+    jacobian.set_column(3, &na::Matrix4x1::new(kappa[2], 0.0, -kappa[0], 0.0));
+    jacobian.set_column(7, &na::Matrix4x1::new(0.0, kappa[3], 0.0, -kappa[1]));
+
+    jacobian
+}
+
+impl ElasticEnergy for Bend {
+    fn name(&self) -> &'static str {
+        "bend"
+    }
+
+    fn energy(&self, context: &EnergyContext) -> f64 {
+        let strand = context.strand;
+        let state = context.state;
+        let h_factor = bend_factor(strand);
+
+        // Over the rest Voronoi length of each vertex
+        let mut energy = 0.0;
+        for i in 1..(strand.v_num - 1) {
+            let kappa_diff = state.kappa[i] - rest_kappa(strand, i);
+            energy += h_factor / (2.0 * strand.get_voronoi_length(i)) * kappa_diff.norm_squared();
+        }
+        energy
+    }
+
+    fn gradient(&self, context: &EnergyContext, gradient: &mut na::DVector<f64>) {
+        let strand = context.strand;
+        let state = context.state;
+        let h_factor = bend_factor(strand);
+
+        for i in 1..(strand.v_num - 1) {
+            let kappa_diff = state.kappa[i] - rest_kappa(strand, i);
+            let jacobian = calc_kappa_jacobian(state, i);
+
+            // Over the whole stencil, so the end vertices feel the curvature next
+            // to them too
+            let local = h_factor / strand.get_voronoi_length(i) * jacobian.transpose() * kappa_diff;
+            add_local_to_vector(gradient, &state.stencil_dofs(i), &local);
+        }
+    }
+
+    fn hessian(&self, context: &EnergyContext, hessian: &mut SymmetricBandMatrix) {
+        let strand = context.strand;
+        let state = context.state;
+        let h_factor = bend_factor(strand);
+
+        for i in 1..(strand.v_num - 1) {
+            let jacobian = calc_kappa_jacobian(state, i);
+
+            // Gauss-Newton, the curvature of kappa itself is dropped. Every block
+            // of J^T J is added, including the rows of the two end vertices, so
+            // the matrix is symmetric.
+            let local = h_factor / strand.get_voronoi_length(i) * jacobian.transpose() * jacobian;
+            add_local_to_matrix(hessian, &state.stencil_dofs(i), &local);
+        }
+    }
+}
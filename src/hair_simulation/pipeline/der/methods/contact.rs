@@ -0,0 +1,134 @@
+use crate::hair_simulation::pipeline::{
+    der::utils::add_to_matrix, utils::band_matrix::SymmetricBandMatrix,
+};
+
+use super::{ElasticEnergy, EnergyContext};
+extern crate nalgebra as na;
+
+pub const HEAD_CONTACT_OFFSET: f64 = 0.01;
+pub const HEAD_CONTACT_STIFFNESS: f64 = 20.0;
+
+// Per unit vertex mass, so that the penetration under a given acceleration
+// does not depend on the strand mass
+pub const COLLIDER_CONTACT_STIFFNESS: f64 = 1e5;
+
+// Penalty keeping vertices outside the head sphere. The stiffness scales with
+// the vertex speed, which is treated as a constant within the step.
+pub struct HeadContact;
+
+fn penetration(context: &EnergyContext, index: usize) -> Option<(f64, na::Vector3<f64>)> {
+    let head = context.head;
+    let offset = context.strand.v_position[index] - head.position;
+    let depth = offset.norm() - head.radius - HEAD_CONTACT_OFFSET;
+    if depth < 0.0 {
+        Some((depth, offset.normalize()))
+    } else {
+        None
+    }
+}
+
+impl ElasticEnergy for HeadContact {
+    fn name(&self) -> &'static str {
+        "head_contact"
+    }
+
+    fn energy(&self, context: &EnergyContext) -> f64 {
+        let strand = context.strand;
+        let mut energy = 0.0;
+        for i in 0..strand.v_num {
+            if let Some((depth, _)) = penetration(context, i) {
+                let stiffness = HEAD_CONTACT_STIFFNESS * strand.v_velocity[i].norm();
+                energy -= stiffness * depth.powi(3) / 3.0;
+            }
+        }
+        energy
+    }
+
+    fn gradient(&self, context: &EnergyContext, gradient: &mut na::DVector<f64>) {
+        let strand = context.strand;
+        for i in 0..strand.v_num {
+            if let Some((depth, direction)) = penetration(context, i) {
+                let stiffness = HEAD_CONTACT_STIFFNESS * strand.v_velocity[i].norm();
+                let force_head = direction * depth * depth * stiffness;
+                let row = context.state.vertex_dof(i);
+                gradient[row] -= force_head.x;
+                gradient[row + 1] -= force_head.y;
+                gradient[row + 2] -= force_head.z;
+            }
+        }
+    }
+
+    fn hessian(&self, context: &EnergyContext, hessian: &mut SymmetricBandMatrix) {
+        let strand = context.strand;
+        for i in 0..strand.v_num {
+            if let Some((depth, direction)) = penetration(context, i) {
+                // Stiffness along the contact normal
+                let stiffness = HEAD_CONTACT_STIFFNESS * strand.v_velocity[i].norm();
+                let h = -2.0 * stiffness * depth * direction * direction.transpose();
+                let dof = context.state.vertex_dof(i);
+                add_to_matrix(hessian, &h, (dof, dof));
+            }
+        }
+    }
+}
+
+// Quadratic penalty E = k m / 2 (d - thickness)^2 on vertices within the
+// thickness of a collider, with d its signed distance.
+pub struct ColliderContact;
+
+fn collider_penetrations<'a>(
+    context: &'a EnergyContext,
+    index: usize,
+) -> impl Iterator<Item = (f64, na::Vector3<f64>)> + 'a {
+    let position = context.strand.v_position[index];
+    context.colliders.iter().filter_map(move |collider| {
+        let (distance, gradient) = collider.distance(&position)?;
+        let depth = distance - collider.thickness;
+        (depth < 0.0).then_some((depth, gradient))
+    })
+}
+
+impl ElasticEnergy for ColliderContact {
+    fn name(&self) -> &'static str {
+        "collider_contact"
+    }
+
+    fn energy(&self, context: &EnergyContext) -> f64 {
+        let strand = context.strand;
+        let mut energy = 0.0;
+        for i in 0..strand.v_num {
+            let stiffness = COLLIDER_CONTACT_STIFFNESS * strand.v_mass[i];
+            for (depth, _) in collider_penetrations(context, i) {
+                energy += stiffness * depth.powi(2) / 2.0;
+            }
+        }
+        energy
+    }
+
+    fn gradient(&self, context: &EnergyContext, gradient: &mut na::DVector<f64>) {
+        let strand = context.strand;
+        for i in 0..strand.v_num {
+            let stiffness = COLLIDER_CONTACT_STIFFNESS * strand.v_mass[i];
+            let row = context.state.vertex_dof(i);
+            for (depth, normal) in collider_penetrations(context, i) {
+                let local = stiffness * depth * normal;
+                for k in 0..3 {
+                    gradient[row + k] += local[k];
+                }
+            }
+        }
+    }
+
+    fn hessian(&self, context: &EnergyContext, hessian: &mut SymmetricBandMatrix) {
+        let strand = context.strand;
+        for i in 0..strand.v_num {
+            let stiffness = COLLIDER_CONTACT_STIFFNESS * strand.v_mass[i];
+            let dof = context.state.vertex_dof(i);
+            for (_, normal) in collider_penetrations(context, i) {
+                // Gauss-Newton, the curvature of the field is dropped
+                let h = stiffness * normal * normal.transpose();
+                add_to_matrix(hessian, &h, (dof, dof));
+            }
+        }
+    }
+}
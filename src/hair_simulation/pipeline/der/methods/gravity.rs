@@ -0,0 +1,26 @@
+use super::{ElasticEnergy, EnergyContext};
+extern crate nalgebra as na;
+
+pub const GRAVITY: f64 = 9.8;
+
+pub struct Gravity;
+
+impl ElasticEnergy for Gravity {
+    fn name(&self) -> &'static str {
+        "gravity"
+    }
+
+    fn energy(&self, context: &EnergyContext) -> f64 {
+        let strand = context.strand;
+        (0..strand.v_num)
+            .map(|i| GRAVITY * strand.v_mass[i] * strand.v_position[i].y)
+            .sum()
+    }
+
+    fn gradient(&self, context: &EnergyContext, gradient: &mut na::DVector<f64>) {
+        let strand = context.strand;
+        for i in 0..strand.v_num {
+            gradient[context.state.vertex_dof(i) + 1] += GRAVITY * strand.v_mass[i];
+        }
+    }
+}
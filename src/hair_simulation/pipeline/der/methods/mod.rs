@@ -1,3 +1,88 @@
 pub mod bend;
+pub mod contact;
+pub mod gravity;
+pub mod original;
 pub mod stretch;
 pub mod twist;
+
+#[cfg(test)]
+mod tests;
+use crate::hair_simulation::{
+    collider::Collider,
+    config::{EnergyTerms, RodModel},
+    data::{HairStrand, Head},
+    pipeline::utils::band_matrix::SymmetricBandMatrix,
+};
+
+use self::{
+    bend::Bend,
+    contact::{ColliderContact, HeadContact},
+    gravity::Gravity,
+    original::{OriginalBend, OriginalGravity, OriginalStretch},
+    stretch::Stretch,
+    twist::Twist,
+};
+
+use super::state::StrandState;
+extern crate nalgebra as na;
+
+// Everything a term may read while evaluating one strand.
+pub struct EnergyContext<'a> {
+    pub strand: &'a HairStrand,
+    pub state: &'a StrandState,
+    pub head: &'a Head,
+    pub colliders: &'a [Collider],
+}
+
+// One potential of the rod. Terms accumulate into the strand system laid out
+// by `StrandState::vertex_dof` / `StrandState::twist_dof`.
+pub trait ElasticEnergy: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn energy(&self, context: &EnergyContext) -> f64;
+
+    // Adds dE/dq, the force is its negation
+    fn gradient(&self, context: &EnergyContext, gradient: &mut na::DVector<f64>);
+
+    // Adds (an approximation of) d2E/dq2
+    fn hessian(&self, _context: &EnergyContext, _hessian: &mut SymmetricBandMatrix) {}
+}
+
+pub fn registered_energies(terms: &EnergyTerms) -> Vec<Box<dyn ElasticEnergy>> {
+    let mut energies: Vec<Box<dyn ElasticEnergy>> = Vec::new();
+
+    let original = terms.rod_model == RodModel::Original;
+
+    if terms.stretch {
+        if original {
+            energies.push(Box::new(OriginalStretch));
+        } else {
+            energies.push(Box::new(Stretch));
+        }
+    }
+    if terms.bend {
+        if original {
+            energies.push(Box::new(OriginalBend));
+        } else {
+            energies.push(Box::new(Bend));
+        }
+    }
+    if terms.twist {
+        energies.push(Box::new(Twist));
+    }
+    if terms.gravity {
+        if original {
+            energies.push(Box::new(OriginalGravity));
+        } else {
+            energies.push(Box::new(Gravity));
+        }
+    }
+    if terms.head_contact {
+        energies.push(Box::new(HeadContact));
+    }
+    if terms.collider_contact {
+        energies.push(Box::new(ColliderContact));
+    }
+
+    energies
+}
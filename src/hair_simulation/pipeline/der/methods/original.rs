@@ -0,0 +1,293 @@
+use crate::hair_simulation::{
+    data::Frame,
+    pipeline::{
+        der::utils::{
+            add_to_matrix, calc_nabla_i_kappa_i, calc_nabla_i_kappa_i1, calc_nabla_i_kappa_i_1,
+        },
+        utils::band_matrix::SymmetricBandMatrix,
+    },
+};
+
+use super::{
+    bend::bend_factor, gravity::GRAVITY, stretch::stretch_factor, ElasticEnergy, EnergyContext,
+};
+extern crate nalgebra as na;
+
+// The stretch, bend and gravity forces of the do_der loop the terms were split
+// from, selected by `RodModel::Original`. They act on the free vertices only,
+// and bend differs from the gradient of its energy:
+//
+// - partial_kappa reads kappa1^i and kappa2^(i-1) from each other's slot
+// - the gradients of kappa_(i-1) and kappa_(i+1) at x_i use t_tilde of vertex i
+// - each curvature is weighted by the current length of its edge
+// - only the rows of the interior vertices are assembled
+pub struct OriginalStretch;
+pub struct OriginalBend;
+pub struct OriginalGravity;
+
+impl ElasticEnergy for OriginalStretch {
+    fn name(&self) -> &'static str {
+        "stretch"
+    }
+
+    fn energy(&self, context: &EnergyContext) -> f64 {
+        super::stretch::Stretch.energy(context)
+    }
+
+    fn gradient(&self, context: &EnergyContext, gradient: &mut na::DVector<f64>) {
+        let strand = context.strand;
+        let state = context.state;
+        let k_s = stretch_factor(context);
+
+        for i in 0..strand.l_num {
+            let f_si = k_s
+                * ((state.length_vec[i] / strand.l_rest_length[i] - 1.0)
+                    * state.reference_frame[i].t);
+
+            let from = state.vertex_dof(i);
+            let to = state.vertex_dof(i + 1);
+            for k in 0..3 {
+                if !strand.v_pinned[i] {
+                    gradient[from + k] -= f_si[k];
+                }
+                gradient[to + k] += f_si[k];
+            }
+        }
+    }
+
+    fn hessian(&self, context: &EnergyContext, hessian: &mut SymmetricBandMatrix) {
+        let strand = context.strand;
+        let state = context.state;
+        let k_s = stretch_factor(context);
+
+        for i in 0..strand.l_num {
+            let h_i = k_s
+                * (1.0 / strand.l_rest_length[i]
+                    * state.reference_frame[i].t
+                    * state.reference_frame[i].t.transpose());
+
+            let from = state.vertex_dof(i);
+            let to = state.vertex_dof(i + 1);
+            if !strand.v_pinned[i] {
+                add_to_matrix(hessian, &h_i, (from, from));
+                add_to_matrix(hessian, &-h_i, (from, to));
+                add_to_matrix(hessian, &-h_i, (to, from));
+            }
+            add_to_matrix(hessian, &h_i, (to, to));
+        }
+    }
+}
+
+// Gradient of kappa_(i-1), kappa_i and kappa_(i+1) at x_i, as the loop built them
+fn original_nabla_kappa(context: &EnergyContext) -> Vec<[na::Matrix4x3<f64>; 3]> {
+    let strand = context.strand;
+    let state = context.state;
+    let frames = &state.reference_frame;
+
+    let e_vec: Vec<na::Vector3<f64>> = (0..strand.l_num)
+        .map(|i| strand.v_position[i + 1] - strand.v_position[i])
+        .collect();
+    let material_frame: Vec<Frame> = frames
+        .iter()
+        .zip(strand.l_twist.iter())
+        .map(|(frame, &theta)| {
+            let (a1, a2) = (frame.n, frame.b);
+            Frame {
+                b: -a1 * f64::sin(theta) + a2 * f64::cos(theta),
+                n: a1 * f64::cos(theta) + a2 * f64::sin(theta),
+                t: frame.t,
+            }
+        })
+        .collect();
+    let kappa: Vec<na::Matrix4x1<f64>> = state
+        .kappa
+        .iter()
+        .map(|kappa| na::Matrix4x1::new(kappa[0], kappa[2], kappa[1], kappa[3]))
+        .collect();
+
+    let mut nabla_kappa = vec![[na::Matrix4x3::<f64>::zeros(); 3]; strand.v_num];
+    for i in 1..(strand.v_num - 1) {
+        let t_tilde = (frames[i - 1].t + frames[i].t) / (1.0 + frames[i - 1].t.dot(&frames[i].t));
+
+        if i - 1 > 0 {
+            nabla_kappa[i][0] =
+                calc_nabla_i_kappa_i_1(&e_vec, &t_tilde, &kappa, frames, &material_frame, i - 1);
+        }
+        nabla_kappa[i][1] =
+            calc_nabla_i_kappa_i(&e_vec, &t_tilde, &kappa, frames, &material_frame, i);
+        if i + 1 < strand.v_num - 1 {
+            nabla_kappa[i][2] =
+                calc_nabla_i_kappa_i1(&e_vec, &t_tilde, &kappa, frames, &material_frame, i + 1);
+        }
+    }
+    nabla_kappa
+}
+
+fn rest_kappa(context: &EnergyContext, index: usize) -> na::Matrix4x1<f64> {
+    context
+        .strand
+        .l_rest_kappa
+        .get(index)
+        .cloned()
+        .unwrap_or_else(na::Matrix4x1::zeros)
+}
+
+// Derivatives of kappa_i by theta_(i-1) and theta_i
+fn nabla_theta(kappa: &na::Matrix4x1<f64>) -> [na::Matrix4x1<f64>; 2] {
+    [
+        na::Matrix4x1::new(kappa[2], 0.0, -kappa[0], 0.0),
+        na::Matrix4x1::new(0.0, kappa[3], 0.0, -kappa[1]),
+    ]
+}
+
+impl ElasticEnergy for OriginalBend {
+    fn name(&self) -> &'static str {
+        "bend"
+    }
+
+    fn energy(&self, context: &EnergyContext) -> f64 {
+        let strand = context.strand;
+        let state = context.state;
+        let h_factor = bend_factor(strand);
+
+        let mut energy = 0.0;
+        for i in 1..(strand.v_num - 1) {
+            let kappa_diff = state.kappa[i] - rest_kappa(context, i);
+            energy += h_factor / (2.0 * state.length_vec[i]) * kappa_diff.norm_squared();
+        }
+        energy
+    }
+
+    fn gradient(&self, context: &EnergyContext, gradient: &mut na::DVector<f64>) {
+        let strand = context.strand;
+        let state = context.state;
+        let kappa = &state.kappa;
+        let length_vec = &state.length_vec;
+        let nabla_kappa = original_nabla_kappa(context);
+        let h_factor = bend_factor(strand);
+
+        for i in 1..(strand.v_num - 1) {
+            let mut kappa_part = na::Matrix3x1::<f64>::zeros();
+            if i - 1 > 0 {
+                kappa_part += nabla_kappa[i][0].transpose()
+                    * (kappa[i - 1] - rest_kappa(context, i - 1))
+                    / length_vec[i - 1];
+            }
+            kappa_part +=
+                nabla_kappa[i][1].transpose() * (kappa[i] - rest_kappa(context, i)) / length_vec[i];
+            if i + 1 < strand.v_num - 1 {
+                kappa_part += nabla_kappa[i][2].transpose()
+                    * (kappa[i + 1] - rest_kappa(context, i + 1))
+                    / length_vec[i + 1];
+            }
+
+            let f_sum = -h_factor * kappa_part;
+            let row = state.vertex_dof(i);
+            for k in 0..3 {
+                gradient[row + k] -= f_sum[k];
+            }
+
+            // Torque on the two edges of the vertex
+            let kappa_diff = kappa[i] - rest_kappa(context, i);
+            for (a, nabla_theta_a) in nabla_theta(&kappa[i]).iter().enumerate() {
+                gradient[state.twist_dof(i - 1 + a)] +=
+                    h_factor * nabla_theta_a.dot(&kappa_diff) / length_vec[i];
+            }
+        }
+    }
+
+    fn hessian(&self, context: &EnergyContext, hessian: &mut SymmetricBandMatrix) {
+        let strand = context.strand;
+        let state = context.state;
+        let length_vec = &state.length_vec;
+        let nabla_kappa = original_nabla_kappa(context);
+        let h_factor = bend_factor(strand);
+        let dof = |i: usize| state.vertex_dof(i);
+
+        for i in 1..(strand.v_num - 1) {
+            let nabla_theta = nabla_theta(&state.kappa[i]);
+            let nabla_x = [
+                nabla_kappa[i - 1][2],
+                nabla_kappa[i][1],
+                nabla_kappa[i + 1][0],
+            ];
+
+            // Lower triangle of the twist and cross blocks
+            for (a, nabla_theta_a) in nabla_theta.iter().enumerate() {
+                let theta = state.twist_dof(i - 1 + a);
+                for (b, nabla_theta_b) in nabla_theta.iter().enumerate().take(a + 1) {
+                    hessian.add(
+                        theta,
+                        state.twist_dof(i - 1 + b),
+                        h_factor * nabla_theta_a.dot(nabla_theta_b) / length_vec[i],
+                    );
+                }
+                for (b, nabla_x_b) in nabla_x.iter().enumerate() {
+                    let h = h_factor * nabla_x_b.transpose() * nabla_theta_a / length_vec[i];
+                    for k in 0..3 {
+                        let x = dof(i - 1 + b) + k;
+                        hessian.add(x.max(theta), x.min(theta), h[k]);
+                    }
+                }
+            }
+
+            let mut h_i_i = nabla_kappa[i][1].transpose() * nabla_kappa[i][1] / length_vec[i];
+
+            if i >= 2 {
+                let h_i_i_2 = h_factor
+                    * (nabla_kappa[i][0].transpose() * nabla_kappa[i - 2][2] / length_vec[i - 1]);
+                add_to_matrix(hessian, &h_i_i_2, (dof(i), dof(i - 2)));
+            }
+
+            if i >= 1 {
+                let mut h_i_i_1 = nabla_kappa[i][0].transpose() * nabla_kappa[i - 1][1]
+                    / length_vec[i - 1]
+                    + nabla_kappa[i][1].transpose() * nabla_kappa[i - 1][2] / length_vec[i];
+                h_i_i_1 *= h_factor;
+                add_to_matrix(hessian, &h_i_i_1, (dof(i), dof(i - 1)));
+
+                h_i_i += nabla_kappa[i][0].transpose() * nabla_kappa[i][0] / length_vec[i - 1];
+            }
+
+            if i + 1 < strand.l_num {
+                let mut h_i_i1 = nabla_kappa[i][1].transpose() * nabla_kappa[i + 1][0]
+                    / length_vec[i]
+                    + nabla_kappa[i][2].transpose() * nabla_kappa[i + 1][1] / length_vec[i + 1];
+                h_i_i1 *= h_factor;
+                add_to_matrix(hessian, &h_i_i1, (dof(i), dof(i + 1)));
+
+                h_i_i += nabla_kappa[i][2].transpose() * nabla_kappa[i][2] / length_vec[i + 1];
+            }
+
+            if i + 2 < strand.l_num {
+                let h_i_i2 = h_factor
+                    * (nabla_kappa[i][2].transpose() * nabla_kappa[i + 2][0] / length_vec[i + 1]);
+                add_to_matrix(hessian, &h_i_i2, (dof(i), dof(i + 2)));
+            }
+
+            h_i_i *= h_factor;
+            add_to_matrix(hessian, &h_i_i, (dof(i), dof(i)));
+        }
+    }
+}
+
+impl ElasticEnergy for OriginalGravity {
+    fn name(&self) -> &'static str {
+        "gravity"
+    }
+
+    fn energy(&self, context: &EnergyContext) -> f64 {
+        let strand = context.strand;
+        (0..strand.v_num)
+            .filter(|&i| !strand.v_pinned[i])
+            .map(|i| GRAVITY * strand.v_mass[i] * strand.v_position[i].y)
+            .sum()
+    }
+
+    fn gradient(&self, context: &EnergyContext, gradient: &mut na::DVector<f64>) {
+        let strand = context.strand;
+        for i in (0..strand.v_num).filter(|&i| !strand.v_pinned[i]) {
+            gradient[context.state.vertex_dof(i) + 1] += GRAVITY * strand.v_mass[i];
+        }
+    }
+}
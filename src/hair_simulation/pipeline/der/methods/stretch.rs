@@ -0,0 +1,74 @@
+use std::f64::consts::PI;
+
+use crate::hair_simulation::pipeline::{
+    der::utils::add_to_matrix, utils::band_matrix::SymmetricBandMatrix,
+};
+
+use super::{ElasticEnergy, EnergyContext};
+extern crate nalgebra as na;
+
+pub struct Stretch;
+
+pub fn stretch_factor(context: &EnergyContext) -> f64 {
+    PI * context.strand.radius.powi(2) * context.strand.youngs
+}
+
+impl ElasticEnergy for Stretch {
+    fn name(&self) -> &'static str {
+        "stretch"
+    }
+
+    fn energy(&self, context: &EnergyContext) -> f64 {
+        let strand = context.strand;
+        let state = context.state;
+        let k_s = stretch_factor(context);
+
+        let mut energy = 0.0;
+        for i in 0..strand.l_num {
+            let strain = state.length_vec[i] / strand.l_rest_length[i] - 1.0;
+            energy += k_s / 2.0 * strain.powi(2) * strand.l_rest_length[i];
+        }
+        energy
+    }
+
+    fn gradient(&self, context: &EnergyContext, gradient: &mut na::DVector<f64>) {
+        let strand = context.strand;
+        let state = context.state;
+        let k_s = stretch_factor(context);
+
+        for i in 0..strand.l_num {
+            let f_si = k_s
+                * ((state.length_vec[i] / strand.l_rest_length[i] - 1.0)
+                    * state.reference_frame[i].t);
+
+            // Pinned vertices get their share too. Their dofs are constrained in
+            // the solve, so they do not move.
+            let from = state.vertex_dof(i);
+            let to = state.vertex_dof(i + 1);
+            for k in 0..3 {
+                gradient[from + k] -= f_si[k];
+                gradient[to + k] += f_si[k];
+            }
+        }
+    }
+
+    fn hessian(&self, context: &EnergyContext, hessian: &mut SymmetricBandMatrix) {
+        let strand = context.strand;
+        let state = context.state;
+        let k_s = stretch_factor(context);
+
+        for i in 0..strand.l_num {
+            let h_i = k_s
+                * (1.0 / strand.l_rest_length[i]
+                    * state.reference_frame[i].t
+                    * state.reference_frame[i].t.transpose());
+
+            let from = state.vertex_dof(i);
+            let to = state.vertex_dof(i + 1);
+            add_to_matrix(hessian, &h_i, (from, from));
+            add_to_matrix(hessian, &-h_i, (from, to));
+            add_to_matrix(hessian, &-h_i, (to, from));
+            add_to_matrix(hessian, &h_i, (to, to));
+        }
+    }
+}
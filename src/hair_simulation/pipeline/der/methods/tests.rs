@@ -0,0 +1,960 @@
+use std::f64::consts::PI;
+
+use crate::hair_simulation::{
+    collider::{shape::ColliderShape, Collider},
+    config::{EnergyTerms, RodModel},
+    data::{
+        generate_hair_strand, generate_straight_hair_strand, Frame, HairStrand, Head, StrandParams,
+        StrandShape,
+    },
+    pipeline::{
+        der::{
+            state::StrandState,
+            utils::{
+                calc_nabla_i_kappa_i, calc_nabla_i_kappa_i1, calc_nabla_i_kappa_i_1, partial_kappa,
+                transport_frame,
+            },
+            MAX_T_DOT,
+        },
+        utils::band_matrix::SymmetricBandMatrix,
+    },
+};
+
+use super::{
+    bend::{bend_factor, Bend},
+    contact::{
+        ColliderContact, HeadContact, COLLIDER_CONTACT_STIFFNESS, HEAD_CONTACT_OFFSET,
+        HEAD_CONTACT_STIFFNESS,
+    },
+    gravity::Gravity,
+    registered_energies,
+    stretch::{stretch_factor, Stretch},
+    twist::{calc_reference_twist, calc_twist, twist_factor, Twist},
+    ElasticEnergy, EnergyContext,
+};
+extern crate nalgebra as na;
+
+const EPSILON: f64 = 1e-6;
+const SEEDS: [u64; 4] = [1, 7, 42, 1234];
+
+const GRADIENT_TOLERANCE: f64 = 1e-5;
+const HESSIAN_TOLERANCE: f64 = 1e-4;
+
+// Linear congruential generator, enough for reproducible perturbations
+struct Lcg(u64);
+
+impl Lcg {
+    // Uniform in [-1, 1)
+    fn next(&mut self) -> f64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+
+    fn vector(&mut self) -> na::Vector3<f64> {
+        na::Vector3::new(self.next(), self.next(), self.next())
+    }
+}
+
+fn random_strand(seed: u64) -> HairStrand {
+    let mut rng = Lcg(seed);
+    let mut strand = generate_straight_hair_strand(
+        1e-6,
+        8,
+        na::Vector3::zeros(),
+        na::Vector3::new(0.2, -1.0, 0.1),
+        1e9,
+        1e9,
+        0.01,
+        0,
+    );
+
+    for position in strand.v_position.iter_mut() {
+        *position += 0.03 * rng.vector();
+    }
+    for velocity in strand.v_velocity.iter_mut() {
+        *velocity = rng.vector();
+    }
+    for twist in strand.l_twist.iter_mut() {
+        *twist = 0.3 * rng.next();
+    }
+    strand.l_rest_kappa = (0..(strand.v_num - 1))
+        .map(|_| 0.05 * na::Matrix4x1::new(rng.next(), rng.next(), rng.next(), rng.next()))
+        .collect();
+
+    // The analytic derivatives assume frames transported to the evaluation point
+    StrandState::new(&strand).commit(&mut strand);
+    strand
+}
+
+// Same geometry, with rest lengths, curvatures and twists taken from the
+// current state so that the Gauss-Newton Hessians are exact.
+fn rest_strand(seed: u64) -> HairStrand {
+    let mut strand = random_strand(seed);
+    strand.capture_rest_shape();
+    strand
+}
+
+#[derive(Default)]
+struct Scene {
+    head: Head,
+    colliders: Vec<Collider>,
+}
+
+// A head sphere and colliders of every shape over the strand but the
+// ellipsoid and the mesh, whose normals are not the gradients of their
+// distances
+fn scene_around(strand: &HairStrand) -> Scene {
+    let middle = strand.v_position[strand.v_num / 2];
+    let rotation = na::UnitQuaternion::from_euler_angles(0.4, -0.7, 1.1);
+    let primitive = |shape: ColliderShape, offset: na::Vector3<f64>| {
+        Collider::new(shape).with_transform(middle + offset, rotation)
+    };
+
+    Scene {
+        head: Head {
+            position: middle + na::Vector3::new(0.05, 0.0, 0.0),
+            radius: 0.1,
+            ..Default::default()
+        },
+        colliders: vec![
+            primitive(
+                ColliderShape::Capsule {
+                    radius: 0.05,
+                    half_length: 0.1,
+                },
+                na::Vector3::new(0.0, 0.2, 0.03),
+            ),
+            primitive(
+                ColliderShape::Box {
+                    half_extents: na::Vector3::new(0.08, 0.05, 0.1),
+                },
+                na::Vector3::new(0.03, -0.25, 0.0),
+            ),
+            primitive(
+                ColliderShape::Sphere { radius: 0.08 },
+                na::Vector3::new(0.02, 0.35, -0.03),
+            ),
+            primitive(ColliderShape::Plane, na::Vector3::new(0.0, -0.3, 0.0)),
+        ],
+    }
+}
+
+// Moves one dof of the strand, following the `StrandState` layout
+fn perturbed(strand: &HairStrand, dof: usize, delta: f64) -> HairStrand {
+    let mut strand = strand.clone();
+    if dof % 4 == 3 {
+        strand.l_twist[dof / 4] += delta;
+    } else {
+        strand.v_position[dof / 4][dof % 4] += delta;
+    }
+    strand
+}
+
+fn energy_of(term: &dyn ElasticEnergy, strand: &HairStrand, scene: &Scene) -> f64 {
+    let state = StrandState::new(strand);
+    term.energy(&EnergyContext {
+        strand,
+        state: &state,
+        head: &scene.head,
+        colliders: &scene.colliders,
+    })
+}
+
+fn gradient_of(term: &dyn ElasticEnergy, strand: &HairStrand, scene: &Scene) -> na::DVector<f64> {
+    let state = StrandState::new(strand);
+    let mut gradient = na::DVector::zeros(state.dof_num());
+    term.gradient(
+        &EnergyContext {
+            strand,
+            state: &state,
+            head: &scene.head,
+            colliders: &scene.colliders,
+        },
+        &mut gradient,
+    );
+    gradient
+}
+
+fn hessian_of(term: &dyn ElasticEnergy, strand: &HairStrand, scene: &Scene) -> na::DMatrix<f64> {
+    let state = StrandState::new(strand);
+    let mut hessian = SymmetricBandMatrix::zeros(state.dof_num(), state.bandwidth());
+    term.hessian(
+        &EnergyContext {
+            strand,
+            state: &state,
+            head: &scene.head,
+            colliders: &scene.colliders,
+        },
+        &mut hessian,
+    );
+    hessian.to_dense()
+}
+
+fn relative_error(analytic: &na::DMatrix<f64>, numeric: &na::DMatrix<f64>) -> f64 {
+    let scale = numeric.amax();
+    if scale == 0.0 {
+        return analytic.amax();
+    }
+    (analytic - numeric).amax() / scale
+}
+
+fn all_terms() -> Vec<Box<dyn ElasticEnergy>> {
+    vec![
+        Box::new(Stretch),
+        Box::new(Bend),
+        Box::new(Twist),
+        Box::new(Gravity),
+        Box::new(HeadContact),
+        Box::new(ColliderContact),
+    ]
+}
+
+#[test]
+fn kappa_gradient_matches_finite_differences() {
+    let mut worst: f64 = 0.0;
+    for seed in SEEDS {
+        let strand = random_strand(seed);
+        let state = StrandState::new(&strand);
+
+        for i in 1..(strand.v_num - 1) {
+            for (block, vertex) in [i - 1, i, i + 1].into_iter().enumerate() {
+                let mut numeric = na::DMatrix::<f64>::zeros(4, 3);
+                for k in 0..3 {
+                    let dof = state.vertex_dof(vertex) + k;
+                    let plus = StrandState::new(&perturbed(&strand, dof, EPSILON)).kappa[i];
+                    let minus = StrandState::new(&perturbed(&strand, dof, -EPSILON)).kappa[i];
+                    numeric.set_column(k, &((plus - minus) / (2.0 * EPSILON)));
+                }
+                let analytic =
+                    na::DMatrix::from_column_slice(4, 3, state.nabla_kappa[i][block].as_slice());
+                worst = worst.max(relative_error(&analytic, &numeric));
+            }
+        }
+    }
+
+    println!("kappa: worst relative error {:e}", worst);
+    assert!(worst < GRADIENT_TOLERANCE);
+}
+
+#[test]
+fn energy_gradients_match_finite_differences() {
+    for term in all_terms() {
+        let mut worst: f64 = 0.0;
+        for seed in SEEDS {
+            let strand = random_strand(seed);
+            let scene = scene_around(&strand);
+            let analytic = gradient_of(term.as_ref(), &strand, &scene);
+
+            let numeric = na::DVector::from_fn(analytic.len(), |dof, _| {
+                let plus = energy_of(term.as_ref(), &perturbed(&strand, dof, EPSILON), &scene);
+                let minus = energy_of(term.as_ref(), &perturbed(&strand, dof, -EPSILON), &scene);
+                (plus - minus) / (2.0 * EPSILON)
+            });
+
+            let analytic = na::DMatrix::from_column_slice(analytic.len(), 1, analytic.as_slice());
+            let numeric = na::DMatrix::from_column_slice(numeric.len(), 1, numeric.as_slice());
+            worst = worst.max(relative_error(&analytic, &numeric));
+        }
+
+        println!("{} gradient: worst relative error {:e}", term.name(), worst);
+        assert!(worst < GRADIENT_TOLERANCE, "{} gradient", term.name());
+    }
+}
+
+#[test]
+fn energy_hessians_match_finite_differences() {
+    // The contact Hessians keep only the normal stiffness, they are not exact anywhere
+    for term in all_terms()
+        .into_iter()
+        .filter(|term| !term.name().ends_with("contact"))
+    {
+        let mut worst: f64 = 0.0;
+        for seed in SEEDS {
+            let strand = rest_strand(seed);
+            let scene = scene_around(&strand);
+            let analytic = hessian_of(term.as_ref(), &strand, &scene);
+
+            let mut numeric = na::DMatrix::<f64>::zeros(analytic.nrows(), analytic.ncols());
+            for dof in 0..analytic.ncols() {
+                let plus = gradient_of(term.as_ref(), &perturbed(&strand, dof, EPSILON), &scene);
+                let minus = gradient_of(term.as_ref(), &perturbed(&strand, dof, -EPSILON), &scene);
+                numeric.set_column(dof, &((plus - minus) / (2.0 * EPSILON)));
+            }
+
+            worst = worst.max(relative_error(&analytic, &numeric));
+        }
+
+        println!("{} hessian: worst relative error {:e}", term.name(), worst);
+        assert!(worst < HESSIAN_TOLERANCE, "{} hessian", term.name());
+    }
+}
+
+// Each term is a sum of outer functions of inner maps, E = sum_k phi_k(r_k).
+// Returns the r_k with the curvatures phi_k'' at them. Contacts list every
+// vertex, with a zero curvature when it does not penetrate, so that the maps
+// keep their length under perturbation.
+fn inner_maps(term: &str, strand: &HairStrand, scene: &Scene) -> (Vec<f64>, Vec<f64>) {
+    let state = StrandState::new(strand);
+    let context = EnergyContext {
+        strand,
+        state: &state,
+        head: &scene.head,
+        colliders: &scene.colliders,
+    };
+    let interior = 1..(strand.v_num - 1);
+    let mut maps = Vec::new();
+    let mut curvatures = Vec::new();
+
+    match term {
+        "stretch" => {
+            for i in 0..strand.l_num {
+                maps.push(state.length_vec[i]);
+                curvatures.push(stretch_factor(&context) / strand.l_rest_length[i]);
+            }
+        }
+        "bend" => {
+            for i in interior {
+                for c in 0..4 {
+                    maps.push(state.kappa[i][c]);
+                    curvatures.push(bend_factor(strand) / strand.get_voronoi_length(i));
+                }
+            }
+        }
+        "twist" => {
+            for i in interior {
+                maps.push(calc_twist(strand, &state, i));
+                curvatures.push(twist_factor(strand) / strand.get_voronoi_length(i));
+            }
+        }
+        "head_contact" => {
+            for i in 0..strand.v_num {
+                let depth = (strand.v_position[i] - scene.head.position).norm()
+                    - scene.head.radius
+                    - HEAD_CONTACT_OFFSET;
+                let stiffness = HEAD_CONTACT_STIFFNESS * strand.v_velocity[i].norm();
+                maps.push(depth);
+                curvatures.push(if depth < 0.0 {
+                    -2.0 * stiffness * depth
+                } else {
+                    0.0
+                });
+            }
+        }
+        "collider_contact" => {
+            for i in 0..strand.v_num {
+                for collider in &scene.colliders {
+                    let depth = collider
+                        .distance(&strand.v_position[i])
+                        .map_or(0.0, |(distance, _)| distance - collider.thickness);
+                    maps.push(depth);
+                    curvatures.push(if depth < 0.0 {
+                        COLLIDER_CONTACT_STIFFNESS * strand.v_mass[i]
+                    } else {
+                        0.0
+                    });
+                }
+            }
+        }
+        _ => {}
+    }
+    (maps, curvatures)
+}
+
+#[test]
+fn energy_hessians_match_the_gauss_newton_model() {
+    // Away from rest the Hessians are Gauss-Newton: the inner maps are
+    // linearized and sum_k phi_k'' grad r_k grad r_k^T is assembled, with the
+    // gradients of the maps taken by finite differences here
+    for term in all_terms() {
+        let mut worst: f64 = 0.0;
+        let mut largest: f64 = 0.0;
+        for seed in SEEDS {
+            let strand = random_strand(seed);
+            let scene = scene_around(&strand);
+            let analytic = hessian_of(term.as_ref(), &strand, &scene);
+
+            let (maps, curvatures) = inner_maps(term.name(), &strand, &scene);
+            let mut jacobian = na::DMatrix::<f64>::zeros(maps.len(), analytic.ncols());
+            for dof in 0..analytic.ncols() {
+                let (plus, _) = inner_maps(term.name(), &perturbed(&strand, dof, EPSILON), &scene);
+                let (minus, _) =
+                    inner_maps(term.name(), &perturbed(&strand, dof, -EPSILON), &scene);
+                for k in 0..maps.len() {
+                    jacobian[(k, dof)] = (plus[k] - minus[k]) / (2.0 * EPSILON);
+                }
+            }
+            let numeric = jacobian.transpose()
+                * na::DMatrix::from_diagonal(&na::DVector::from_vec(curvatures))
+                * jacobian;
+
+            worst = worst.max(relative_error(&analytic, &numeric));
+            largest = largest.max(numeric.amax());
+        }
+
+        // Every term but gravity has curvature, contacts included
+        assert!(
+            term.name() == "gravity" || largest > 0.0,
+            "{} inactive",
+            term.name()
+        );
+        println!(
+            "{} gauss-newton hessian: worst relative error {:e}",
+            term.name(),
+            worst
+        );
+        assert!(worst < HESSIAN_TOLERANCE, "{} hessian", term.name());
+    }
+}
+
+// Frame along x, left-handed like the reference frames (n x b = -t), turned
+// by `angle` about its tangent
+fn turned_frame(angle: f64) -> Frame {
+    let (n, b) = (na::Vector3::y(), -na::Vector3::z());
+    Frame {
+        n: n * angle.cos() + b * angle.sin(),
+        b: -n * angle.sin() + b * angle.cos(),
+        t: na::Vector3::x(),
+    }
+}
+
+#[test]
+fn reference_twist_measures_the_turn_between_frames() {
+    // Straight edges, the frame of the second turned against the first
+    for angle in [0.0, 0.4, -1.2, 3.0] {
+        let frames = [turned_frame(0.0), turned_frame(angle)];
+        let twist = calc_reference_twist(&frames, &[0.0, 0.0], 1);
+        assert!((twist - angle).abs() < 1e-12, "{} {}", angle, twist);
+    }
+
+    // Unwrapped against the last value across the half turn
+    let frames = [turned_frame(0.0), turned_frame(-3.0)];
+    let twist = calc_reference_twist(&frames, &[0.0, 3.0], 1);
+    assert!((twist - (2.0 * PI - 3.0)).abs() < 1e-12, "{}", twist);
+
+    // A frame parallel transported around a bend has no twist
+    let bent = transport_frame(&turned_frame(0.7), na::Vector3::new(0.6, 0.8, 0.0));
+    let frames = [turned_frame(0.7), bent];
+    let twist = calc_reference_twist(&frames, &[0.0, 0.0], 1);
+    assert!(twist.abs() < 1e-12, "{}", twist);
+}
+
+#[test]
+fn generated_shapes_are_at_rest() {
+    let shapes = [
+        StrandShape::Straight,
+        StrandShape::Curly {
+            radius: 0.02,
+            pitch: 0.05,
+            phase: 0.7,
+        },
+        StrandShape::Wavy {
+            amplitude: 0.03,
+            wavelength: 0.15,
+            phase: 0.3,
+        },
+    ];
+    let elastic: [Box<dyn ElasticEnergy>; 3] = [Box::new(Stretch), Box::new(Bend), Box::new(Twist)];
+
+    for shape in shapes {
+        let params = StrandParams {
+            shape,
+            seg_num: 40,
+            youngs: 1e9,
+            shear: 1e9,
+            radius: 0.01,
+            last_pin: 0,
+        };
+        let strand = generate_hair_strand(
+            &params,
+            1e-6,
+            na::Vector3::zeros(),
+            na::Vector3::new(0.2, -1.0, 0.1) * 0.5,
+        );
+        let scene = Scene::default();
+
+        for term in elastic.iter() {
+            let energy = energy_of(term.as_ref(), &strand, &scene);
+            let gradient = gradient_of(term.as_ref(), &strand, &scene);
+            println!(
+                "{:?} {}: energy {:e}, gradient {:e}",
+                shape,
+                term.name(),
+                energy,
+                gradient.amax()
+            );
+            assert!(energy < 1e-20, "{:?} {} energy", shape, term.name());
+            assert!(
+                gradient.amax() < 1e-9,
+                "{:?} {} gradient",
+                shape,
+                term.name()
+            );
+        }
+    }
+}
+// partial_kappa as do_der called it, before the kappa slots were fixed
+#[allow(clippy::too_many_arguments)]
+fn original_partial_kappa(
+    e_vec: &[na::Vector3<f64>],
+    t_tilde: &na::Vector3<f64>,
+    kappa: &[na::Matrix4x1<f64>],
+    reference_frame: &[Frame],
+    material_frame: &[Frame],
+    index: usize,
+    kappa_up_index_b: bool,
+    kappa_sub_index_b: bool,
+    e_index_b: bool,
+) -> na::Vector3<f64> {
+    let (kappa_up_index, mut kappa_index) = if kappa_up_index_b {
+        (index - 1, 0)
+    } else {
+        (index, 2)
+    };
+    let mut latter_sign = 1.0;
+
+    let m = if kappa_sub_index_b {
+        material_frame[kappa_up_index].b
+    } else {
+        kappa_index += 1;
+        latter_sign *= -1.0;
+        material_frame[kappa_up_index].n
+    };
+
+    let (e_index, t_index) = if e_index_b {
+        (index - 1, index)
+    } else {
+        latter_sign *= -1.0;
+        (index, index - 1)
+    };
+
+    let t_dot = reference_frame[index - 1]
+        .t
+        .dot(&reference_frame[index].t)
+        .clamp(-MAX_T_DOT, MAX_T_DOT);
+
+    let e = e_vec[e_index];
+    let kappa_part = -kappa[index][kappa_index] * t_tilde;
+    let latter_part = (2.0 * reference_frame[t_index].t.cross(&m)) / (1.0 + t_dot);
+
+    1.0 / e.norm() * (kappa_part + latter_sign * latter_part)
+}
+
+// The original terms build their kappa gradients from partial_kappa with the
+// two middle slots of kappa swapped, which is how do_der read them
+#[test]
+fn swapped_kappa_reproduces_the_original_partial_kappa() {
+    for seed in SEEDS {
+        let strand = random_strand(seed);
+        let state = StrandState::new(&strand);
+        let e_vec: Vec<na::Vector3<f64>> = (0..strand.l_num)
+            .map(|i| strand.v_position[i + 1] - strand.v_position[i])
+            .collect();
+        let material_frame = material_frame(&strand, &state.reference_frame);
+        let swapped: Vec<na::Matrix4x1<f64>> = state
+            .kappa
+            .iter()
+            .map(|kappa| na::Matrix4x1::new(kappa[0], kappa[2], kappa[1], kappa[3]))
+            .collect();
+
+        for i in 1..(strand.v_num - 1) {
+            let t_tilde = state.reference_frame[i].t;
+            for flags in 0..8 {
+                let (up, sub, e) = (flags & 1 != 0, flags & 2 != 0, flags & 4 != 0);
+                let original = original_partial_kappa(
+                    &e_vec,
+                    &t_tilde,
+                    &state.kappa,
+                    &state.reference_frame,
+                    &material_frame,
+                    i,
+                    up,
+                    sub,
+                    e,
+                );
+                let fixed = partial_kappa(
+                    &e_vec,
+                    &t_tilde,
+                    &swapped,
+                    &state.reference_frame,
+                    &material_frame,
+                    i,
+                    up,
+                    sub,
+                    e,
+                );
+                assert_eq!(original, fixed, "vertex {} flags {}", i, flags);
+            }
+        }
+    }
+}
+
+fn material_frame(strand: &HairStrand, reference_frame: &[Frame]) -> Vec<Frame> {
+    let mut material_frame = Vec::new();
+    for (frame, &theta) in reference_frame.iter().zip(strand.l_twist.iter()) {
+        let a1 = frame.n;
+        let a2 = frame.b;
+
+        let m1 = a1 * f64::cos(theta) + a2 * f64::sin(theta);
+        let m2 = -a1 * f64::sin(theta) + a2 * f64::cos(theta);
+
+        material_frame.push(Frame {
+            b: m2,
+            n: m1,
+            t: na::Vector3::zeros(),
+        });
+    }
+    material_frame
+}
+
+fn add_block<const R: usize, const C: usize>(
+    matrix: &mut na::DMatrix<f64>,
+    value: &na::SMatrix<f64, R, C>,
+    start: (usize, usize),
+) {
+    for r in 0..R {
+        for c in 0..C {
+            matrix[(start.0 + r, start.1 + c)] += value[(r, c)];
+        }
+    }
+}
+
+// Force and Hessian of stretch, bend, twist and gravity as the do_der loop
+// assembled them before the split, in its layout: x_0 .. x_(n-1), then
+// theta_0 .. theta_(n-2). The head force is left out.
+fn original_do_der(strand: &HairStrand) -> (na::DVector<f64>, na::DMatrix<f64>) {
+    let mut strand = strand.clone();
+    let strand = &mut strand;
+    let mut force = na::DVector::<f64>::zeros(4 * strand.v_num - 1);
+    let mut hessian = na::DMatrix::<f64>::zeros(4 * strand.v_num - 1, 4 * strand.v_num - 1);
+
+    let mut e_vec = Vec::new();
+    let mut length_vec = Vec::new();
+    // Update reference frame
+    for i in 0..strand.l_num {
+        let e = strand.v_position[i + 1] - strand.v_position[i];
+        let t = e.normalize();
+        length_vec.push(e.norm());
+
+        // Time-parallel transport from the previous step
+        strand.reference_frame[i] = transport_frame(&strand.reference_frame[i], t);
+        e_vec.push(e);
+    }
+
+    for i in 1..(strand.v_num - 1) {
+        strand.v_reference_twist[i] =
+            calc_reference_twist(&strand.reference_frame, &strand.v_reference_twist, i);
+    }
+
+    let material_frame = material_frame(strand, &strand.reference_frame);
+
+    // Calculate kappa_b
+    let mut kappa_b = Vec::new();
+    for i in 0..(strand.v_num - 1) {
+        if i == 0 {
+            kappa_b.push(na::Vector3::zeros());
+        } else {
+            let t_dot = strand.reference_frame[i - 1]
+                .t
+                .dot(&strand.reference_frame[i].t)
+                .clamp(-MAX_T_DOT, MAX_T_DOT);
+            let temp_kappa_b = (2.0
+                * strand.reference_frame[i - 1]
+                    .t
+                    .cross(&strand.reference_frame[i].t))
+                / (1.0 + t_dot);
+            if temp_kappa_b.norm() > 1.0 {
+                kappa_b.push(temp_kappa_b.normalize() * 1.0);
+            } else {
+                kappa_b.push(temp_kappa_b);
+            }
+        }
+    }
+
+    // Calculate kappa
+    let mut kappa = Vec::new();
+    for i in 0..(strand.v_num - 1) {
+        if i == 0 {
+            kappa.push(na::Matrix4x1::new(0.0, 0.0, 0.0, 0.0));
+        } else {
+            let kappa_i = na::Matrix4x1::new(
+                material_frame[i - 1].b.dot(&kappa_b[i]),
+                material_frame[i].b.dot(&kappa_b[i]),
+                -material_frame[i - 1].n.dot(&kappa_b[i]),
+                -material_frame[i].n.dot(&kappa_b[i]),
+            );
+            kappa.push(kappa_i)
+        }
+    }
+    let initial_kappa = &strand.l_rest_kappa;
+
+    // Apply stretch
+    for i in 0..strand.l_num {
+        let f_si = PI
+            * strand.radius.powi(2)
+            * strand.youngs
+            * ((length_vec[i] / strand.l_rest_length[i] - 1.0) * strand.reference_frame[i].t);
+
+        if !strand.v_pinned[i] {
+            force[i * 3] += f_si[0];
+            force[i * 3 + 1] += f_si[1];
+            force[i * 3 + 2] += f_si[2];
+        }
+
+        force[(i + 1) * 3] -= f_si[0];
+        force[(i + 1) * 3 + 1] -= f_si[1];
+        force[(i + 1) * 3 + 2] -= f_si[2];
+
+        let h_i = PI
+            * strand.radius.powi(2)
+            * strand.youngs
+            * (1.0 / strand.l_rest_length[i]
+                * strand.reference_frame[i].t
+                * strand.reference_frame[i].t.transpose());
+
+        if !strand.v_pinned[i] {
+            add_block(&mut hessian, &h_i, ((i * 3), (i * 3)));
+            add_block(&mut hessian, &-h_i, ((i * 3), ((i + 1) * 3)));
+            add_block(&mut hessian, &-h_i, (((i + 1) * 3), (i * 3)));
+        }
+
+        add_block(&mut hessian, &h_i, (((i + 1) * 3), ((i + 1) * 3)));
+    }
+
+    // partial_kappa with its original slots, see the test above
+    let swapped: Vec<na::Matrix4x1<f64>> = kappa
+        .iter()
+        .map(|kappa| na::Matrix4x1::new(kappa[0], kappa[2], kappa[1], kappa[3]))
+        .collect();
+    let frames = &strand.reference_frame;
+    let mut nabla_kappa_vec = vec![vec![na::Matrix4x3::<f64>::zeros(); 3]; strand.v_num];
+
+    for i in 1..(strand.v_num - 1) {
+        let t_tilde = (frames[i - 1].t + frames[i].t) / (1.0 + frames[i - 1].t.dot(&frames[i].t));
+
+        if i - 1 > 0 {
+            nabla_kappa_vec[i][0] =
+                calc_nabla_i_kappa_i_1(&e_vec, &t_tilde, &swapped, frames, &material_frame, i - 1);
+        }
+        nabla_kappa_vec[i][1] =
+            calc_nabla_i_kappa_i(&e_vec, &t_tilde, &swapped, frames, &material_frame, i);
+        if i + 1 < strand.v_num - 1 {
+            nabla_kappa_vec[i][2] =
+                calc_nabla_i_kappa_i1(&e_vec, &t_tilde, &swapped, frames, &material_frame, i + 1);
+        }
+    }
+
+    // Apply bend
+    for i in 1..(strand.v_num - 1) {
+        // Calc bend force
+        let mut kappa_part = na::Matrix3x1::<f64>::zeros();
+
+        if i - 1 > 0 {
+            kappa_part += nabla_kappa_vec[i][0].transpose() * (kappa[i - 1] - initial_kappa[i - 1])
+                / length_vec[i - 1];
+        }
+
+        {
+            kappa_part +=
+                nabla_kappa_vec[i][1].transpose() * (kappa[i] - initial_kappa[i]) / length_vec[i];
+        }
+
+        if i + 1 < strand.v_num - 1 {
+            kappa_part += nabla_kappa_vec[i][2].transpose() * (kappa[i + 1] - initial_kappa[i + 1])
+                / length_vec[i + 1];
+        }
+
+        let h_factor = PI * strand.radius.powf(4.0) * strand.youngs / 8.0;
+
+        let f_sum = -h_factor * kappa_part;
+
+        force[i * 3] += f_sum[0];
+        force[i * 3 + 1] += f_sum[1];
+        force[i * 3 + 2] += f_sum[2];
+
+        // Calc bend force torque
+        let kappa_diff = kappa[i] - initial_kappa[i];
+        let nabla_theta = [
+            na::Matrix4x1::new(kappa[i][2], 0.0, -kappa[i][0], 0.0),
+            na::Matrix4x1::new(0.0, kappa[i][3], 0.0, -kappa[i][1]),
+        ];
+        let nabla_x = [
+            nabla_kappa_vec[i - 1][2],
+            nabla_kappa_vec[i][1],
+            nabla_kappa_vec[i + 1][0],
+        ];
+
+        for (a, nabla_theta_a) in nabla_theta.iter().enumerate() {
+            let theta_index = 3 * strand.v_num + i - 1 + a;
+            force[theta_index] -= h_factor * nabla_theta_a.dot(&kappa_diff) / length_vec[i];
+
+            for (b, nabla_theta_b) in nabla_theta.iter().enumerate() {
+                hessian[(theta_index, 3 * strand.v_num + i - 1 + b)] +=
+                    h_factor * nabla_theta_a.dot(nabla_theta_b) / length_vec[i];
+            }
+
+            for (b, nabla_x_b) in nabla_x.iter().enumerate() {
+                let h = h_factor * nabla_x_b.transpose() * nabla_theta_a / length_vec[i];
+                add_block(&mut hessian, &h, ((i - 1 + b) * 3, theta_index));
+                add_block(&mut hessian, &h.transpose(), (theta_index, (i - 1 + b) * 3));
+            }
+        }
+
+        // Calc bend force hessian
+        let mut h_i_i = nabla_kappa_vec[i][1].transpose() * nabla_kappa_vec[i][1] / length_vec[i];
+
+        if i >= 2 {
+            let mut h_i_i_2 =
+                nabla_kappa_vec[i][0].transpose() * nabla_kappa_vec[i - 2][2] / length_vec[i - 1];
+
+            h_i_i_2 *= h_factor;
+            add_block(&mut hessian, &h_i_i_2, ((i * 3), ((i - 2) * 3)));
+        }
+
+        {
+            let mut h_i_i_1 = nabla_kappa_vec[i][0].transpose() * nabla_kappa_vec[i - 1][1]
+                / length_vec[i - 1]
+                + nabla_kappa_vec[i][1].transpose() * nabla_kappa_vec[i - 1][2] / length_vec[i];
+
+            h_i_i_1 *= h_factor;
+            add_block(&mut hessian, &h_i_i_1, ((i * 3), ((i - 1) * 3)));
+
+            h_i_i += nabla_kappa_vec[i][0].transpose() * nabla_kappa_vec[i][0] / length_vec[i - 1];
+        }
+
+        if i + 1 < strand.l_num {
+            let mut h_i_i1 = nabla_kappa_vec[i][1].transpose() * nabla_kappa_vec[i + 1][0]
+                / length_vec[i]
+                + nabla_kappa_vec[i][2].transpose() * nabla_kappa_vec[i + 1][1] / length_vec[i + 1];
+
+            h_i_i1 *= h_factor;
+            add_block(&mut hessian, &h_i_i1, ((i * 3), ((i + 1) * 3)));
+
+            h_i_i += nabla_kappa_vec[i][2].transpose() * nabla_kappa_vec[i][2] / length_vec[i + 1];
+        }
+
+        if i + 2 < strand.l_num {
+            let mut h_i_i2 =
+                nabla_kappa_vec[i][2].transpose() * nabla_kappa_vec[i + 2][0] / length_vec[i + 1];
+
+            h_i_i2 *= h_factor;
+            add_block(&mut hessian, &h_i_i2, ((i * 3), ((i + 2) * 3)));
+        }
+
+        h_i_i *= h_factor;
+        add_block(&mut hessian, &h_i_i, ((i * 3), (i * 3)));
+    }
+
+    // Apply twist
+    let k_t = twist_factor(strand);
+    let theta_offset = 3 * strand.v_num;
+    for i in 1..(strand.v_num - 1) {
+        let l_bar = (strand.l_rest_length[i - 1] + strand.l_rest_length[i]) / 2.0;
+        let factor = k_t / l_bar;
+        let m = strand.l_twist[i] - strand.l_twist[i - 1] + strand.v_reference_twist[i];
+        let d_e_prev = -kappa_b[i] / (2.0 * e_vec[i - 1].norm());
+        let d_e_next = -kappa_b[i] / (2.0 * e_vec[i].norm());
+        let nabla_x = [-d_e_prev, d_e_prev - d_e_next, d_e_next];
+
+        for (j, nabla) in nabla_x.iter().enumerate() {
+            let f = -factor * m * nabla;
+            let row = (i - 1 + j) * 3;
+            force[row] += f.x;
+            force[row + 1] += f.y;
+            force[row + 2] += f.z;
+        }
+
+        force[theta_offset + i - 1] += factor * m;
+        force[theta_offset + i] -= factor * m;
+
+        let nabla_theta = [-1.0, 1.0];
+        for (a, nabla_a) in nabla_x.iter().enumerate() {
+            for (b, nabla_b) in nabla_x.iter().enumerate() {
+                let h = factor * nabla_a * nabla_b.transpose();
+                add_block(&mut hessian, &h, ((i - 1 + a) * 3, (i - 1 + b) * 3));
+            }
+
+            for (b, d_theta) in nabla_theta.iter().enumerate() {
+                let h = factor * d_theta * nabla_a;
+                let col = theta_offset + i - 1 + b;
+                add_block(&mut hessian, &h, ((i - 1 + a) * 3, col));
+                add_block(&mut hessian, &h.transpose(), (col, (i - 1 + a) * 3));
+            }
+        }
+
+        for (a, d_theta_a) in nabla_theta.iter().enumerate() {
+            for (b, d_theta_b) in nabla_theta.iter().enumerate() {
+                hessian[(theta_offset + i - 1 + a, theta_offset + i - 1 + b)] +=
+                    factor * d_theta_a * d_theta_b;
+            }
+        }
+    }
+
+    // Apply gravity
+    for i in (0..strand.v_num).filter(|&i| !strand.v_pinned[i]) {
+        force[i * 3] += 0.0;
+        force[i * 3 + 1] -= 9.8 * strand.v_mass[i];
+        force[i * 3 + 2] += 0.0;
+    }
+
+    (force, hessian)
+}
+
+// The terms of `RodModel::Original` are the do_der loop they were split from,
+// bit for bit: the force is the negated gradient, and the lower triangle of
+// the Hessian is kept, as the banded solve only reads that one
+#[test]
+fn original_terms_reproduce_the_do_der_loop() {
+    let terms = EnergyTerms {
+        rod_model: RodModel::Original,
+        head_contact: false,
+        collider_contact: false,
+        ..Default::default()
+    };
+    let scene = Scene::default();
+
+    for seed in SEEDS {
+        let mut strand = random_strand(seed);
+        strand.v_pinned[1] = true;
+        let (force, original_hessian) = original_do_der(&strand);
+
+        let state = StrandState::new(&strand);
+        let context = EnergyContext {
+            strand: &strand,
+            state: &state,
+            head: &scene.head,
+            colliders: &scene.colliders,
+        };
+        let mut gradient = na::DVector::<f64>::zeros(state.dof_num());
+        let mut hessian = SymmetricBandMatrix::zeros(state.dof_num(), state.bandwidth());
+        for term in registered_energies(&terms) {
+            term.gradient(&context, &mut gradient);
+            term.hessian(&context, &mut hessian);
+        }
+
+        // From the layout of the loop to the interleaved one of the terms
+        let dof = |index: usize| {
+            if index < 3 * strand.v_num {
+                state.vertex_dof(index / 3) + index % 3
+            } else {
+                state.twist_dof(index - 3 * strand.v_num)
+            }
+        };
+
+        for r in 0..force.len() {
+            assert_eq!(-gradient[dof(r)], force[r], "seed {} force {}", seed, r);
+            for c in (0..force.len()).filter(|&c| dof(c) <= dof(r)) {
+                assert_eq!(
+                    hessian.get(dof(r), dof(c)),
+                    original_hessian[(r, c)],
+                    "seed {} hessian ({}, {})",
+                    seed,
+                    r,
+                    c
+                );
+            }
+        }
+    }
+}
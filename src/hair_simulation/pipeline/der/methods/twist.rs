@@ -0,0 +1,117 @@
+use std::f64::consts::PI;
+
+use crate::hair_simulation::{
+    data::{Frame, HairStrand},
+    pipeline::{
+        der::{
+            state::StrandState,
+            utils::{add_local_to_matrix, add_local_to_vector, parallel_transport_vector},
+        },
+        utils::band_matrix::SymmetricBandMatrix,
+    },
+};
+
+use super::{ElasticEnergy, EnergyContext};
+extern crate nalgebra as na;
+
+pub struct Twist;
+
+pub fn twist_factor(strand: &HairStrand) -> f64 {
+    // G * J, with J the polar moment of a circular section
+    PI * strand.radius.powi(4) * strand.shear / 2.0
+}
+
+pub fn wrap_angle(angle: f64) -> f64 {
+    let mut angle = angle;
+    while angle > PI {
+        angle -= 2.0 * PI;
+    }
+    while angle <= -PI {
+        angle += 2.0 * PI;
+    }
+    angle
+}
+
+// Angle that takes the reference frame of edge i-1, space-parallel transported
+// across vertex i, onto the reference frame of edge i. Measured in the same
+// sense as the material angle, so that m_i = theta_i - theta_(i-1) + ref_twist_i.
+pub fn calc_reference_twist(reference_frame: &[Frame], previous: &[f64], index: usize) -> f64 {
+    let prev = &reference_frame[index - 1];
+    let curr = &reference_frame[index];
+
+    let transported = parallel_transport_vector(prev.n, prev.t, curr.t);
+    let angle = -f64::atan2(transported.dot(&curr.b), transported.dot(&curr.n));
+
+    // Unwrap against the last value so the twist can exceed a half turn
+    previous[index] + wrap_angle(angle - previous[index])
+}
+
+pub fn calc_twist(strand: &HairStrand, state: &StrandState, index: usize) -> f64 {
+    strand.l_twist[index] - strand.l_twist[index - 1] + state.reference_twist[index]
+}
+
+fn rest_twist(strand: &HairStrand, index: usize) -> f64 {
+    strand.v_rest_twist.get(index).copied().unwrap_or(0.0)
+}
+
+// Gradient of the twist at vertex i over its stencil, see `StrandState::stencil_dofs`
+pub fn calc_twist_jacobian(state: &StrandState, index: usize) -> na::SVector<f64, 11> {
+    // The reference frames are left-handed (n x b = -t), which flips the
+    // sign of the usual dm/de = kb / 2|e| relation.
+    let d_e_prev = -state.kappa_b[index] / (2.0 * state.length_vec[index - 1]);
+    let d_e_next = -state.kappa_b[index] / (2.0 * state.length_vec[index]);
+
+    let mut jacobian = na::SVector::<f64, 11>::zeros();
+    jacobian.fixed_rows_mut::<3>(0).copy_from(&-d_e_prev);
+    jacobian[3] = -1.0;
+    jacobian
+        .fixed_rows_mut::<3>(4)
+        .copy_from(&(d_e_prev - d_e_next));
+    jacobian[7] = 1.0;
+    jacobian.fixed_rows_mut::<3>(8).copy_from(&d_e_next);
+    jacobian
+}
+
+impl ElasticEnergy for Twist {
+    fn name(&self) -> &'static str {
+        "twist"
+    }
+
+    fn energy(&self, context: &EnergyContext) -> f64 {
+        let strand = context.strand;
+        let k_t = twist_factor(strand);
+
+        let mut energy = 0.0;
+        for i in 1..(strand.v_num - 1) {
+            let m = calc_twist(strand, context.state, i) - rest_twist(strand, i);
+            energy += k_t / (2.0 * strand.get_voronoi_length(i)) * m.powi(2);
+        }
+        energy
+    }
+
+    fn gradient(&self, context: &EnergyContext, gradient: &mut na::DVector<f64>) {
+        let strand = context.strand;
+        let state = context.state;
+        let k_t = twist_factor(strand);
+
+        for i in 1..(strand.v_num - 1) {
+            let m = calc_twist(strand, state, i) - rest_twist(strand, i);
+            let local = k_t / strand.get_voronoi_length(i) * m * calc_twist_jacobian(state, i);
+            add_local_to_vector(gradient, &state.stencil_dofs(i), &local);
+        }
+    }
+
+    fn hessian(&self, context: &EnergyContext, hessian: &mut SymmetricBandMatrix) {
+        let strand = context.strand;
+        let state = context.state;
+        let k_t = twist_factor(strand);
+
+        for i in 1..(strand.v_num - 1) {
+            let jacobian = calc_twist_jacobian(state, i);
+
+            // Gauss-Newton approximation k_t / l * grad(m) * grad(m)^T
+            let local = k_t / strand.get_voronoi_length(i) * jacobian * jacobian.transpose();
+            add_local_to_matrix(hessian, &state.stencil_dofs(i), &local);
+        }
+    }
+}
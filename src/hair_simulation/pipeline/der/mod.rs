@@ -1,393 +1,514 @@
+pub mod contact_response;
+pub mod damping;
+pub mod hair_collision;
+pub mod inextensibility;
+pub mod integrator;
 pub mod methods;
+pub mod state;
 pub mod utils;
 
+#[cfg(test)]
+mod tests;
 extern crate nalgebra as na;
-use core::task;
-use std::f64::consts::PI;
 
-use bevy::{log::info, scene::ron::de};
+use bevy::{
+    log::warn,
+    tasks::{ComputeTaskPool, ParallelSlice, ParallelSliceMut, TaskPool},
+};
 
 use crate::{
     hair_simulation::{
-        data::{self, Frame},
-        pipeline::der::utils::{
-            add_to_matrix, calc_nabla_i_kappa_i, calc_nabla_i_kappa_i1, calc_nabla_i_kappa_i_1,
+        collider::Collider,
+        config::{FailurePolicy, LinearSolver, NonlinearSolver, SimulationConfig},
+        data::{convert_to_na_quat, HairStrand, Head, SimulationData},
+        pipeline::{
+            der::{
+                contact_response::apply_contact_response,
+                damping::damping_matrix,
+                hair_collision::apply_hair_collisions,
+                inextensibility::apply_inextensibility,
+                integrator::{step_scheme, DofState},
+                methods::{registered_energies, twist::wrap_angle, ElasticEnergy, EnergyContext},
+                state::StrandState,
+            },
+            utils::band_matrix::SymmetricBandMatrix,
         },
     },
-    physic_simulation::interfaces::SimulationTaskInterface,
+    physic_simulation::interfaces::{
+        ContactCounts, EnergyDiagnostics, FailureAction, SimulationDiagnostics, SimulationError,
+        SimulationErrorKind, SimulationTaskInterface,
+    },
 };
 
 pub const MAX_T_DOT: f64 = 100.0;
 
-pub fn do_der(task_interface: &mut SimulationTaskInterface) {
-    let hairs = &mut task_interface.data.hairs;
-    let head = &task_interface.data.head;
-    print!("{:?}", hairs.strands.len());
-
-    for strand in hairs.strands.iter_mut() {
-        let mut force = na::DMatrix::<f64>::zeros(4 * strand.v_num - 1, 1);
-        let mut hessian = na::DMatrix::<f64>::zeros(4 * strand.v_num - 1, 4 * strand.v_num - 1);
-        let mut velocity_0 = na::DMatrix::<f64>::zeros(4 * strand.v_num - 1, 1);
-        let mut velocity = na::DMatrix::<f64>::zeros(4 * strand.v_num - 1, 1);
-        let mut mass = na::DMatrix::<f64>::zeros(4 * strand.v_num - 1, 4 * strand.v_num - 1);
-
-        // Fill mass matrix
-        for i in 0..strand.v_num {
-            mass[(3 * i, 3 * i)] = strand.v_mass[i];
-            mass[(3 * i + 1, 3 * i + 1)] = strand.v_mass[i];
-            mass[(3 * i + 2, 3 * i + 2)] = strand.v_mass[i];
-        }
-
-        for i in 0..strand.l_num {
-            mass[(3 * strand.v_num + i, 3 * strand.v_num + i)] = strand.l_momemtum[i];
-        }
-
-        // Fill velocity_0
-        for i in 0..strand.v_num {
-            velocity_0[(3 * i, 0)] = strand.v_velocity[i].x;
-            velocity_0[(3 * i + 1, 0)] = strand.v_velocity[i].y;
-            velocity_0[(3 * i + 2, 0)] = strand.v_velocity[i].z;
-        }
-
-        for i in 0..strand.l_num {
-            velocity_0[(3 * strand.v_num + i, 0)] = strand.l_angular[i];
-        }
+// Strands handed to one task of the compute pool
+pub const STRAND_CHUNK_SIZE: usize = 16;
+
+// Diagonal shifts tried by `FailurePolicy::Regularize`, relative to the largest pivot
+pub const REGULARIZATION_STEPS: [f64; 3] = [1e-8, 1e-6, 1e-4];
+
+// Halvings of the Newton step before giving up, and the sufficient decrease
+// required of the incremental potential
+pub const LINE_SEARCH_STEPS: usize = 12;
+pub const LINE_SEARCH_ARMIJO: f64 = 1e-4;
+
+pub fn solve_strand_system(
+    solver: LinearSolver,
+    a: &SymmetricBandMatrix,
+    b: &na::DVector<f64>,
+) -> Result<na::DVector<f64>, SimulationErrorKind> {
+    let x = match solver {
+        LinearSolver::BandedLdlt => a.ldlt().map(|ldlt| ldlt.solve(b)),
+        LinearSolver::DenseLu => a.to_dense().lu().solve(b),
+    }
+    .ok_or(SimulationErrorKind::SingularSystem)?;
 
-        let mut e_vec = Vec::new();
-        let mut length_vec = Vec::new();
-        // Update reference frame
-        for i in 0..strand.l_num {
-            let e = strand.v_position[i + 1] - strand.v_position[i];
-            let t = e.normalize();
-            // info!("{:?}", t);
-            length_vec.push(e.norm());
-
-            let reference_frame = utils::parallel_transport(strand.reference_frame[i].t, t.clone());
-
-            strand.reference_frame[i].b = reference_frame.0;
-            strand.reference_frame[i].n = reference_frame.2;
-            strand.reference_frame[i].t = t;
-            e_vec.push(e);
-        }
+    if x.iter().all(|v| v.is_finite()) {
+        Ok(x)
+    } else {
+        Err(SimulationErrorKind::NonFiniteSolution)
+    }
+}
 
-        // Calculate material frame
-        let mut material_frame = Vec::new();
-        for i in 0..(strand.l_num) {
-            let a1 = strand.reference_frame[i].n;
-            let a2 = strand.reference_frame[i].b;
+// Everything shared by the strands of one step
+struct DerStep<'a> {
+    energies: &'a Vec<Box<dyn ElasticEnergy>>,
+    head: &'a Head,
+    colliders: &'a [Collider],
+    config: &'a SimulationConfig,
+    delta_time: f64,
+    iteration_cnt: u64,
+}
 
-            let m1 = a1 * f64::cos(strand.l_twist[i]) + a2 * f64::sin(strand.l_twist[i]);
-            let m2 = -a1 * f64::sin(strand.l_twist[i]) + a2 * f64::cos(strand.l_twist[i]);
+pub fn do_der(task_interface: &mut SimulationTaskInterface) {
+    do_der_in_chunks(task_interface, STRAND_CHUNK_SIZE);
+}
 
-            material_frame.push(Frame {
-                b: m2,
-                n: m1,
-                t: na::Vector3::zeros(),
-            });
-        }
+// One task of the compute pool per chunk_size strands
+fn do_der_in_chunks(task_interface: &mut SimulationTaskInterface, chunk_size: usize) {
+    let energies = registered_energies(&task_interface.data.config.energies);
+    let step = DerStep {
+        energies: &energies,
+        head: &task_interface.data.head,
+        colliders: &task_interface.data.colliders,
+        config: &task_interface.data.config,
+        delta_time: task_interface.delta_time,
+        iteration_cnt: task_interface.iteration_cnt,
+    };
+    let hairs = &mut task_interface.data.hairs;
 
-        // Calculate kappa_b
-        let mut kappa_b = Vec::new();
-        for i in 0..(strand.v_num - 1) {
-            if i == 0 {
-                kappa_b.push(na::Vector3::zeros());
-            } else {
-                let mut t_dot = strand.reference_frame[i - 1]
-                    .t
-                    .dot(&strand.reference_frame[i].t);
-                if t_dot > MAX_T_DOT {
-                    t_dot = MAX_T_DOT;
-                } else if t_dot < -MAX_T_DOT {
-                    t_dot = -MAX_T_DOT;
-                }
-                let temp_kappa_b = (2.0
-                    * strand.reference_frame[i - 1]
-                        .t
-                        .cross(&strand.reference_frame[i].t))
-                    / (1.0 + t_dot);
-                if temp_kappa_b.norm() > 1.0 {
-                    kappa_b.push(temp_kappa_b.normalize() * 1.0);
-                } else {
-                    kappa_b.push(temp_kappa_b);
-                }
+    // Strands are independent, so the result does not depend on the split
+    let task_pool = ComputeTaskPool::get_or_init(TaskPool::default);
+    let chunk_results = hairs
+        .strands
+        .par_chunk_map_mut(task_pool, chunk_size, |strands| {
+            let mut errors = Vec::new();
+            let mut diagnostics = Vec::new();
+            for (index, strand) in strands.iter_mut().enumerate() {
+                diagnostics.push(step_strand_guarded(strand, index, &step, &mut errors));
             }
+            (errors, diagnostics)
+        });
+
+    let mut diagnostics = SimulationDiagnostics::default();
+    let mut contacts = ContactCounts::default();
+    let mut factors = Vec::with_capacity(hairs.strands.len());
+    for (chunk, (errors, strand_diagnostics)) in chunk_results.into_iter().enumerate() {
+        for mut error in errors {
+            error.strand += chunk * chunk_size;
+            warn!("simulation error: {}", error);
+            task_interface.errors.push(error);
         }
-
-        // Calculate kappa
-        let mut kappa = Vec::new();
-        for i in 0..(strand.v_num - 1) {
-            if i == 0 {
-                kappa.push(na::Matrix4x1::new(0.0, 0.0, 0.0, 0.0));
-            } else {
-                let kappa_i = na::Matrix4x1::new(
-                    material_frame[i - 1].b.dot(&kappa_b[i]),
-                    material_frame[i].b.dot(&kappa_b[i]),
-                    -material_frame[i - 1].n.dot(&kappa_b[i]),
-                    -material_frame[i].n.dot(&kappa_b[i]),
-                );
-                kappa.push(kappa_i)
-            }
+        for (strand_diagnostic, iterations, strand_contacts, factor) in strand_diagnostics {
+            diagnostics.total.accumulate(&strand_diagnostic);
+            diagnostics.strands.push(strand_diagnostic);
+            diagnostics.iterations.push(iterations);
+            contacts.accumulate(&strand_contacts);
+            factors.push(factor);
         }
+    }
 
-        if strand.l_initial_kappa.len() == 0 {
-            for i in 0..(strand.v_num - 1) {
-                if i == 0 {
-                    strand
-                        .l_initial_kappa
-                        .push(na::Matrix4x1::new(0.0, 0.0, 0.0, 0.0));
-                } else {
-                    strand.l_initial_kappa.push(kappa[i]);
-                }
-            }
-            info!("kappa initialized");
-        }
+    // Strands only meet once they have all moved
+    contacts.between_strands =
+        apply_hair_collisions(step.config.hair_collision, &mut hairs.strands, &factors);
+    diagnostics.contacts.push(contacts);
+    task_interface.diagnostics = diagnostics;
+}
 
-        // Apply stretch
-        for i in 0..strand.l_num {
-            let f_si = PI
-                * strand.radius.powi(2)
-                * strand.youngs
-                * ((length_vec[i] / strand.l_rest_length[i] - 1.0) * strand.reference_frame[i].t);
-
-            if i > strand.last_pin {
-                force[i * 3] += f_si[0];
-                force[i * 3 + 1] += f_si[1];
-                force[i * 3 + 2] += f_si[2];
+// Potential and kinetic energy of all running strands at their current state
+pub fn total_energy(data: &SimulationData) -> f64 {
+    let energies = registered_energies(&data.config.energies);
+    let task_pool = ComputeTaskPool::get_or_init(TaskPool::default);
+    let strands = &data.hairs.strands;
+    let chunk_energies = strands.par_chunk_map(task_pool, STRAND_CHUNK_SIZE, |strands| {
+        let mut energy = 0.0;
+        for strand in strands.iter().filter(|strand| !strand.frozen) {
+            let state = StrandState::new(strand);
+            let context = EnergyContext {
+                strand,
+                state: &state,
+                head: &data.head,
+                colliders: &data.colliders,
+            };
+            energy += energies
+                .iter()
+                .map(|energy| energy.energy(&context))
+                .sum::<f64>();
+            for i in 0..strand.v_num {
+                energy += 0.5 * strand.v_mass[i] * strand.v_velocity[i].norm_squared();
             }
-
-            force[(i + 1) * 3] -= f_si[0];
-            force[(i + 1) * 3 + 1] -= f_si[1];
-            force[(i + 1) * 3 + 2] -= f_si[2];
-
-            let h_i = PI
-                * strand.radius.powi(2)
-                * strand.youngs
-                * (1.0 / strand.l_rest_length[i]
-                    * strand.reference_frame[i].t
-                    * strand.reference_frame[i].t.transpose());
-            // info!("{:?}", h_i);
-
-            if i > strand.last_pin {
-                add_to_matrix(&mut hessian, &h_i, ((i * 3), (i * 3)));
-                add_to_matrix(&mut hessian, &-h_i, ((i * 3), ((i + 1) * 3)));
-                add_to_matrix(&mut hessian, &-h_i, (((i + 1) * 3), (i * 3)));
+            for i in 0..strand.l_num {
+                energy += 0.5 * strand.l_momemtum[i] * strand.l_angular[i].powi(2);
             }
-
-            add_to_matrix(&mut hessian, &h_i, (((i + 1) * 3), ((i + 1) * 3)));
         }
-        // info!("{:?}", force);
-
-        let mut nabla_kappa_vec = vec![vec![na::Matrix4x3::<f64>::zeros(); 3]; strand.v_num];
-
-        for i in 1..(strand.v_num - 1) {
-            let t_tilde = (strand.reference_frame[i - 1].t + strand.reference_frame[i].t)
-                / (1.0
-                    + strand.reference_frame[i - 1]
-                        .t
-                        .dot(&strand.reference_frame[i].t));
-
-            if i - 1 > 0 {
-                let nabla_i_kappa_i_1 = calc_nabla_i_kappa_i_1(
-                    &e_vec,
-                    &t_tilde,
-                    &kappa,
-                    &strand.reference_frame,
-                    &material_frame,
-                    i - 1,
-                );
-                // info!("nabla_i_kappa_i_1{:?}", nabla_i_kappa_i_1);
-                nabla_kappa_vec[i][0] = nabla_i_kappa_i_1;
-            }
+        energy
+    });
+    chunk_energies.iter().sum()
+}
 
-            {
-                let nabla_i_kappa_i = calc_nabla_i_kappa_i(
-                    &e_vec,
-                    &t_tilde,
-                    &kappa,
-                    &strand.reference_frame,
-                    &material_frame,
-                    i,
-                );
-                // info!("nabla_i_kappa_i{:?}", nabla_i_kappa_i);
-                nabla_kappa_vec[i][1] = nabla_i_kappa_i;
-            }
+// Apply the failure policy around a single strand step
+fn step_strand_guarded(
+    strand: &mut HairStrand,
+    index: usize,
+    step: &DerStep,
+    errors: &mut Vec<SimulationError>,
+) -> (EnergyDiagnostics, usize, ContactCounts, f64) {
+    if strand.frozen {
+        return Default::default();
+    }
 
-            if i + 1 < strand.v_num - 1 {
-                let nabla_i_kappa_i1 = calc_nabla_i_kappa_i1(
-                    &e_vec,
-                    &t_tilde,
-                    &kappa,
-                    &strand.reference_frame,
-                    &material_frame,
-                    i + 1,
-                );
-                // info!("nabla_i_kappa_i1{:?}", nabla_i_kappa_i1);
-                nabla_kappa_vec[i][2] = nabla_i_kappa_i1;
-            }
+    let previous = strand.clone();
+    match step_strand(strand, index, step, errors) {
+        Ok(result) => result,
+        Err(kind) => {
+            *strand = previous;
+            strand.clear_history();
+            strand
+                .v_velocity
+                .iter_mut()
+                .for_each(|v| *v = na::Vector3::zeros());
+            strand.l_angular.iter_mut().for_each(|w| *w = 0.0);
+
+            let action = if step.config.failure_policy == FailurePolicy::Freeze {
+                strand.frozen = true;
+                FailureAction::Frozen
+            } else {
+                FailureAction::Reverted
+            };
+
+            errors.push(SimulationError {
+                iteration_cnt: step.iteration_cnt,
+                strand: index,
+                kind,
+                action,
+            });
+            Default::default()
         }
+    }
+}
 
-        // Apply bend
-        for i in 1..(strand.v_num - 1) {
-            // Calc bend force
-            let mut kappa_part = na::Matrix3x1::<f64>::zeros();
+// Energies, gradient and Hessian of a strand at one configuration
+struct Assembly {
+    potential: Vec<(&'static str, f64)>,
+    gradient: na::DVector<f64>,
+    hessian: Option<SymmetricBandMatrix>,
+}
 
-            if i - 1 > 0 {
-                kappa_part = kappa_part
-                    + nabla_kappa_vec[i][0].transpose()
-                        * (kappa[i - 1] - strand.l_initial_kappa[i - 1])
-                        / length_vec[i - 1];
-            }
+impl Assembly {
+    fn energy(&self) -> f64 {
+        self.potential.iter().map(|(_, energy)| energy).sum()
+    }
 
-            {
-                kappa_part = kappa_part
-                    + nabla_kappa_vec[i][1].transpose() * (kappa[i] - strand.l_initial_kappa[i])
-                        / length_vec[i];
-            }
+    fn is_finite(&self) -> bool {
+        self.gradient.iter().all(|v| v.is_finite()) && self.hessian.iter().all(|h| h.is_finite())
+    }
+}
 
-            if i + 1 < strand.v_num - 1 {
-                kappa_part = kappa_part
-                    + nabla_kappa_vec[i][2].transpose()
-                        * (kappa[i + 1] - strand.l_initial_kappa[i + 1])
-                        / length_vec[i + 1];
-            }
+fn assemble(strand: &HairStrand, state: &StrandState, step: &DerStep) -> Assembly {
+    let dof_num = state.dof_num();
+    let mut gradient = na::DVector::<f64>::zeros(dof_num);
+    let mut hessian = step
+        .config
+        .integrator
+        .needs_hessian()
+        .then(|| SymmetricBandMatrix::zeros(dof_num, state.bandwidth()));
+    let mut potential = Vec::new();
+
+    let context = EnergyContext {
+        strand,
+        state,
+        head: step.head,
+        colliders: step.colliders,
+    };
+    for energy in step.energies.iter() {
+        energy.gradient(&context, &mut gradient);
+        if let Some(hessian) = hessian.as_mut() {
+            energy.hessian(&context, hessian);
+        }
+        potential.push((energy.name(), energy.energy(&context)));
+    }
 
-            let h_factor = PI * strand.radius.powf(4.0) * strand.youngs / 8.0;
+    Assembly {
+        potential,
+        gradient,
+        hessian,
+    }
+}
 
-            let f_sum = -h_factor * kappa_part;
-            // info!("{:?}", f_sum);
+fn potential_energy(strand: &HairStrand, state: &StrandState, step: &DerStep) -> f64 {
+    let context = EnergyContext {
+        strand,
+        state,
+        head: step.head,
+        colliders: step.colliders,
+    };
+    step.energies
+        .iter()
+        .map(|energy| energy.energy(&context))
+        .sum()
+}
 
-            force[i * 3] += f_sum[0];
-            force[i * 3 + 1] += f_sum[1];
-            force[i * 3 + 2] += f_sum[2];
+// The strand moved to the given dofs, with the frames of the start of the step
+fn strand_at(strand: &HairStrand, state: &StrandState, position: &na::DVector<f64>) -> HairStrand {
+    let mut trial = strand.clone();
+    for i in 0..trial.v_num {
+        trial.v_position[i] = position.fixed_rows::<3>(state.vertex_dof(i)).into();
+    }
+    for i in 0..trial.l_num {
+        trial.l_twist[i] = position[state.twist_dof(i)];
+    }
+    trial
+}
 
-            // Calc bend force torque
+// Returns the energies of the start of the step, the number of linear solves,
+// the contacts resolved at its end and the position factor of the scheme
+fn step_strand(
+    strand: &mut HairStrand,
+    index: usize,
+    step: &DerStep,
+    errors: &mut Vec<SimulationError>,
+) -> Result<(EnergyDiagnostics, usize, ContactCounts, f64), SimulationErrorKind> {
+    let state = StrandState::new(strand);
+    state.commit(strand);
+
+    // Fill mass matrix
+    let mut mass = na::DVector::<f64>::zeros(state.dof_num());
+    for i in 0..strand.v_num {
+        for k in 0..3 {
+            mass[state.vertex_dof(i) + k] = strand.v_mass[i];
+        }
+    }
 
-            // Calc bend force hessian
+    for i in 0..strand.l_num {
+        mass[state.twist_dof(i)] = strand.l_momemtum[i];
+    }
 
-            let mut h_i_i =
-                nabla_kappa_vec[i][1].transpose() * nabla_kappa_vec[i][1] / length_vec[i];
+    let current = DofState {
+        position: state.to_dofs(&strand.v_position, &strand.l_twist),
+        velocity: state.to_dofs(&strand.v_velocity, &strand.l_angular),
+    };
+    let previous = strand.has_history().then(|| {
+        let previous = DofState {
+            position: state.to_dofs(&strand.v_previous_position, &strand.l_previous_twist),
+            velocity: state.to_dofs(&strand.v_previous_velocity, &strand.l_previous_angular),
+        };
+        (previous, strand.previous_delta_time)
+    });
+    let mut scheme = step_scheme(step.config.integrator, step.delta_time, &current, previous);
+
+    let mut assembly = assemble(strand, &state, step);
+    scheme.damping = damping_matrix(
+        &step.config.damping,
+        strand,
+        &state,
+        &mass,
+        assembly.hessian.as_ref(),
+    );
+    let mut diagnostics = EnergyDiagnostics {
+        potential: assembly.potential.clone(),
+        kinetic: 0.5 * current.velocity.dot(&mass.component_mul(&current.velocity)),
+        momentum: na::Vector3::zeros(),
+    };
+    for i in 0..strand.v_num {
+        diagnostics.momentum += strand.v_mass[i] * strand.v_velocity[i];
+    }
 
-            if i >= 2 {
-                let mut h_i_i_2 = nabla_kappa_vec[i][0].transpose() * nabla_kappa_vec[i - 2][2]
-                    / length_vec[i - 1];
+    let (max_iterations, tolerance) = match step.config.nonlinear_solver {
+        NonlinearSolver::Newton {
+            max_iterations,
+            tolerance,
+        } if step.config.integrator.needs_hessian() => (max_iterations.max(1), tolerance),
+        _ => (1, f64::INFINITY),
+    };
+
+    let root_twist = clamped_root_twist(strand, &state, step);
+    let mut velocity = scheme.initial_velocity(&current.position);
+    let mut iterations = 0;
+    loop {
+        if !assembly.is_finite() {
+            return Err(SimulationErrorKind::NonFiniteSystem);
+        }
 
-                h_i_i_2 *= h_factor;
-                add_to_matrix(&mut hessian, &h_i_i_2, ((i * 3), ((i - 2) * 3)));
-                // add_to_matrix(&mut hessian, &h_i_i_2.transpose(), (((i - 2) * 3), (i * 3)));
+        let (mut matrix, mut rhs) = scheme.newton_system(
+            &mass,
+            &velocity,
+            &assembly.gradient,
+            assembly.hessian.take(),
+        );
+
+        // Pinned vertices keep their velocity
+        for i in (0..strand.v_num).filter(|&i| strand.v_pinned[i]) {
+            for k in 0..3 {
+                let dof = state.vertex_dof(i) + k;
+                matrix.constrain(dof, strand.v_velocity[i][k] - velocity[dof], &mut rhs);
             }
+        }
 
-            if i >= 1 {
-                let mut h_i_i_1 = nabla_kappa_vec[i][0].transpose() * nabla_kappa_vec[i - 1][1]
-                    / length_vec[i - 1]
-                    + nabla_kappa_vec[i][1].transpose() * nabla_kappa_vec[i - 1][2] / length_vec[i];
-
-                h_i_i_1 *= h_factor;
-                add_to_matrix(&mut hessian, &h_i_i_1, ((i * 3), ((i - 1) * 3)));
-                // add_to_matrix(&mut hessian, &h_i_i_1.transpose(), (((i - 1) * 3), (i * 3)));
-
-                h_i_i +=
-                    nabla_kappa_vec[i][0].transpose() * nabla_kappa_vec[i][0] / length_vec[i - 1];
-            }
+        // The root twist lands on the follicle orientation
+        if let Some(root_twist) = root_twist {
+            let dof = state.twist_dof(0);
+            let target = (root_twist - scheme.base[dof]) / scheme.factor;
+            matrix.constrain(dof, target - velocity[dof], &mut rhs);
+        }
 
-            if i + 1 < strand.l_num {
-                let mut h_i_i1 = nabla_kappa_vec[i][1].transpose() * nabla_kappa_vec[i + 1][0]
-                    / length_vec[i]
-                    + nabla_kappa_vec[i][2].transpose() * nabla_kappa_vec[i + 1][1]
-                        / length_vec[i + 1];
+        let direction = solve_with_policy(&matrix, &rhs, index, step, errors)?;
+        iterations += 1;
 
-                h_i_i1 *= h_factor;
-                add_to_matrix(&mut hessian, &h_i_i1, ((i * 3), ((i + 1) * 3)));
-                // add_to_matrix(&mut hessian, &h_i_i1.transpose(), (((i + 1) * 3), (i * 3)));
+        if max_iterations == 1 {
+            velocity += direction;
+            break;
+        }
 
-                h_i_i +=
-                    nabla_kappa_vec[i][2].transpose() * nabla_kappa_vec[i][2] / length_vec[i + 1];
+        // Backtrack until the incremental potential decreases enough
+        let objective = scheme.objective(&mass, &velocity, assembly.energy());
+        let slope = scheme
+            .objective_gradient(&mass, &velocity, &assembly.gradient)
+            .dot(&direction);
+        let mut alpha = 1.0;
+        let mut accepted = None;
+        for _ in 0..LINE_SEARCH_STEPS {
+            let trial_velocity = &velocity + alpha * &direction;
+            let trial = strand_at(strand, &state, &scheme.position(&trial_velocity));
+            let trial_state = StrandState::new(&trial);
+            let energy = potential_energy(&trial, &trial_state, step);
+            let trial_objective = scheme.objective(&mass, &trial_velocity, energy);
+            if trial_objective <= objective + LINE_SEARCH_ARMIJO * alpha * slope {
+                accepted = Some((trial_velocity, trial, trial_state));
+                break;
             }
+            alpha *= 0.5;
+        }
 
-            if i + 2 < strand.l_num {
-                let mut h_i_i2 = nabla_kappa_vec[i][2].transpose() * nabla_kappa_vec[i + 2][0]
-                    / length_vec[i + 1];
-
-                h_i_i2 *= h_factor;
-                add_to_matrix(&mut hessian, &h_i_i2, ((i * 3), ((i + 2) * 3)));
-                // add_to_matrix(&mut hessian, &h_i_i2.transpose(), (((i + 2) * 3), (i * 3)));
+        // No decrease along the Newton direction. Later iterations keep the last
+        // iterate, but the first one still has the velocity that leaves the
+        // strand in place, so it takes the full step of the linearised solve.
+        let Some((trial_velocity, trial, trial_state)) = accepted else {
+            if iterations == 1 {
+                warn!(
+                    "#{}: line search found no decrease, taking the full Newton step",
+                    step.iteration_cnt
+                );
+                velocity += direction;
             }
+            break;
+        };
+        velocity = trial_velocity;
 
-            h_i_i *= h_factor;
-            add_to_matrix(&mut hessian, &h_i_i, ((i * 3), (i * 3)));
+        let converged = scheme.factor * alpha * direction.amax() < tolerance;
+        if converged || iterations >= max_iterations {
+            break;
         }
+        assembly = assemble(&trial, &trial_state, step);
+    }
 
-        // for i in 0..strand.l_num {
-        //     let mut nabla_i_kappa_i = na::Matrix4x1::<f64>::zeros();
-
-        //     nabla_i_kappa_i[1] = -material_frame[i].n.dot(&kappa_b[i]);
-        //     nabla_i_kappa_i[3] = -material_frame[i].b.dot(&kappa_b[i]);
-        //     let mut tah = nabla_i_kappa_i.transpose() * (kappa[i] - strand.l_initial_kappa[i])
-        //         / length_vec[i];
-
-        //     if i + 1 < strand.l_num {
-        //         let mut nabla_i_kappa_i1 = na::Matrix4x1::<f64>::zeros();
-        //         nabla_i_kappa_i1[0] = -material_frame[i].n.dot(&kappa_b[i + 1]);
-        //         nabla_i_kappa_i1[2] = -material_frame[i].b.dot(&kappa_b[i + 1]);
-
-        //         tah += nabla_i_kappa_i1.transpose()
-        //             * (kappa[i + 1] - strand.l_initial_kappa[i + 1])
-        //             / length_vec[i + 1];
-        //     }
-
-        //     tah *= -PI * strand.radius.powf(4.0) * strand.youngs / 8.0;
-
-        //     force[strand.v_num * 3 + i] += tah[0];
-        // }
+    // Update strand states
+    strand.record_history(step.delta_time);
+    let mut position = scheme.position(&velocity);
+    apply_inextensibility(
+        step.config.inextensibility,
+        strand,
+        &state,
+        scheme.factor,
+        &mut position,
+        &mut velocity,
+    );
+    let contacts = apply_contact_response(
+        step.config.contact_response,
+        strand,
+        &state,
+        step.colliders,
+        scheme.factor,
+        &mut position,
+        &mut velocity,
+    );
+    for i in (0..strand.v_num).filter(|&i| !strand.v_pinned[i]) {
+        let dof = state.vertex_dof(i);
+        strand.v_velocity[i] = velocity.fixed_rows::<3>(dof).into();
+        strand.v_position[i] = position.fixed_rows::<3>(dof).into();
+    }
 
-        // info!("{:?}", force);
+    for i in 0..(strand.l_num) {
+        let dof = state.twist_dof(i);
+        strand.l_angular[i] = velocity[dof];
+        strand.l_twist[i] = position[dof];
+    }
 
-        // Apply twist
+    Ok((diagnostics, iterations, contacts, scheme.factor))
+}
 
-        // Apply gravity
-        for i in (strand.last_pin + 1)..(strand.v_num) {
-            force[i * 3] += 0.0;
-            force[i * 3 + 1] -= 9.8 * strand.v_mass[i];
-            force[i * 3 + 2] += 0.0;
+// Twist of the root edge that aligns its material frame with the follicle
+// direction carried by the head
+fn clamped_root_twist(strand: &HairStrand, state: &StrandState, step: &DerStep) -> Option<f64> {
+    if !step.config.clamp_root_frame {
+        return None;
+    }
+    let director = convert_to_na_quat(step.head.rotation) * strand.root_director?;
 
-            // hessian[(i * 3 + 1, i * 3 + 1)] += 9.8;
-        }
+    // m1 = a1 cos(theta) + a2 sin(theta), see `StrandState::new`
+    let frame = &state.reference_frame[0];
+    let angle = f64::atan2(director.dot(&frame.b), director.dot(&frame.n));
+    Some(strand.l_twist[0] + wrap_angle(angle - strand.l_twist[0]))
+}
 
-        // Apply force from head
-        for i in (strand.last_pin + 1)..(strand.v_num) {
-            let velocity_norm = strand.v_velocity[i].norm();
-            let distance = (strand.v_position[i] - head.position).norm();
-            let direction = (strand.v_position[i] - head.position).normalize();
-            let depth = distance - head.radius - 0.01;
-            if depth < 0.0 {
-                let force_head = direction * depth * depth * 20.0 * velocity_norm;
-                force[i * 3] += force_head.x;
-                force[i * 3 + 1] += force_head.y;
-                force[i * 3 + 2] += force_head.z;
-            }
+// Solve, falling back to `solve_regularized` under `FailurePolicy::Regularize`
+fn solve_with_policy(
+    a: &SymmetricBandMatrix,
+    b: &na::DVector<f64>,
+    index: usize,
+    step: &DerStep,
+    errors: &mut Vec<SimulationError>,
+) -> Result<na::DVector<f64>, SimulationErrorKind> {
+    match solve_strand_system(step.config.linear_solver, a, b) {
+        Ok(x) => Ok(x),
+        Err(kind) if step.config.failure_policy == FailurePolicy::Regularize => {
+            let x = solve_regularized(step.config.linear_solver, a, b)?;
+            errors.push(SimulationError {
+                iteration_cnt: step.iteration_cnt,
+                strand: index,
+                kind,
+                action: FailureAction::Regularized,
+            });
+            Ok(x)
         }
+        Err(kind) => Err(kind),
+    }
+}
 
-        // info!("{:?}", force);
-        // info!("{:?}", hessian);
-        let a = mass.clone() + task_interface.delta_time.powi(2) * hessian;
-
-        let b = mass.clone() * velocity_0 + task_interface.delta_time * force;
-
-        // Solve linear system
-        velocity = a.lu().solve(&b).unwrap();
-
-        // Update strand states
-        for i in (strand.last_pin + 1)..(strand.v_num) {
-            strand.v_velocity[i] =
-                na::Vector3::new(velocity[i * 3], velocity[i * 3 + 1], velocity[i * 3 + 2]);
-
-            strand.v_position[i] =
-                strand.v_position[i] + task_interface.delta_time * strand.v_velocity[i];
+fn solve_regularized(
+    solver: LinearSolver,
+    a: &SymmetricBandMatrix,
+    b: &na::DVector<f64>,
+) -> Result<na::DVector<f64>, SimulationErrorKind> {
+    let scale = a.max_diagonal();
+    let mut result = Err(SimulationErrorKind::SingularSystem);
+    for factor in REGULARIZATION_STEPS {
+        let mut regularized = a.clone();
+        regularized.add_diagonal(&na::DVector::from_element(a.size(), factor * scale));
+        result = solve_strand_system(solver, &regularized, b);
+        if result.is_ok() {
+            break;
         }
-
-        // for i in 0..(strand.l_num) {
-        //     strand.l_angular[i] = velocity[3 * strand.v_num + i];
-
-        //     strand.l_twist[i] += task_interface.delta_time * strand.l_angular[i];
-        // }
     }
+    result
 }
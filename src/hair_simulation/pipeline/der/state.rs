@@ -0,0 +1,196 @@
+use crate::hair_simulation::data::{Frame, HairStrand};
+
+use super::{
+    methods::twist::calc_reference_twist,
+    utils::{calc_nabla_i_kappa_i, calc_nabla_i_kappa_i1, calc_nabla_i_kappa_i_1, transport_frame},
+    MAX_T_DOT,
+};
+extern crate nalgebra as na;
+
+// Geometry of a strand at the current positions, shared by every energy term.
+#[derive(Clone)]
+pub struct StrandState {
+    pub v_num: usize,
+    pub l_num: usize,
+    pub length_vec: Vec<f64>,
+    pub reference_frame: Vec<Frame>,
+    pub reference_twist: Vec<f64>,
+    pub kappa_b: Vec<na::Vector3<f64>>,
+    pub kappa: Vec<na::Matrix4x1<f64>>,
+    // Gradient of kappa_i with respect to x_(i-1), x_i and x_(i+1)
+    pub nabla_kappa: Vec<[na::Matrix4x3<f64>; 3]>,
+}
+
+impl StrandState {
+    pub fn new(strand: &HairStrand) -> Self {
+        let mut e_vec = Vec::new();
+        let mut length_vec = Vec::new();
+        let mut reference_frame = Vec::new();
+
+        // Update reference frame
+        for i in 0..strand.l_num {
+            let e = strand.v_position[i + 1] - strand.v_position[i];
+            let t = e.normalize();
+            length_vec.push(e.norm());
+
+            // Time-parallel transport from the previous step
+            reference_frame.push(transport_frame(&strand.reference_frame[i], t));
+            e_vec.push(e);
+        }
+
+        let mut reference_twist = vec![0.0; strand.v_num];
+        for (i, twist) in reference_twist
+            .iter_mut()
+            .enumerate()
+            .take(strand.v_num - 1)
+            .skip(1)
+        {
+            *twist = calc_reference_twist(&reference_frame, &strand.v_reference_twist, i);
+        }
+
+        // Calculate material frame
+        let material_frame: Vec<Frame> = reference_frame
+            .iter()
+            .zip(strand.l_twist.iter())
+            .map(|(frame, &theta)| {
+                let (a1, a2) = (frame.n, frame.b);
+                Frame {
+                    b: -a1 * f64::sin(theta) + a2 * f64::cos(theta),
+                    n: a1 * f64::cos(theta) + a2 * f64::sin(theta),
+                    t: frame.t,
+                }
+            })
+            .collect();
+
+        // Calculate kappa_b
+        let mut kappa_b = Vec::new();
+        for i in 0..(strand.v_num - 1) {
+            if i == 0 {
+                kappa_b.push(na::Vector3::zeros());
+            } else {
+                let t_dot = reference_frame[i - 1]
+                    .t
+                    .dot(&reference_frame[i].t)
+                    .clamp(-MAX_T_DOT, MAX_T_DOT);
+                let temp_kappa_b =
+                    (2.0 * reference_frame[i - 1].t.cross(&reference_frame[i].t)) / (1.0 + t_dot);
+                if temp_kappa_b.norm() > 1.0 {
+                    kappa_b.push(temp_kappa_b.normalize() * 1.0);
+                } else {
+                    kappa_b.push(temp_kappa_b);
+                }
+            }
+        }
+
+        // Calculate kappa
+        let mut kappa = Vec::new();
+        for i in 0..(strand.v_num - 1) {
+            if i == 0 {
+                kappa.push(na::Matrix4x1::new(0.0, 0.0, 0.0, 0.0));
+            } else {
+                let kappa_i = na::Matrix4x1::new(
+                    material_frame[i - 1].b.dot(&kappa_b[i]),
+                    material_frame[i].b.dot(&kappa_b[i]),
+                    -material_frame[i - 1].n.dot(&kappa_b[i]),
+                    -material_frame[i].n.dot(&kappa_b[i]),
+                );
+                kappa.push(kappa_i)
+            }
+        }
+
+        // Calculate the gradient of kappa
+        let mut nabla_kappa = vec![[na::Matrix4x3::<f64>::zeros(); 3]; strand.v_num - 1];
+        for i in 1..(strand.v_num - 1) {
+            let t_tilde = (reference_frame[i - 1].t + reference_frame[i].t)
+                / (1.0 + reference_frame[i - 1].t.dot(&reference_frame[i].t));
+
+            nabla_kappa[i] = [
+                calc_nabla_i_kappa_i1(
+                    &e_vec,
+                    &t_tilde,
+                    &kappa,
+                    &reference_frame,
+                    &material_frame,
+                    i,
+                ),
+                calc_nabla_i_kappa_i(
+                    &e_vec,
+                    &t_tilde,
+                    &kappa,
+                    &reference_frame,
+                    &material_frame,
+                    i,
+                ),
+                calc_nabla_i_kappa_i_1(
+                    &e_vec,
+                    &t_tilde,
+                    &kappa,
+                    &reference_frame,
+                    &material_frame,
+                    i,
+                ),
+            ];
+        }
+
+        StrandState {
+            v_num: strand.v_num,
+            l_num: strand.l_num,
+            length_vec,
+            reference_frame,
+            reference_twist,
+            kappa_b,
+            kappa,
+            nabla_kappa,
+        }
+    }
+
+    // Write the transported frames back, they are the start of the next step.
+    pub fn commit(&self, strand: &mut HairStrand) {
+        strand.reference_frame = self.reference_frame.clone();
+        strand.v_reference_twist = self.reference_twist.clone();
+    }
+
+    // Degrees of freedom interleaved as x_0, theta_0, x_1, theta_1, ..., x_(n-1)
+    // so that the system is banded.
+    pub fn dof_num(&self) -> usize {
+        4 * self.v_num - 1
+    }
+
+    pub fn vertex_dof(&self, index: usize) -> usize {
+        4 * index
+    }
+
+    pub fn twist_dof(&self, index: usize) -> usize {
+        4 * index + 3
+    }
+
+    // Scatter per-vertex and per-edge values into one dof vector
+    pub fn to_dofs(&self, vertex: &[na::Vector3<f64>], edge: &[f64]) -> na::DVector<f64> {
+        let mut dofs = na::DVector::<f64>::zeros(self.dof_num());
+        for (i, v) in vertex.iter().enumerate().take(self.v_num) {
+            dofs.fixed_rows_mut::<3>(self.vertex_dof(i)).copy_from(v);
+        }
+        for (i, &e) in edge.iter().enumerate().take(self.l_num) {
+            dofs[self.twist_dof(i)] = e;
+        }
+        dofs
+    }
+
+    // Widest coupling is a vertex stencil, x_(i-1) to x_(i+1)
+    pub fn bandwidth(&self) -> usize {
+        10
+    }
+
+    // Every dof touched by vertex i: x_(i-1), theta_(i-1), x_i, theta_i, x_(i+1)
+    pub fn stencil_dofs(&self, index: usize) -> [usize; 11] {
+        let mut dofs = [0; 11];
+        for k in 0..3 {
+            dofs[k] = self.vertex_dof(index - 1) + k;
+            dofs[4 + k] = self.vertex_dof(index) + k;
+            dofs[8 + k] = self.vertex_dof(index + 1) + k;
+        }
+        dofs[3] = self.twist_dof(index - 1);
+        dofs[7] = self.twist_dof(index);
+        dofs
+    }
+}
@@ -0,0 +1,1221 @@
+use std::f64::consts::PI;
+
+use bevy::math::Quat;
+
+use crate::{
+    hair_simulation::{
+        collider::{shape::ColliderShape, Collider},
+        config::{
+            ContactResponse, Damping, EnergyTerms, FailurePolicy, HairCollision, Inextensibility,
+            Integrator, NonlinearSolver, SceneConfig, SimulationConfig,
+        },
+        conversion::default_scene,
+        data::{
+            convert_to_na_quat, generate_hair_strand, HairStrand, Hairs, Head, SimulationData,
+            StrandParams,
+        },
+        pipeline::{
+            der::{
+                contact_response::apply_contact_response,
+                damping::damping_matrix,
+                do_der_in_chunks,
+                hair_collision::{apply_hair_collisions, closest_segment_parameters},
+                inextensibility::apply_inextensibility,
+                integrator::{step_scheme, DofState},
+                methods::{registered_energies, ElasticEnergy, EnergyContext},
+                state::StrandState,
+                step_strand, step_strand_guarded, DerStep,
+            },
+            utils::band_matrix::SymmetricBandMatrix,
+        },
+    },
+    physic_simulation::interfaces::{
+        EnergyDiagnostics, FailureAction, SimulationError, SimulationErrorKind,
+        SimulationTaskInterface,
+    },
+};
+extern crate nalgebra as na;
+
+const DELTA_TIME: f64 = 0.01;
+
+// A light, stiff strand straight from `from` to `to`, pinned at its root
+fn straight_strand(
+    seg_num: usize,
+    radius: f64,
+    from: na::Vector3<f64>,
+    to: na::Vector3<f64>,
+) -> HairStrand {
+    let params = StrandParams {
+        seg_num,
+        youngs: 1e9,
+        shear: 1e9,
+        radius,
+        last_pin: 0,
+        ..Default::default()
+    };
+    generate_hair_strand(&params, 1e-6, from, to)
+}
+
+// A strand lying on the ground plane, within the skin of the collider, with
+// every free vertex moving at `velocity`
+fn resolve(
+    velocity: na::Vector3<f64>,
+    restitution: f64,
+) -> (HairStrand, na::DVector<f64>, na::DVector<f64>) {
+    let mut strand = straight_strand(
+        4,
+        0.01,
+        na::Vector3::new(0.0, 0.001, 0.0),
+        na::Vector3::new(0.4, 0.001, 0.0),
+    );
+    strand.v_velocity.iter_mut().for_each(|v| *v = velocity);
+
+    let state = StrandState::new(&strand);
+    let mut position = state.to_dofs(&strand.v_position, &strand.l_twist);
+    let mut velocity = state.to_dofs(&strand.v_velocity, &strand.l_angular);
+    let colliders = [Collider {
+        static_friction: 0.4,
+        kinetic_friction: 0.3,
+        ..Collider::new(ColliderShape::Plane)
+    }];
+    let counts = apply_contact_response(
+        ContactResponse {
+            enabled: true,
+            restitution,
+        },
+        &strand,
+        &state,
+        &colliders,
+        DELTA_TIME,
+        &mut position,
+        &mut velocity,
+    );
+    assert_eq!(counts.total, strand.v_num - 1);
+    (strand, position, velocity)
+}
+
+fn free_velocities(strand: &HairStrand, velocity: &na::DVector<f64>) -> Vec<na::Vector3<f64>> {
+    let state = StrandState::new(strand);
+    (1..strand.v_num)
+        .map(|i| velocity.fixed_rows::<3>(state.vertex_dof(i)).into())
+        .collect()
+}
+
+#[test]
+fn static_friction_stops_slow_slip() {
+    let (strand, position, velocity) = resolve(na::Vector3::new(0.3, -1.0, 0.0), 0.0);
+    for v in free_velocities(&strand, &velocity) {
+        assert!(v.norm() < 1e-12, "{:?}", v);
+    }
+
+    // Back by the inbound motion of the step, outside the skin of the plane
+    let state = StrandState::new(&strand);
+    for i in 1..strand.v_num {
+        let y = position[state.vertex_dof(i) + 1];
+        assert!((y - (0.001 + DELTA_TIME)).abs() < 1e-12, "{}", y);
+    }
+}
+
+#[test]
+fn static_friction_holds_a_resting_vertex() {
+    // No inbound velocity, but pushed out of the skin of the plane
+    let (strand, _, velocity) = resolve(na::Vector3::new(0.03, 0.0, 0.0), 0.0);
+    for v in free_velocities(&strand, &velocity) {
+        assert!(v.norm() < 1e-12, "{:?}", v);
+    }
+}
+
+#[test]
+fn kinetic_friction_slows_fast_slip() {
+    let (strand, _, velocity) = resolve(na::Vector3::new(1.0, -1.0, 0.5), 0.0);
+    let slip = na::Vector3::<f64>::new(1.0, 0.0, 0.5);
+    let expected = slip * (1.0 - 0.3 / slip.norm());
+    for v in free_velocities(&strand, &velocity) {
+        assert!((v - expected).norm() < 1e-12, "{:?}", v);
+    }
+}
+
+#[test]
+fn restitution_returns_normal_velocity() {
+    let (strand, _, velocity) = resolve(na::Vector3::new(0.0, -1.0, 0.0), 0.5);
+    for v in free_velocities(&strand, &velocity) {
+        assert!(
+            (v - na::Vector3::new(0.0, 0.5, 0.0)).norm() < 1e-12,
+            "{:?}",
+            v
+        );
+    }
+
+    // Moving away from the surface keeps its velocity, the vertices are only
+    // projected out to the skin of the plane
+    let (strand, position, velocity) = resolve(na::Vector3::new(0.2, 0.5, 0.0), 0.5);
+    for v in free_velocities(&strand, &velocity) {
+        assert!(
+            (v - na::Vector3::new(0.2, 0.5, 0.0)).norm() < 1e-12,
+            "{:?}",
+            v
+        );
+    }
+    let state = StrandState::new(&strand);
+    for i in 1..strand.v_num {
+        let y = position[state.vertex_dof(i) + 1];
+        assert!((y - 0.002).abs() < 1e-12, "{}", y);
+    }
+}
+
+// A strand lying level on a plane tilted by `angle` about z, released for a
+// second. Returns how far its vertices slid down the slope on average.
+fn slide_down_incline(angle: f64) -> f64 {
+    let rotation = na::UnitQuaternion::from_euler_angles(0.0, 0.0, angle);
+    let normal = rotation * na::Vector3::y();
+    let downhill = -(rotation * na::Vector3::x());
+    let thickness = Collider::new(ColliderShape::Plane).thickness;
+
+    let mut strand = straight_strand(
+        4,
+        0.01,
+        thickness * normal - 0.2 * na::Vector3::z(),
+        thickness * normal + 0.2 * na::Vector3::z(),
+    );
+    strand.v_pinned[0] = false;
+    let start = strand.v_position.clone();
+
+    let mut config = SimulationConfig {
+        contact_response: ContactResponse {
+            enabled: true,
+            restitution: 0.0,
+        },
+        clamp_root_frame: false,
+        ..Default::default()
+    };
+    config.energies.head_contact = false;
+    let mut task_interface = SimulationTaskInterface {
+        delta_time: DELTA_TIME,
+        data: SimulationData {
+            hairs: Hairs {
+                strands: vec![strand],
+            },
+            colliders: vec![Collider {
+                static_friction: 0.4,
+                kinetic_friction: 0.3,
+                ..Collider::new(ColliderShape::Plane).with_transform(na::Vector3::zeros(), rotation)
+            }],
+            config,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    for iteration_cnt in 0..100 {
+        task_interface.iteration_cnt = iteration_cnt;
+        do_der_in_chunks(&mut task_interface, 1);
+    }
+    assert!(task_interface.errors.is_empty());
+
+    let strand = &task_interface.data.hairs.strands[0];
+    let slide: f64 = (0..strand.v_num)
+        .map(|i| (strand.v_position[i] - start[i]).dot(&downhill))
+        .sum();
+    slide / strand.v_num as f64
+}
+
+#[test]
+fn strand_rests_on_an_inclined_plane() {
+    // tan(angle) = 0.2, below the static coefficient: friction holds it
+    let slide = slide_down_incline(0.2_f64.atan());
+    assert!(slide.abs() < 1e-6, "{}", slide);
+
+    // tan(angle) = 1, above it: it slides, slowed by kinetic friction
+    let angle = std::f64::consts::FRAC_PI_4;
+    let acceleration = 9.8 * (angle.sin() - 0.3 * angle.cos());
+    let slide = slide_down_incline(angle);
+    assert!(
+        (slide - acceleration / 2.0).abs() < 0.1 * acceleration,
+        "{} {}",
+        slide,
+        acceleration / 2.0
+    );
+}
+
+#[test]
+fn closest_segment_points() {
+    let v = na::Vector3::new;
+    let cases = [
+        // Crossing
+        (
+            v(0.0, 0.0, 0.0),
+            v(2.0, 0.0, 0.0),
+            v(0.5, -1.0, 1.0),
+            v(0.5, 1.0, 1.0),
+            (0.25, 0.5),
+        ),
+        // Parallel and overlapping, from the start of the first
+        (
+            v(0.0, 0.0, 0.0),
+            v(1.0, 0.0, 0.0),
+            v(-0.5, 1.0, 0.0),
+            v(0.5, 1.0, 0.0),
+            (0.0, 0.5),
+        ),
+        // Clamped at both ends
+        (
+            v(0.0, 0.0, 0.0),
+            v(1.0, 0.0, 0.0),
+            v(2.0, 1.0, 0.0),
+            v(3.0, 2.0, 0.0),
+            (1.0, 0.0),
+        ),
+    ];
+    for (p0, p1, q0, q1, expected) in cases {
+        let (s, t) = closest_segment_parameters(&p0, &p1, &q0, &q1);
+        assert!(
+            (s - expected.0).abs() < 1e-12 && (t - expected.1).abs() < 1e-12,
+            "{:?} instead of {:?}",
+            (s, t),
+            expected
+        );
+    }
+}
+
+#[test]
+fn crossing_strands_are_pushed_apart() {
+    let strand = |from: na::Vector3<f64>, to: na::Vector3<f64>, velocity: f64| {
+        let mut strand = straight_strand(8, 0.01, from, to);
+        strand
+            .v_pinned
+            .iter_mut()
+            .for_each(|pinned| *pinned = false);
+        strand
+            .v_velocity
+            .iter_mut()
+            .for_each(|v| *v = na::Vector3::new(0.0, velocity, 0.0));
+        strand
+    };
+    let settings = HairCollision {
+        enabled: true,
+        ..Default::default()
+    };
+    let gap = settings.distance / 2.0;
+    let mut strands = vec![
+        strand(
+            na::Vector3::new(-0.2, gap / 2.0, 0.01),
+            na::Vector3::new(0.2, gap / 2.0, 0.01),
+            -0.02,
+        ),
+        strand(
+            na::Vector3::new(0.01, -gap / 2.0, -0.2),
+            na::Vector3::new(0.01, -gap / 2.0, 0.2),
+            0.02,
+        ),
+    ];
+    let momentum = |strands: &[HairStrand]| -> na::Vector3<f64> {
+        strands
+            .iter()
+            .flat_map(|strand| (0..strand.v_num).map(|i| strand.v_mass[i] * strand.v_velocity[i]))
+            .sum()
+    };
+    let before = momentum(&strands);
+
+    // Closest points of the closest pair of segments, and their normal velocity
+    let closest_pair = |strands: &[HairStrand]| {
+        let mut closest = (f64::INFINITY, 0, 0);
+        for i in 0..strands[0].l_num {
+            for j in 0..strands[1].l_num {
+                let (p0, p1) = (strands[0].v_position[i], strands[0].v_position[i + 1]);
+                let (q0, q1) = (strands[1].v_position[j], strands[1].v_position[j + 1]);
+                let (s, t) = closest_segment_parameters(&p0, &p1, &q0, &q1);
+                let distance = (p0.lerp(&p1, s) - q0.lerp(&q1, t)).norm();
+                if distance < closest.0 {
+                    closest = (distance, i, j);
+                }
+            }
+        }
+        (closest.1, closest.2)
+    };
+    let measure = |strands: &[HairStrand], (i, j): (usize, usize)| {
+        let (p0, p1) = (strands[0].v_position[i], strands[0].v_position[i + 1]);
+        let (q0, q1) = (strands[1].v_position[j], strands[1].v_position[j + 1]);
+        let (s, t) = closest_segment_parameters(&p0, &p1, &q0, &q1);
+        let offset = p0.lerp(&p1, s) - q0.lerp(&q1, t);
+        let va = strands[0].v_velocity[i].lerp(&strands[0].v_velocity[i + 1], s);
+        let vb = strands[1].v_velocity[j].lerp(&strands[1].v_velocity[j + 1], t);
+        (offset.norm(), (va - vb).dot(&offset.normalize()))
+    };
+    let pair = closest_pair(&strands);
+
+    let (_, approach) = measure(&strands, pair);
+
+    let start = strands.clone();
+    let pairs = apply_hair_collisions(settings, &mut strands, &[DELTA_TIME; 2]);
+    assert_eq!(pairs, 1);
+    assert!((momentum(&strands) - before).norm() < 1e-15);
+
+    // The approach is stopped and the strands moved apart. The closest points
+    // shift a little with the move.
+    let (distance, normal_speed) = measure(&strands, pair);
+    println!(
+        "gap {:e} -> {:e}, normal speed {:e} -> {:e}",
+        gap, distance, approach, normal_speed
+    );
+    assert!(distance > gap);
+    assert!(normal_speed.abs() < 1e-3 * approach.abs());
+
+    // Nothing happens when turned off
+    let mut strands_off = strands.clone();
+    let pairs = apply_hair_collisions(HairCollision::default(), &mut strands_off, &[DELTA_TIME; 2]);
+    assert_eq!(pairs, 0);
+    assert_eq!(strands_off[0].v_position, strands[0].v_position);
+
+    // Velocity changes move the positions by the factor of the step of each
+    // strand
+    let factors = [0.5 * DELTA_TIME, 2.0 * DELTA_TIME];
+    let mut scaled = start.clone();
+    apply_hair_collisions(settings, &mut scaled, &factors);
+    for (k, factor) in factors.into_iter().enumerate() {
+        for i in 0..scaled[k].v_num {
+            let change = scaled[k].v_velocity[i] - start[k].v_velocity[i];
+            let expected = strands[k].v_position[i] + (factor - DELTA_TIME) * change;
+            assert!((scaled[k].v_position[i] - expected).norm() < 1e-15);
+        }
+    }
+}
+
+// Bits of every vertex after a few steps of the default scene, its strands
+// split into chunks of chunk_size
+fn stepped_in_chunks(chunk_size: usize) -> Vec<u64> {
+    let mut data = default_scene(&SceneConfig::default()).unwrap().0;
+    data.config.hair_collision.enabled = true;
+    let mut task_interface = SimulationTaskInterface {
+        delta_time: data.config.time_stepping.frame_time,
+        data,
+        ..Default::default()
+    };
+    for iteration_cnt in 0..3 {
+        task_interface.iteration_cnt = iteration_cnt;
+        do_der_in_chunks(&mut task_interface, chunk_size);
+    }
+    assert!(task_interface.errors.is_empty());
+
+    task_interface
+        .data
+        .hairs
+        .strands
+        .iter()
+        .flat_map(|strand| strand.v_position.iter())
+        .flat_map(|p| p.iter().map(|x| x.to_bits()))
+        .collect()
+}
+
+#[test]
+fn chunking_does_not_change_the_result() {
+    let strand_num = default_scene(&SceneConfig::default())
+        .unwrap()
+        .0
+        .hairs
+        .strands
+        .len();
+    // A single chunk runs the strands one after the other on one task
+    let serial = stepped_in_chunks(strand_num);
+    for chunk_size in [1, 3, 16] {
+        assert!(
+            stepped_in_chunks(chunk_size) == serial,
+            "chunks of {} strands",
+            chunk_size
+        );
+    }
+}
+
+// A unit mass on a spring of period 1, from x = 1 at rest
+const SPRING_STIFFNESS: f64 = 4.0 * PI * PI;
+
+fn spring_energy((x, v): (f64, f64)) -> f64 {
+    0.5 * v * v + 0.5 * SPRING_STIFFNESS * x * x
+}
+
+// One step of the spring through the scheme of the integrator. The system is
+// linear, so a single Newton step solves it.
+fn spring_step(
+    integrator: Integrator,
+    delta_time: f64,
+    (x, v): (f64, f64),
+    previous: Option<((f64, f64), f64)>,
+) -> (f64, f64) {
+    let dofs = |(x, v): (f64, f64)| DofState {
+        position: na::DVector::from_element(1, x),
+        velocity: na::DVector::from_element(1, v),
+    };
+    let previous = previous.map(|(state, delta_time)| (dofs(state), delta_time));
+    let current = dofs((x, v));
+    let scheme = step_scheme(integrator, delta_time, &current, previous);
+
+    let mass = na::DVector::from_element(1, 1.0);
+    let mut velocity = scheme.initial_velocity(&current.position);
+    let gradient = SPRING_STIFFNESS * scheme.position(&velocity);
+    let hessian = integrator.needs_hessian().then(|| {
+        let mut hessian = SymmetricBandMatrix::zeros(1, 0);
+        hessian.add(0, 0, SPRING_STIFFNESS);
+        hessian
+    });
+    let (matrix, rhs) = scheme.newton_system(&mass, &velocity, &gradient, hessian);
+    velocity += matrix.ldlt().unwrap().solve(&rhs);
+
+    (scheme.position(&velocity)[0], velocity[0])
+}
+
+// Every state of the spring over the given steps
+fn spring_run(integrator: Integrator, steps: &[f64]) -> Vec<(f64, f64)> {
+    let mut states = vec![(1.0, 0.0)];
+    for (n, &delta_time) in steps.iter().enumerate() {
+        let previous = (n > 0).then(|| (states[n - 1], steps[n - 1]));
+        states.push(spring_step(integrator, delta_time, states[n], previous));
+    }
+    states
+}
+
+// Distance to the exact state at the end of the steps, with the velocity over
+// the angular frequency so that both weigh alike
+fn spring_error(integrator: Integrator, steps: &[f64]) -> f64 {
+    let (x, v) = *spring_run(integrator, steps).last().unwrap();
+    let time: f64 = steps.iter().sum();
+    let omega = SPRING_STIFFNESS.sqrt();
+    let (exact_x, exact_v) = ((omega * time).cos(), -omega * (omega * time).sin());
+    (x - exact_x).hypot((v - exact_v) / omega)
+}
+
+#[test]
+fn integrators_converge_at_their_order() {
+    for (integrator, order) in [
+        (Integrator::ImplicitEuler, 1),
+        (Integrator::SymplecticEuler, 1),
+        (Integrator::Bdf2, 2),
+    ] {
+        // Not a whole period, where symplectic Euler happens to be exact to O(h^2)
+        let coarse = spring_error(integrator, &[1.0 / 100.0; 80]);
+        let fine = spring_error(integrator, &[1.0 / 200.0; 160]);
+        let ratio = coarse / fine;
+        println!(
+            "{:?}: error {:e} then {:e}, ratio {}",
+            integrator, coarse, fine, ratio
+        );
+        let expected = 2f64.powi(order);
+        assert!(
+            (ratio - expected).abs() < 0.25 * expected,
+            "{:?}",
+            integrator
+        );
+    }
+}
+
+#[test]
+fn bdf2_keeps_its_order_with_varying_steps() {
+    // Steps alternating between h and h/2, as substeps of an adaptive frame
+    let varying = |cycles: usize| {
+        let h = 1.0 / (1.5 * cycles as f64);
+        (0..cycles).flat_map(|_| [h, 0.5 * h]).collect::<Vec<_>>()
+    };
+    let coarse = spring_error(Integrator::Bdf2, &varying(60));
+    let fine = spring_error(Integrator::Bdf2, &varying(120));
+    println!("varying steps: error {:e} then {:e}", coarse, fine);
+    assert!(coarse / fine > 3.5);
+}
+
+#[test]
+fn integrators_dissipate_energy_as_expected() {
+    let steps = [1.0 / 100.0; 1000];
+    let initial = spring_energy((1.0, 0.0));
+    let last_energy = |integrator| spring_energy(*spring_run(integrator, &steps).last().unwrap());
+
+    // Backward Euler damps the spring strongly, BDF2 far less, neither adds energy
+    let implicit_euler = last_energy(Integrator::ImplicitEuler);
+    let bdf2 = last_energy(Integrator::Bdf2);
+    println!(
+        "after 10 periods: implicit Euler {}, BDF2 {} of {}",
+        implicit_euler, bdf2, initial
+    );
+    assert!(implicit_euler < 0.1 * initial);
+    assert!(bdf2 > 0.9 * initial && bdf2 < initial);
+
+    // Symplectic Euler neither gains nor loses, the energy only oscillates by O(h)
+    let deviation = spring_run(Integrator::SymplecticEuler, &steps)
+        .into_iter()
+        .map(|state| (spring_energy(state) - initial).abs() / initial)
+        .fold(0.0, f64::max);
+    println!("symplectic Euler: largest deviation {}", deviation);
+    assert!(deviation < 0.1);
+}
+
+// Pulls the strand down like gravity, but its energy jumps by one as soon as
+// the strand leaves where it started, so no step along any direction is a
+// sufficient decrease
+struct Cliff {
+    start: Vec<na::Vector3<f64>>,
+}
+
+impl ElasticEnergy for Cliff {
+    fn name(&self) -> &'static str {
+        "cliff"
+    }
+
+    fn energy(&self, context: &EnergyContext) -> f64 {
+        if context.strand.v_position == self.start {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    fn gradient(&self, context: &EnergyContext, gradient: &mut na::DVector<f64>) {
+        let strand = context.strand;
+        for i in 0..strand.v_num {
+            gradient[context.state.vertex_dof(i) + 1] += 9.8 * strand.v_mass[i];
+        }
+    }
+}
+
+// A strand moving sideways after one step over the cliff
+fn step_over_cliff(nonlinear_solver: NonlinearSolver) -> HairStrand {
+    let mut strand = straight_strand(
+        4,
+        0.01,
+        na::Vector3::zeros(),
+        na::Vector3::new(0.4, 0.0, 0.0),
+    );
+    strand
+        .v_velocity
+        .iter_mut()
+        .for_each(|v| *v = na::Vector3::new(0.0, 0.0, 1.0));
+
+    let energies: Vec<Box<dyn ElasticEnergy>> = vec![Box::new(Cliff {
+        start: strand.v_position.clone(),
+    })];
+    let config = SimulationConfig {
+        nonlinear_solver,
+        ..Default::default()
+    };
+    let step = DerStep {
+        energies: &energies,
+        head: &Head::default(),
+        colliders: &[],
+        config: &config,
+        delta_time: DELTA_TIME,
+        iteration_cnt: 0,
+    };
+    let mut errors = Vec::new();
+    step_strand(&mut strand, 0, &step, &mut errors).unwrap();
+    assert!(errors.is_empty());
+    strand
+}
+
+#[test]
+fn failed_line_search_keeps_the_momentum() {
+    let newton = step_over_cliff(NonlinearSolver::Newton {
+        max_iterations: 5,
+        tolerance: 1e-9,
+    });
+    let linearized = step_over_cliff(NonlinearSolver::Linearized);
+
+    // The full step of the first iteration, as if there were no line search
+    let expected = na::Vector3::new(0.0, -9.8 * DELTA_TIME, 1.0);
+    for i in 1..newton.v_num {
+        assert!(
+            (newton.v_velocity[i] - expected).amax() < 1e-12,
+            "{:?}",
+            newton.v_velocity[i]
+        );
+        assert_eq!(newton.v_velocity[i], linearized.v_velocity[i]);
+        assert_eq!(newton.v_position[i], linearized.v_position[i]);
+    }
+}
+
+// A straight strand at rest along x, clear of the head, moving along z
+fn damped_strand(damping: &Damping) -> (HairStrand, StrandState, SymmetricBandMatrix) {
+    let mut strand = straight_strand(
+        6,
+        1e-4,
+        na::Vector3::new(1.0, 0.0, 0.0),
+        na::Vector3::new(1.3, 0.0, 0.0),
+    );
+    strand
+        .v_velocity
+        .iter_mut()
+        .for_each(|v| *v = na::Vector3::new(0.0, 0.0, 1.0));
+    let state = StrandState::new(&strand);
+
+    let head = Head::default();
+    let context = EnergyContext {
+        strand: &strand,
+        state: &state,
+        head: &head,
+        colliders: &[],
+    };
+    let mut hessian = SymmetricBandMatrix::zeros(state.dof_num(), state.bandwidth());
+    for energy in registered_energies(&EnergyTerms::default()) {
+        energy.hessian(&context, &mut hessian);
+    }
+
+    let mut mass = na::DVector::<f64>::zeros(state.dof_num());
+    for i in 0..strand.v_num {
+        for k in 0..3 {
+            mass[state.vertex_dof(i) + k] = strand.v_mass[i];
+        }
+    }
+    for i in 0..strand.l_num {
+        mass[state.twist_dof(i)] = strand.l_momemtum[i];
+    }
+
+    let matrix = damping_matrix(damping, &strand, &state, &mass, Some(&hessian)).unwrap();
+    (strand, state, matrix)
+}
+
+// Velocities of the strand moving as a rigid body
+fn rigid_velocity(
+    strand: &HairStrand,
+    state: &StrandState,
+    translation: na::Vector3<f64>,
+    rotation: na::Vector3<f64>,
+) -> na::DVector<f64> {
+    let vertex: Vec<_> = strand
+        .v_position
+        .iter()
+        .map(|p| translation + rotation.cross(p))
+        .collect();
+    state.to_dofs(&vertex, &vec![0.0; strand.l_num])
+}
+
+#[test]
+fn damping_removes_energy() {
+    let terms = [
+        Damping {
+            mass: 2.0,
+            ..Default::default()
+        },
+        Damping {
+            stiffness: 1e-3,
+            ..Default::default()
+        },
+        Damping {
+            air_drag: 0.6,
+            ..Default::default()
+        },
+    ];
+    for damping in terms {
+        let (_, _, matrix) = damped_strand(&damping);
+        let matrix = matrix.to_dense();
+
+        // The force -D v never does positive work, whatever the velocity
+        let eigenvalues = matrix.symmetric_eigenvalues();
+        assert!(
+            eigenvalues.min() >= -1e-12 * eigenvalues.amax(),
+            "{:?}: {}",
+            damping,
+            eigenvalues.min()
+        );
+        let velocity = na::DVector::from_fn(matrix.nrows(), |i, _| (i as f64 * 0.7).sin());
+        assert!(velocity.dot(&(&matrix * &velocity)) > 0.0, "{:?}", damping);
+    }
+}
+
+#[test]
+fn stiffness_damping_leaves_rigid_motion_alone() {
+    let (strand, state, matrix) = damped_strand(&Damping {
+        stiffness: 1e-3,
+        ..Default::default()
+    });
+    let matrix = matrix.to_dense();
+
+    // Translations, and rotations about axes across the strand
+    let motions = [
+        (na::Vector3::new(1.0, -2.0, 0.5), na::Vector3::zeros()),
+        (na::Vector3::zeros(), na::Vector3::new(0.0, 1.0, 0.0)),
+        (na::Vector3::zeros(), na::Vector3::new(0.0, 0.3, -2.0)),
+    ];
+    for (translation, rotation) in motions {
+        let velocity = rigid_velocity(&strand, &state, translation, rotation);
+        let force = &matrix * &velocity;
+        assert!(
+            force.amax() < 1e-9 * matrix.amax() * velocity.amax(),
+            "{:?} {:?}: {:e}",
+            translation,
+            rotation,
+            force.amax()
+        );
+    }
+}
+
+#[test]
+fn air_drag_leaves_sliding_alone() {
+    let (strand, state, matrix) = damped_strand(&Damping {
+        air_drag: 0.6,
+        ..Default::default()
+    });
+    let matrix = matrix.to_dense();
+
+    // Only the velocity across a segment is dragged
+    let sliding = rigid_velocity(
+        &strand,
+        &state,
+        na::Vector3::new(1.0, 0.0, 0.0),
+        na::Vector3::zeros(),
+    );
+    assert!((&matrix * &sliding).amax() < 1e-12 * matrix.amax());
+    let across = rigid_velocity(
+        &strand,
+        &state,
+        na::Vector3::new(0.0, 0.0, 1.0),
+        na::Vector3::zeros(),
+    );
+    assert!(across.dot(&(&matrix * &across)) > 0.0);
+}
+
+#[test]
+fn stiffness_damping_without_hessian_warns() {
+    let mut config = SimulationConfig {
+        damping: Damping {
+            stiffness: 1e-3,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    assert!(config.warnings().is_empty());
+    config.integrator = Integrator::SymplecticEuler;
+    assert_eq!(config.warnings().len(), 1);
+}
+
+// A strand pinned at its first two vertices whose free ones were pushed a few
+// percent off their rest lengths by a step of DELTA_TIME, corrected by the settings.
+// Returns the strand, and the positions and velocities before and after.
+fn corrected(
+    settings: Inextensibility,
+) -> (HairStrand, [na::DVector<f64>; 2], [na::DVector<f64>; 2]) {
+    let mut strand = straight_strand(
+        8,
+        1e-4,
+        na::Vector3::zeros(),
+        na::Vector3::new(0.0, -0.4, 0.0),
+    );
+    strand.v_pinned[..=1].fill(true);
+    for i in 2..strand.v_num {
+        let phase = i as f64 * 1.3;
+        strand.v_position[i] +=
+            0.002 * na::Vector3::new(phase.sin(), -(2.0 * phase).cos().abs(), phase.cos());
+        strand.v_velocity[i] = na::Vector3::new(phase.cos(), -1.0, phase.sin());
+    }
+
+    let state = StrandState::new(&strand);
+    let position = state.to_dofs(&strand.v_position, &strand.l_twist);
+    let velocity = state.to_dofs(&strand.v_velocity, &strand.l_angular);
+    let (mut corrected_position, mut corrected_velocity) = (position.clone(), velocity.clone());
+    apply_inextensibility(
+        settings,
+        &strand,
+        &state,
+        DELTA_TIME,
+        &mut corrected_position,
+        &mut corrected_velocity,
+    );
+    (
+        strand,
+        [position, corrected_position],
+        [velocity, corrected_velocity],
+    )
+}
+
+fn edge_strains(strand: &HairStrand, position: &na::DVector<f64>) -> Vec<f64> {
+    let state = StrandState::new(strand);
+    let vertex = |i: usize| position.fixed_rows::<3>(state.vertex_dof(i)).into_owned();
+    (0..strand.l_num)
+        .map(|j| (vertex(j + 1) - vertex(j)).norm() / strand.l_rest_length[j] - 1.0)
+        .collect()
+}
+
+#[test]
+fn inextensibility_meets_its_tolerance() {
+    let settings = [
+        (
+            Inextensibility::StrainLimiting {
+                max_strain: 0.01,
+                iterations: 50,
+            },
+            0.01 * (1.0 + 1e-9),
+        ),
+        (
+            Inextensibility::FastProjection {
+                tolerance: 1e-6,
+                max_iterations: 20,
+            },
+            1e-6,
+        ),
+    ];
+    for (settings, bound) in settings {
+        let (strand, [position, corrected_position], [velocity, corrected_velocity]) =
+            corrected(settings);
+        let state = StrandState::new(&strand);
+
+        let before = edge_strains(&strand, &position);
+        assert!(before.iter().any(|strain| strain.abs() > 0.02));
+        for (j, strain) in edge_strains(&strand, &corrected_position)
+            .into_iter()
+            .enumerate()
+        {
+            assert!(
+                strain.abs() <= bound,
+                "{:?} edge {}: {}",
+                settings,
+                j,
+                strain
+            );
+        }
+
+        for i in 0..strand.v_num {
+            let dof = state.vertex_dof(i);
+            let moved = corrected_position.fixed_rows::<3>(dof) - position.fixed_rows::<3>(dof);
+            let sped = corrected_velocity.fixed_rows::<3>(dof) - velocity.fixed_rows::<3>(dof);
+            if strand.v_pinned[i] {
+                assert_eq!(moved, na::Vector3::zeros(), "{:?} vertex {}", settings, i);
+                assert_eq!(sped, na::Vector3::zeros(), "{:?} vertex {}", settings, i);
+            }
+            // The velocity takes the correction of the position over the step
+            assert!(
+                (sped - moved / DELTA_TIME).amax() <= 1e-12 * sped.amax().max(1.0),
+                "{:?} vertex {}",
+                settings,
+                i
+            );
+        }
+        // Twists are left alone
+        for j in 0..strand.l_num {
+            let dof = state.twist_dof(j);
+            assert_eq!(corrected_position[dof], position[dof]);
+            assert_eq!(corrected_velocity[dof], velocity[dof]);
+        }
+    }
+}
+
+#[test]
+fn pinned_vertices_and_root_frame_follow_the_head() {
+    let mut head = Head {
+        position: na::Vector3::new(0.0, 1.0, 0.0),
+        ..Default::default()
+    };
+    let mut strand = straight_strand(
+        6,
+        1e-4,
+        na::Vector3::new(0.0, 1.1, 0.0),
+        na::Vector3::new(0.0, 1.7, 0.0),
+    );
+    strand.v_pinned[..=2].fill(true);
+    strand.attach_to_head(&head);
+    let start = strand.v_position.clone();
+
+    head.position = na::Vector3::new(0.05, 1.0, 0.0);
+    head.rotation = Quat::from_rotation_x(0.3) * Quat::from_rotation_y(0.4);
+    strand.follow_head(&head, DELTA_TIME);
+
+    // Rigidly carried by the head, at the velocity that takes them there
+    let rotation = convert_to_na_quat(head.rotation);
+    let carried =
+        |p: &na::Vector3<f64>| rotation * (p - na::Vector3::new(0.0, 1.0, 0.0)) + head.position;
+    for (i, start) in start.iter().enumerate() {
+        if strand.v_pinned[i] {
+            let target = carried(start);
+            assert!(
+                (strand.v_position[i] - target).amax() < 1e-12,
+                "vertex {}",
+                i
+            );
+            let velocity = (target - start) / DELTA_TIME;
+            assert!(
+                (strand.v_velocity[i] - velocity).amax() < 1e-9,
+                "vertex {}",
+                i
+            );
+        } else {
+            assert_eq!(strand.v_position[i], *start);
+        }
+    }
+    let pinned = strand.v_position[..3].to_vec();
+
+    let config = SimulationConfig::default();
+    assert!(config.clamp_root_frame);
+    let energies = registered_energies(&config.energies);
+    let step = DerStep {
+        energies: &energies,
+        head: &head,
+        colliders: &[],
+        config: &config,
+        delta_time: DELTA_TIME,
+        iteration_cnt: 0,
+    };
+    let mut errors = Vec::new();
+    step_strand(&mut strand, 0, &step, &mut errors).unwrap();
+    assert!(errors.is_empty());
+
+    // The solve leaves the pinned vertices where the head put them
+    assert_eq!(&strand.v_position[..3], &pinned[..]);
+
+    // and turns the root material frame onto the follicle direction
+    let director = rotation * strand.root_director.unwrap();
+    let root = &StrandState::new(&strand).reference_frame[0];
+    let m1 = root.n * strand.l_twist[0].cos() + root.b * strand.l_twist[0].sin();
+    assert!((m1 - director).amax() < 1e-9, "{:?} {:?}", m1, director);
+}
+
+// Strands hanging side by side, clear of the head and of each other
+fn hanging_strands(num: usize) -> Vec<HairStrand> {
+    (0..num)
+        .map(|i| {
+            let x = 0.1 * i as f64;
+            straight_strand(
+                4,
+                1e-4,
+                na::Vector3::new(x, 2.0, 0.0),
+                na::Vector3::new(x, 1.6, 0.0),
+            )
+        })
+        .collect()
+}
+
+// Leaves the tip with slightly less than no inertia along x, an indefinite
+// system that the largest regularization step makes definite again
+struct Indefinite;
+
+impl ElasticEnergy for Indefinite {
+    fn name(&self) -> &'static str {
+        "indefinite"
+    }
+
+    fn energy(&self, _context: &EnergyContext) -> f64 {
+        0.0
+    }
+
+    fn gradient(&self, _context: &EnergyContext, _gradient: &mut na::DVector<f64>) {}
+
+    fn hessian(&self, context: &EnergyContext, hessian: &mut SymmetricBandMatrix) {
+        let tip = context.strand.v_num - 1;
+        let dof = context.state.vertex_dof(tip);
+        let mass = context.strand.v_mass[tip];
+        hessian.add(dof, dof, -(1.0 + 1e-6) * mass / DELTA_TIME.powi(2));
+    }
+}
+
+fn step_indefinite(
+    failure_policy: FailurePolicy,
+) -> (HairStrand, HairStrand, Vec<SimulationError>) {
+    let mut strand = hanging_strands(1).remove(0);
+    strand
+        .v_velocity
+        .iter_mut()
+        .skip(1)
+        .for_each(|v| *v = na::Vector3::new(0.0, -0.5, 0.2));
+    strand.record_history(DELTA_TIME);
+    let start = strand.clone();
+
+    let energies: Vec<Box<dyn ElasticEnergy>> = vec![Box::new(Indefinite)];
+    let config = SimulationConfig {
+        failure_policy,
+        ..Default::default()
+    };
+    let step = DerStep {
+        energies: &energies,
+        head: &Head::default(),
+        colliders: &[],
+        config: &config,
+        delta_time: DELTA_TIME,
+        iteration_cnt: 7,
+    };
+    let mut errors = Vec::new();
+    step_strand_guarded(&mut strand, 2, &step, &mut errors);
+    (start, strand, errors)
+}
+
+#[test]
+fn regularization_recovers_an_indefinite_system() {
+    let (start, strand, errors) = step_indefinite(FailurePolicy::Regularize);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].iteration_cnt, 7);
+    assert_eq!(errors[0].strand, 2);
+    assert_eq!(errors[0].kind, SimulationErrorKind::SingularSystem);
+    assert_eq!(errors[0].action, FailureAction::Regularized);
+
+    // The step went through
+    assert!(!strand.frozen);
+    assert_ne!(strand.v_position, start.v_position);
+    assert!(strand
+        .v_position
+        .iter()
+        .chain(strand.v_velocity.iter())
+        .all(|v| v.iter().all(|x| x.is_finite())));
+}
+
+#[test]
+fn reverting_stops_the_strand_where_it_was() {
+    for (failure_policy, action) in [
+        (FailurePolicy::Revert, FailureAction::Reverted),
+        (FailurePolicy::Freeze, FailureAction::Frozen),
+    ] {
+        let (start, strand, errors) = step_indefinite(failure_policy);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].strand, 2);
+        assert_eq!(errors[0].kind, SimulationErrorKind::SingularSystem);
+        assert_eq!(errors[0].action, action);
+
+        assert_eq!(strand.v_position, start.v_position);
+        assert_eq!(strand.l_twist, start.l_twist);
+        assert!(strand.v_velocity.iter().all(|v| *v == na::Vector3::zeros()));
+        assert!(strand.l_angular.iter().all(|&w| w == 0.0));
+        assert!(start.has_history() && !strand.has_history());
+        assert_eq!(strand.frozen, failure_policy == FailurePolicy::Freeze);
+    }
+}
+
+// Steps of strands in chunks of two, the fourth with a NaN vertex
+fn step_with_nan(failure_policy: FailurePolicy, steps: u64) -> SimulationTaskInterface {
+    let mut strands = hanging_strands(6);
+    strands[3].v_position[2].x = f64::NAN;
+    let mut task_interface = SimulationTaskInterface {
+        delta_time: DELTA_TIME,
+        data: SimulationData {
+            hairs: Hairs { strands },
+            config: SimulationConfig {
+                failure_policy,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    for iteration_cnt in 0..steps {
+        task_interface.iteration_cnt = iteration_cnt;
+        do_der_in_chunks(&mut task_interface, 2);
+    }
+    task_interface
+}
+
+#[test]
+fn failed_strands_are_reported_by_their_global_index() {
+    // Nothing recovers from a NaN, so regularization falls back to reverting
+    for (failure_policy, action) in [
+        (FailurePolicy::Regularize, FailureAction::Reverted),
+        (FailurePolicy::Revert, FailureAction::Reverted),
+    ] {
+        let task_interface = step_with_nan(failure_policy, 2);
+        let errors = &task_interface.errors;
+        assert_eq!(errors.len(), 2, "{:?}", failure_policy);
+        for (iteration_cnt, error) in errors.iter().enumerate() {
+            assert_eq!(error.iteration_cnt, iteration_cnt as u64);
+            assert_eq!(error.strand, 3);
+            assert_eq!(error.kind, SimulationErrorKind::NonFiniteSystem);
+            assert_eq!(error.action, action);
+        }
+        assert!(!task_interface.data.hairs.strands[3].frozen);
+    }
+
+    // A frozen strand is left out of the later steps
+    let task_interface = step_with_nan(FailurePolicy::Freeze, 3);
+    let errors = &task_interface.errors;
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].strand, 3);
+    assert_eq!(errors[0].action, FailureAction::Frozen);
+
+    let strands = &task_interface.data.hairs.strands;
+    assert!(strands[3].frozen);
+    assert_eq!(task_interface.diagnostics.strands.len(), strands.len());
+    for (i, strand) in strands.iter().enumerate() {
+        assert_eq!(strand.frozen, i == 3);
+        if i != 3 {
+            assert!(strand
+                .v_position
+                .iter()
+                .all(|p| p.iter().all(|x| x.is_finite())));
+        }
+    }
+}
+
+#[test]
+fn diagnostics_report_each_term_and_the_momentum() {
+    // Three vertices hanging straight down, the lower edge stretched by a
+    // tenth, moving at known speeds
+    let strands: Vec<HairStrand> = (1..=2)
+        .map(|k| {
+            let scale = k as f64;
+            let root = na::Vector3::new(0.1 * scale, 2.0, 0.0);
+            let mut strand = straight_strand(2, 1e-4, root, root - na::Vector3::new(0.0, 0.2, 0.0));
+            strand.v_position[2].y -= 0.01;
+            strand.v_velocity[1] = scale * na::Vector3::new(1.0, 0.0, 0.0);
+            strand.v_velocity[2] = scale * na::Vector3::new(0.0, -2.0, 0.5);
+            strand.l_angular = vec![0.5 * scale, -scale];
+            strand
+        })
+        .collect();
+
+    let stretch = PI * 1e-4_f64.powi(2) * 1e9 / 2.0 * 0.1_f64.powi(2) * 0.1;
+    let expected: Vec<(f64, f64, na::Vector3<f64>)> = strands
+        .iter()
+        .map(|strand| {
+            let mut gravity = 0.0;
+            let mut kinetic = 0.0;
+            let mut momentum = na::Vector3::zeros();
+            for i in 0..3 {
+                let m = strand.v_mass[i];
+                gravity += 9.8 * m * strand.v_position[i].y;
+                kinetic += 0.5 * m * strand.v_velocity[i].norm_squared();
+                momentum += m * strand.v_velocity[i];
+            }
+            for j in 0..2 {
+                kinetic += 0.5 * strand.l_momemtum[j] * strand.l_angular[j].powi(2);
+            }
+            (gravity, kinetic, momentum)
+        })
+        .collect();
+
+    let mut task_interface = SimulationTaskInterface {
+        delta_time: DELTA_TIME,
+        data: SimulationData {
+            hairs: Hairs { strands },
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    do_der_in_chunks(&mut task_interface, 1);
+    assert!(task_interface.errors.is_empty());
+
+    let diagnostics = &task_interface.diagnostics;
+    assert_eq!(diagnostics.strands.len(), 2);
+    let term = |energy: &EnergyDiagnostics, name: &str| {
+        energy
+            .potential
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, e)| *e)
+            .unwrap()
+    };
+    for (strand, (gravity, kinetic, momentum)) in diagnostics.strands.iter().zip(expected.iter()) {
+        assert!((term(strand, "stretch") - stretch).abs() < 1e-12 * stretch);
+        assert!((term(strand, "gravity") - gravity).abs() < 1e-12 * gravity.abs());
+        // Straight, untwisted and clear of the head
+        for name in ["bend", "twist", "head_contact"] {
+            assert!(term(strand, name).abs() < 1e-20, "{}", name);
+        }
+        assert!((strand.kinetic - kinetic).abs() < 1e-12 * kinetic);
+        assert!((strand.momentum - momentum).amax() < 1e-18);
+        assert!(momentum.norm() > 1e-6);
+    }
+
+    // The total is the sum over the strands
+    let total = &diagnostics.total;
+    for (name, energy) in total.potential.iter() {
+        let sum: f64 = diagnostics.strands.iter().map(|s| term(s, name)).sum();
+        assert_eq!(*energy, sum, "{}", name);
+    }
+    assert_eq!(
+        total.potential.len(),
+        diagnostics.strands[0].potential.len()
+    );
+    assert_eq!(
+        total.kinetic,
+        diagnostics.strands[0].kinetic + diagnostics.strands[1].kinetic
+    );
+    assert_eq!(
+        total.momentum,
+        diagnostics.strands[0].momentum + diagnostics.strands[1].momentum
+    );
+}
@@ -1,6 +1,6 @@
 use bevy::{log::info, utils::info};
 
-use crate::hair_simulation::data::Frame;
+use crate::hair_simulation::{data::Frame, pipeline::utils::band_matrix::SymmetricBandMatrix};
 
 use super::MAX_T_DOT;
 extern crate nalgebra as na;
@@ -23,6 +23,33 @@ pub fn parallel_transport(
     (b.normalize(), n0.normalize(), n1.normalize())
 }
 
+// Rotate `u` by the minimal rotation that takes t0 onto t1.
+pub fn parallel_transport_vector(
+    u: na::Vector3<f64>,
+    t0: na::Vector3<f64>,
+    t1: na::Vector3<f64>,
+) -> na::Vector3<f64> {
+    let b = t0.cross(&t1);
+    if b.norm() < 1e-12 {
+        return u;
+    }
+    let b = b.normalize();
+    let n0 = t0.cross(&b);
+    let n1 = t1.cross(&b);
+
+    u.dot(&t0) * t1 + u.dot(&n0) * n1 + u.dot(&b) * b
+}
+
+// Transport a whole frame onto a new tangent, keeping it orthonormal.
+pub fn transport_frame(frame: &Frame, t: na::Vector3<f64>) -> Frame {
+    let n = parallel_transport_vector(frame.n, frame.t, t);
+    let n = (n - n.dot(&t) * t).normalize();
+    let b = parallel_transport_vector(frame.b, frame.t, t);
+    let b = (b - b.dot(&t) * t - b.dot(&n) * n).normalize();
+
+    Frame { b, n, t }
+}
+
 pub fn partial_kappa(
     e_vec: &Vec<na::Vector3<f64>>,
     t_tilde: &na::Vector3<f64>,
@@ -48,18 +75,19 @@ pub fn partial_kappa(
     let mut kappa_index = 0;
     let mut latter_sign = 1;
 
+    // kappa is laid out as (kappa1^(i-1), kappa1^i, kappa2^(i-1), kappa2^i)
     if kappa_up_index_b {
         kappa_up_index = index - 1;
     } else {
         kappa_up_index = index;
-        kappa_index = kappa_index + 2;
+        kappa_index = kappa_index + 1;
     }
 
     let m;
     if kappa_sub_index_b {
         m = material_frame[kappa_up_index].b;
     } else {
-        kappa_index = kappa_index + 1;
+        kappa_index = kappa_index + 2;
         latter_sign = latter_sign * -1;
         m = material_frame[kappa_up_index].n;
     }
@@ -309,33 +337,46 @@ pub fn calc_nabla_i_kappa_i1(
     ])
 }
 
+// Callers add both the (r, c) and the (c, r) block of a symmetric
+// contribution, only the entries in the lower triangle are kept so that every
+// one is counted once.
 pub fn add_to_matrix(
-    matrix: &mut na::DMatrix<f64>,
+    matrix: &mut SymmetricBandMatrix,
     value: &na::Matrix3<f64>,
     start: (usize, usize),
 ) {
-    //Check NaN
-    if value[(0, 0)].is_nan()
-        || value[(0, 1)].is_nan()
-        || value[(0, 2)].is_nan()
-        || value[(1, 0)].is_nan()
-        || value[(1, 1)].is_nan()
-        || value[(1, 2)].is_nan()
-        || value[(2, 0)].is_nan()
-        || value[(2, 1)].is_nan()
-        || value[(2, 2)].is_nan()
-    {
-        return;
+    for r in 0..3 {
+        for c in 0..3 {
+            let (row, col) = (start.0 + r, start.1 + c);
+            if col <= row {
+                matrix.add(row, col, value[(r, c)]);
+            }
+        }
     }
-    matrix[(start.0, start.1)] += value[(0, 0)];
-    matrix[(start.0, start.1 + 1)] += value[(0, 1)];
-    matrix[(start.0, start.1 + 2)] += value[(0, 2)];
+}
 
-    matrix[(start.0 + 1, start.1)] += value[(1, 0)];
-    matrix[(start.0 + 1, start.1 + 1)] += value[(1, 1)];
-    matrix[(start.0 + 1, start.1 + 2)] += value[(1, 2)];
+// Symmetric local matrix over the given dofs, its lower triangle in the
+// global numbering is kept
+pub fn add_local_to_matrix<const N: usize>(
+    matrix: &mut SymmetricBandMatrix,
+    dofs: &[usize; N],
+    value: &na::SMatrix<f64, N, N>,
+) {
+    for r in 0..N {
+        for c in 0..N {
+            if dofs[c] <= dofs[r] {
+                matrix.add(dofs[r], dofs[c], value[(r, c)]);
+            }
+        }
+    }
+}
 
-    matrix[(start.0 + 2, start.1)] += value[(2, 0)];
-    matrix[(start.0 + 2, start.1 + 1)] += value[(2, 1)];
-    matrix[(start.0 + 2, start.1 + 2)] += value[(2, 2)];
+pub fn add_local_to_vector<const N: usize>(
+    vector: &mut na::DVector<f64>,
+    dofs: &[usize; N],
+    value: &na::SVector<f64, N>,
+) {
+    for r in 0..N {
+        vector[dofs[r]] += value[r];
+    }
 }
@@ -1,2 +1,6 @@
 pub mod der;
+pub mod stepping;
 pub mod utils;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,143 @@
+use bevy::log::info;
+
+use crate::{
+    hair_simulation::{
+        config::{NonlinearSolver, TimeStepping},
+        data::SimulationData,
+        pipeline::der::{do_der, total_energy},
+    },
+    physic_simulation::interfaces::{SimulationDiagnostics, SimulationTaskInterface},
+};
+
+// Energy growth below this many joules never rejects a substep
+pub const ENERGY_JUMP_FLOOR: f64 = 1e-6;
+
+// Largest substep allowed by the vertex speeds and edge strain rates
+pub fn stable_substep(data: &SimulationData, settings: &TimeStepping) -> f64 {
+    let mut substep = f64::INFINITY;
+    for strand in data.hairs.strands.iter().filter(|strand| !strand.frozen) {
+        // No vertex moves further than a fraction of the shortest segment
+        let min_length = strand
+            .l_rest_length
+            .iter()
+            .fold(f64::INFINITY, |min, &l| min.min(l));
+        let max_speed = strand
+            .v_velocity
+            .iter()
+            .fold(0.0, |max: f64, v| max.max(v.norm()));
+        if max_speed > 0.0 {
+            substep = substep.min(settings.max_displacement * min_length / max_speed);
+        }
+
+        // No edge strain changes by more than max_strain_change
+        for i in 0..strand.l_num {
+            let t = (strand.v_position[i + 1] - strand.v_position[i]).normalize();
+            let relative = strand.v_velocity[i + 1] - strand.v_velocity[i];
+            let strain_rate = (relative.dot(&t) / strand.l_rest_length[i]).abs();
+            if strain_rate > 0.0 {
+                substep = substep.min(settings.max_strain_change / strain_rate);
+            }
+        }
+    }
+    substep
+}
+
+// Shrink the next substep while Newton runs out of iterations, grow it back
+// once it converges quickly
+fn convergence_scale(scale: f64, solver: NonlinearSolver, iterations: &[usize]) -> f64 {
+    let NonlinearSolver::Newton { max_iterations, .. } = solver else {
+        return 1.0;
+    };
+    let used = iterations.iter().max().copied().unwrap_or(0);
+    if used >= max_iterations {
+        scale * 0.5
+    } else if used <= max_iterations / 4 {
+        (scale * 2.0).min(1.0)
+    } else {
+        scale
+    }
+}
+
+// Advance the strands by `delta_time`, split into substeps of `do_der`
+pub fn do_frame(task_interface: &mut SimulationTaskInterface) {
+    let frame_time = task_interface.delta_time;
+    let settings = task_interface.data.config.time_stepping.clone();
+    let solver = task_interface.data.config.nonlinear_solver;
+
+    let min_substep = frame_time / settings.max_substeps.max(1) as f64;
+    let max_substep = frame_time / settings.min_substeps.max(1) as f64;
+
+    let mut diagnostics: Option<SimulationDiagnostics> = None;
+    let mut energy = settings
+        .adaptive
+        .then(|| total_energy(&task_interface.data));
+    let mut scale = 1.0;
+    let mut remaining = frame_time;
+    let mut substeps = 0;
+    let mut retries = 0;
+
+    while remaining > 1e-9 * frame_time {
+        let mut substep = max_substep.min(remaining);
+        if settings.adaptive {
+            let stable = stable_substep(&task_interface.data, &settings) * scale;
+            substep = stable.clamp(min_substep.min(substep), substep);
+        }
+        // Split what is left evenly instead of leaving a sliver at the end
+        substep = remaining / ((remaining / substep) - 1e-9).ceil().max(1.0);
+
+        loop {
+            let hairs = energy.map(|_| task_interface.data.hairs.clone());
+            let error_cnt = task_interface.errors.len();
+
+            task_interface.delta_time = substep;
+            do_der(task_interface);
+
+            let (Some(before), Some(hairs)) = (energy, hairs) else {
+                break;
+            };
+            let after = total_energy(&task_interface.data);
+            let allowed = settings.max_energy_increase * before.abs().max(ENERGY_JUMP_FLOOR);
+            // Not finite counts as a jump too
+            let jumped = after.is_nan() || after > before + allowed;
+            if jumped && substep > min_substep * (1.0 + 1e-9) {
+                // Redo the substep from its start with half the step. The strands
+                // come back with their history, which BDF2 weighs by the new step.
+                task_interface.data.hairs = hairs;
+                task_interface.errors.truncate(error_cnt);
+                substep = (substep * 0.5).max(min_substep);
+                retries += 1;
+                continue;
+            }
+            energy = Some(after);
+            break;
+        }
+
+        let step_diagnostics = std::mem::take(&mut task_interface.diagnostics);
+        scale = convergence_scale(scale, solver, &step_diagnostics.iterations);
+        match diagnostics.as_mut() {
+            Some(diagnostics) => {
+                for (total, iterations) in diagnostics
+                    .iterations
+                    .iter_mut()
+                    .zip(step_diagnostics.iterations)
+                {
+                    *total += iterations;
+                }
+                diagnostics.contacts.extend(step_diagnostics.contacts);
+            }
+            None => diagnostics = Some(step_diagnostics),
+        }
+
+        remaining -= substep;
+        substeps += 1;
+    }
+
+    if retries > 0 {
+        info!("{} substep(s) redone after an energy jump", retries);
+    }
+
+    task_interface.delta_time = frame_time;
+    task_interface.diagnostics = diagnostics.unwrap_or_default();
+    task_interface.substeps = substeps;
+    task_interface.substep_retries = retries;
+}
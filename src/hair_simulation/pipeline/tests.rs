@@ -0,0 +1,165 @@
+use crate::{
+    hair_simulation::{
+        config::{Integrator, SimulationConfig, TimeStepping},
+        data::{generate_straight_hair_strand, SimulationData},
+        pipeline::stepping::{do_frame, stable_substep},
+    },
+    physic_simulation::interfaces::SimulationTaskInterface,
+};
+extern crate nalgebra as na;
+
+// Edges of a strand from `strand_data`
+const SEGMENT_LENGTH: f64 = 0.05;
+
+// A horizontal strand of 8 edges pinned at its root, its free vertices
+// moving at velocity
+fn strand_data(velocity: na::Vector3<f64>, config: SimulationConfig) -> SimulationData {
+    let mut strand = generate_straight_hair_strand(
+        1e-6,
+        8,
+        na::Vector3::zeros(),
+        na::Vector3::new(8.0 * SEGMENT_LENGTH, 0.0, 0.0),
+        1e9,
+        1e9,
+        0.01,
+        0,
+    );
+    for i in 1..strand.v_num {
+        strand.v_velocity[i] = velocity;
+    }
+
+    let mut data = SimulationData {
+        config,
+        ..Default::default()
+    };
+    // Well clear of the strand
+    data.head.position = na::Vector3::new(0.0, 10.0, 0.0);
+    data.hairs.strands.push(strand);
+    data
+}
+
+fn run_frame(data: SimulationData) -> SimulationTaskInterface {
+    let mut task_interface = SimulationTaskInterface {
+        delta_time: data.config.time_stepping.frame_time,
+        data,
+        ..Default::default()
+    };
+    do_frame(&mut task_interface);
+    task_interface
+}
+
+fn config_with(time_stepping: TimeStepping) -> SimulationConfig {
+    SimulationConfig {
+        time_stepping,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn fixed_substeps_split_the_frame_evenly() {
+    let time_stepping = TimeStepping {
+        frame_time: 0.01,
+        adaptive: false,
+        min_substeps: 3,
+        ..Default::default()
+    };
+    let task_interface = run_frame(strand_data(
+        na::Vector3::new(0.0, 0.0, 2.0),
+        config_with(time_stepping),
+    ));
+
+    assert_eq!(task_interface.substeps, 3);
+    assert_eq!(task_interface.substep_retries, 0);
+    let last = task_interface.data.hairs.strands[0].previous_delta_time;
+    assert!((last - 0.01 / 3.0).abs() < 1e-15, "{}", last);
+    assert_eq!(task_interface.delta_time, 0.01);
+}
+
+#[test]
+fn substeps_follow_speed_and_strain_rate() {
+    let settings = TimeStepping::default();
+
+    // No vertex moves by more than half the shortest edge
+    let data = strand_data(
+        na::Vector3::new(0.0, 0.0, 2.0),
+        config_with(settings.clone()),
+    );
+    let substep = stable_substep(&data, &settings);
+    assert!(
+        (substep - 0.5 * SEGMENT_LENGTH / 2.0).abs() < 1e-12,
+        "{}",
+        substep
+    );
+
+    // The tip pulling on its edge changes the strain by 0.01 at most
+    let mut data = strand_data(na::Vector3::zeros(), config_with(settings.clone()));
+    data.hairs.strands[0].v_velocity[8] = na::Vector3::new(1.0, 0.0, 0.0);
+    let substep = stable_substep(&data, &settings);
+    assert!(
+        (substep - 0.01 * SEGMENT_LENGTH).abs() < 1e-12,
+        "{}",
+        substep
+    );
+
+    // At rest nothing limits the step
+    let data = strand_data(na::Vector3::zeros(), config_with(settings.clone()));
+    assert_eq!(stable_substep(&data, &settings), f64::INFINITY);
+
+    // A frame of 0.05 s at 2 m/s needs at least 4 substeps of 0.0125 s
+    let time_stepping = TimeStepping {
+        frame_time: 0.05,
+        ..settings
+    };
+    let task_interface = run_frame(strand_data(
+        na::Vector3::new(0.0, 0.0, 2.0),
+        config_with(time_stepping),
+    ));
+    assert!(task_interface.substeps >= 4, "{}", task_interface.substeps);
+    assert!(task_interface.substeps <= 16);
+    assert!(task_interface.data.hairs.strands[0].previous_delta_time <= 0.0125 + 1e-12);
+}
+
+// Symplectic Euler on a stretched strand, far beyond its stable step. At rest
+// nothing bounds the first substep, only the energy check can catch it.
+fn exploding_data(max_substeps: usize) -> SimulationData {
+    let config = SimulationConfig {
+        integrator: Integrator::SymplecticEuler,
+        time_stepping: TimeStepping {
+            frame_time: 1e-3,
+            max_substeps,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut data = strand_data(na::Vector3::zeros(), config);
+    for (i, position) in data.hairs.strands[0].v_position.iter_mut().enumerate() {
+        position.x *= 1.01;
+        position.y = 1e-3 * (i as f64).sin();
+    }
+    data
+}
+
+#[test]
+fn energy_jump_redoes_the_substep() {
+    let task_interface = run_frame(exploding_data(16));
+    println!(
+        "{} substeps, {} redone",
+        task_interface.substeps, task_interface.substep_retries
+    );
+    // Halved from the whole frame down to the shortest substep at least
+    assert!(task_interface.substep_retries >= 4);
+    let last = task_interface.data.hairs.strands[0].previous_delta_time;
+    assert!((last - 1e-3 / 16.0).abs() < 1e-15, "{}", last);
+}
+
+#[test]
+fn min_substep_bounds_the_retries() {
+    // The frame is already the shortest substep, it is kept whatever the energy
+    let task_interface = run_frame(exploding_data(1));
+    assert_eq!(task_interface.substeps, 1);
+    assert_eq!(task_interface.substep_retries, 0);
+    assert_eq!(
+        task_interface.data.hairs.strands[0].previous_delta_time,
+        1e-3
+    );
+}
@@ -0,0 +1,187 @@
+extern crate nalgebra as na;
+
+// Symmetric matrix keeping only its lower band, |row - col| <= bandwidth.
+// Row r stores columns r - bandwidth ..= r.
+#[derive(Clone, Debug)]
+pub struct SymmetricBandMatrix {
+    size: usize,
+    bandwidth: usize,
+    data: Vec<f64>,
+}
+
+impl SymmetricBandMatrix {
+    pub fn zeros(size: usize, bandwidth: usize) -> Self {
+        Self {
+            size,
+            bandwidth,
+            data: vec![0.0; size * (bandwidth + 1)],
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn bandwidth(&self) -> usize {
+        self.bandwidth
+    }
+
+    fn offset(&self, row: usize, col: usize) -> usize {
+        debug_assert!(col <= row && row - col <= self.bandwidth);
+        row * (self.bandwidth + 1) + self.bandwidth + col - row
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> f64 {
+        let (row, col) = if row >= col { (row, col) } else { (col, row) };
+        if row - col > self.bandwidth {
+            return 0.0;
+        }
+        self.data[self.offset(row, col)]
+    }
+
+    // Adds to the stored entry (row, col), which must be in the lower band. The
+    // symmetric entry (col, row) changes with it.
+    pub fn add(&mut self, row: usize, col: usize, value: f64) {
+        debug_assert!(col <= row, "entry ({}, {}) above the diagonal", row, col);
+        assert!(
+            row - col <= self.bandwidth,
+            "entry ({}, {}) outside of bandwidth {}",
+            row,
+            col,
+            self.bandwidth
+        );
+        let offset = self.offset(row, col);
+        self.data[offset] += value;
+    }
+
+    pub fn scale(&mut self, factor: f64) {
+        self.data.iter_mut().for_each(|v| *v *= factor);
+    }
+
+    // self += factor * other, other may have a narrower band
+    pub fn add_scaled(&mut self, other: &SymmetricBandMatrix, factor: f64) {
+        assert!(other.size == self.size && other.bandwidth <= self.bandwidth);
+        for r in 0..self.size {
+            for c in r.saturating_sub(other.bandwidth)..=r {
+                let offset = self.offset(r, c);
+                self.data[offset] += factor * other.data[other.offset(r, c)];
+            }
+        }
+    }
+
+    pub fn max_diagonal(&self) -> f64 {
+        (0..self.size)
+            .map(|i| self.data[self.offset(i, i)].abs())
+            .fold(0.0, f64::max)
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.data.iter().all(|v| v.is_finite())
+    }
+
+    pub fn add_diagonal(&mut self, values: &na::DVector<f64>) {
+        for i in 0..self.size {
+            let offset = self.offset(i, i);
+            self.data[offset] += values[i];
+        }
+    }
+
+    pub fn mul_vector(&self, x: &na::DVector<f64>) -> na::DVector<f64> {
+        let mut y = na::DVector::<f64>::zeros(self.size);
+        for r in 0..self.size {
+            for c in r.saturating_sub(self.bandwidth)..r {
+                let value = self.data[self.offset(r, c)];
+                y[r] += value * x[c];
+                y[c] += value * x[r];
+            }
+            y[r] += self.data[self.offset(r, r)] * x[r];
+        }
+        y
+    }
+
+    // Dirichlet condition x[dof] = value, moved to the right hand side.
+    pub fn constrain(&mut self, dof: usize, value: f64, rhs: &mut na::DVector<f64>) {
+        let first = dof.saturating_sub(self.bandwidth);
+        let last = (dof + self.bandwidth).min(self.size - 1);
+        for other in first..=last {
+            if other != dof {
+                rhs[other] -= self.get(other, dof) * value;
+                let (row, col) = if other > dof {
+                    (other, dof)
+                } else {
+                    (dof, other)
+                };
+                let offset = self.offset(row, col);
+                self.data[offset] = 0.0;
+            }
+        }
+        let offset = self.offset(dof, dof);
+        self.data[offset] = 1.0;
+        rhs[dof] = value;
+    }
+
+    pub fn to_dense(&self) -> na::DMatrix<f64> {
+        na::DMatrix::from_fn(self.size, self.size, |r, c| self.get(r, c))
+    }
+
+    // Banded LDL^T without pivoting, None if the matrix is not positive definite.
+    pub fn ldlt(&self) -> Option<BandLdlt> {
+        let n = self.size;
+        let p = self.bandwidth;
+        let mut l = SymmetricBandMatrix::zeros(n, p);
+        let mut d = na::DVector::<f64>::zeros(n);
+
+        for j in 0..n {
+            let mut d_j = self.data[self.offset(j, j)];
+            for k in j.saturating_sub(p)..j {
+                d_j -= l.data[l.offset(j, k)].powi(2) * d[k];
+            }
+            if !(d_j > 0.0 && d_j.is_finite()) {
+                return None;
+            }
+            d[j] = d_j;
+
+            for i in (j + 1)..(j + p + 1).min(n) {
+                let mut l_ij = self.data[self.offset(i, j)];
+                for k in i.saturating_sub(p)..j {
+                    l_ij -= l.data[l.offset(i, k)] * l.data[l.offset(j, k)] * d[k];
+                }
+                let offset = l.offset(i, j);
+                l.data[offset] = l_ij / d_j;
+            }
+        }
+
+        Some(BandLdlt { l, d })
+    }
+}
+
+pub struct BandLdlt {
+    l: SymmetricBandMatrix,
+    d: na::DVector<f64>,
+}
+
+impl BandLdlt {
+    pub fn solve(&self, b: &na::DVector<f64>) -> na::DVector<f64> {
+        let n = self.d.len();
+        let p = self.l.bandwidth;
+        let mut x = b.clone();
+
+        // L y = b
+        for i in 0..n {
+            for k in i.saturating_sub(p)..i {
+                x[i] -= self.l.data[self.l.offset(i, k)] * x[k];
+            }
+        }
+        // D z = y
+        for i in 0..n {
+            x[i] /= self.d[i];
+        }
+        // L^T x = z
+        for i in (0..n).rev() {
+            for k in (i + 1)..(i + p + 1).min(n) {
+                x[i] -= self.l.data[self.l.offset(k, i)] * x[k];
+            }
+        }
+        x
+    }
+}
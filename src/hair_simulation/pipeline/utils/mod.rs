@@ -1 +1,4 @@
+pub mod band_matrix;
 
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,127 @@
+use std::f64::consts::PI;
+
+use crate::hair_simulation::{
+    config::EnergyTerms,
+    data::{generate_hair_strand, Head, StrandParams, StrandShape},
+    pipeline::der::{
+        methods::{registered_energies, EnergyContext},
+        state::StrandState,
+    },
+};
+
+use super::band_matrix::SymmetricBandMatrix;
+extern crate nalgebra as na;
+
+fn assert_solves_alike(matrix: &SymmetricBandMatrix, b: &na::DVector<f64>) {
+    let dense_matrix = matrix.to_dense();
+    let banded = matrix.ldlt().unwrap().solve(b);
+    let dense = dense_matrix.clone().lu().solve(b).unwrap();
+
+    // Backward stable: the residual is at rounding level whatever the conditioning
+    let residual = (&dense_matrix * &banded - b).norm() / (dense_matrix.norm() * banded.norm());
+    assert!(residual < 1e-14, "residual {:e}", residual);
+
+    // Both solutions are within what the condition number allows of each other
+    let eigenvalues = dense_matrix.symmetric_eigenvalues();
+    let condition = eigenvalues.amax() / eigenvalues.amin();
+    let error = (&banded - &dense).norm() / dense.norm();
+    println!(
+        "banded against dense: relative error {:e}, condition {:e}",
+        error, condition
+    );
+    assert!(error < 1e-14 * condition);
+}
+
+#[test]
+fn band_ldlt_matches_dense_lu() {
+    let size = 23;
+    let bandwidth = 10;
+    let mut matrix = SymmetricBandMatrix::zeros(size, bandwidth);
+    for r in 0..size {
+        for c in r.saturating_sub(bandwidth)..=r {
+            let value = if r == c {
+                40.0 + r as f64
+            } else {
+                ((r * 7 + c * 3) % 11) as f64 / 5.0 - 1.0
+            };
+            matrix.add(r, c, value);
+        }
+    }
+    let b = na::DVector::from_fn(size, |i, _| (i as f64 * 0.37).sin());
+
+    let banded = matrix.ldlt().unwrap().solve(&b);
+    let dense = matrix.to_dense().lu().solve(&b).unwrap();
+
+    assert!((banded - dense).amax() < 1e-12);
+}
+
+#[test]
+#[should_panic]
+fn band_matrix_rejects_upper_entries() {
+    let mut matrix = SymmetricBandMatrix::zeros(4, 2);
+    matrix.add(1, 2, 1.0);
+}
+
+// M + h^2 H of a displaced curly strand, assembled by the registered terms as
+// the implicit Euler step does
+#[test]
+fn band_ldlt_solves_strand_systems() {
+    let params = StrandParams {
+        shape: StrandShape::Curly {
+            radius: 0.02,
+            pitch: 0.05,
+            phase: 0.7,
+        },
+        seg_num: 24,
+        last_pin: 0,
+        ..Default::default()
+    };
+    let mut strand = generate_hair_strand(
+        &params,
+        1e-6,
+        na::Vector3::zeros(),
+        na::Vector3::new(0.2, -1.0, 0.1) * 0.5,
+    );
+    for (i, position) in strand.v_position.iter_mut().enumerate() {
+        let phase = i as f64 * 0.9;
+        *position += 0.002 * na::Vector3::new(phase.sin(), phase.cos(), (2.0 * phase).sin());
+    }
+    for (i, twist) in strand.l_twist.iter_mut().enumerate() {
+        *twist += 0.2 * (i as f64 * 0.4).sin();
+    }
+
+    let state = StrandState::new(&strand);
+    let head = Head::default();
+    let context = EnergyContext {
+        strand: &strand,
+        state: &state,
+        head: &head,
+        colliders: &[],
+    };
+    let mut hessian = SymmetricBandMatrix::zeros(state.dof_num(), state.bandwidth());
+    for term in registered_energies(&EnergyTerms::default()) {
+        term.hessian(&context, &mut hessian);
+    }
+
+    let mut mass = na::DVector::<f64>::zeros(state.dof_num());
+    for i in 0..strand.v_num {
+        for k in 0..3 {
+            mass[state.vertex_dof(i) + k] = strand.v_mass[i];
+        }
+    }
+    for i in 0..strand.l_num {
+        mass[state.twist_dof(i)] = strand.l_momemtum[i];
+    }
+
+    let b = na::DVector::from_fn(state.dof_num(), |i, _| (i as f64 * 0.37).sin());
+    for delta_time in [1e-4, 1.0 / 240.0, 1.0 / 30.0] {
+        let mut matrix = hessian.clone();
+        matrix.scale(delta_time * delta_time);
+        matrix.add_diagonal(&mass);
+        assert_solves_alike(&matrix, &b);
+    }
+
+    // The stiff stretch blocks are in, far above the bend and twist ones
+    let stretch = PI * strand.radius.powi(2) * strand.youngs;
+    assert!(hessian.max_diagonal() > stretch);
+}
@@ -0,0 +1,245 @@
+use bevy::render::mesh::{Mesh, VertexAttributeValues};
+use serde::{Deserialize, Serialize};
+extern crate nalgebra as na;
+
+use crate::hair_simulation::data::convert_to_triangles;
+
+// Where a strand grows from, in the local space of the head
+#[derive(Clone, Debug)]
+pub struct HairRoot {
+    pub position: na::Vector3<f64>,
+    // Follicle direction, unit length
+    pub direction: na::Vector3<f64>,
+    // Scale of the strand length
+    pub length: f64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RootSampling {
+    // Evenly over the surface area
+    #[default]
+    Uniform,
+    // Proportional to the density map
+    DensityMap,
+}
+
+// The scalp region of a head mesh, in the local space of the head. The maps
+// hold one value per vertex and are interpolated across the triangles.
+#[derive(Clone, Debug, Default)]
+pub struct ScalpMesh {
+    pub positions: Vec<na::Vector3<f64>>,
+    pub normals: Vec<na::Vector3<f64>>,
+    pub triangles: Vec<[usize; 3]>,
+    // Relative hair density, uniform when empty
+    pub density: Vec<f64>,
+    // Scale of the strand length, 1 when empty
+    pub length: Vec<f64>,
+}
+
+// Deterministic splitmix64, so that a seed always grows the same hair
+struct RootRng(u64);
+
+impl RootRng {
+    fn next(&mut self) -> f64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl ScalpMesh {
+    // Vertex normals are the area weighted face normals, with counter-clockwise
+    // triangles facing outwards
+    pub fn new(positions: Vec<na::Vector3<f64>>, triangles: Vec<[usize; 3]>) -> Self {
+        let mut normals = vec![na::Vector3::zeros(); positions.len()];
+        for triangle in triangles.iter() {
+            let [a, b, c] = triangle.map(|i| positions[i]);
+            let normal = (b - a).cross(&(c - a));
+            for &i in triangle {
+                normals[i] += normal;
+            }
+        }
+        for normal in normals.iter_mut() {
+            *normal = normal.try_normalize(0.0).unwrap_or_else(na::Vector3::zeros);
+        }
+
+        ScalpMesh {
+            positions,
+            normals,
+            triangles,
+            density: Vec::new(),
+            length: Vec::new(),
+        }
+    }
+
+    // Positions, indices and, when present, normals of a triangle list mesh
+    pub fn from_mesh(mesh: &Mesh) -> Option<Self> {
+        let (positions, triangles) = convert_to_triangles(mesh)?;
+        let mut scalp = ScalpMesh::new(positions, triangles);
+        if let Some(VertexAttributeValues::Float32x3(normals)) =
+            mesh.attribute(Mesh::ATTRIBUTE_NORMAL)
+        {
+            scalp.normals = normals
+                .iter()
+                .map(|n| na::Vector3::new(n[0] as f64, n[1] as f64, n[2] as f64).normalize())
+                .collect();
+        }
+        Some(scalp)
+    }
+
+    // The part of the mesh whose triangles lie entirely where `keep` holds,
+    // without the vertices left unused
+    pub fn region(self, keep: impl Fn(&na::Vector3<f64>) -> bool) -> Self {
+        let triangles: Vec<[usize; 3]> = self
+            .triangles
+            .into_iter()
+            .filter(|triangle| triangle.iter().all(|&i| keep(&self.positions[i])))
+            .collect();
+
+        let mut remap = vec![None; self.positions.len()];
+        let mut kept = Vec::new();
+        for &i in triangles.iter().flatten() {
+            if remap[i].is_none() {
+                remap[i] = Some(kept.len());
+                kept.push(i);
+            }
+        }
+        let select = |values: &Vec<f64>| -> Vec<f64> {
+            if values.is_empty() {
+                Vec::new()
+            } else {
+                kept.iter().map(|&i| values[i]).collect()
+            }
+        };
+
+        ScalpMesh {
+            positions: kept.iter().map(|&i| self.positions[i]).collect(),
+            normals: kept.iter().map(|&i| self.normals[i]).collect(),
+            density: select(&self.density),
+            length: select(&self.length),
+            triangles: triangles
+                .iter()
+                .map(|triangle| triangle.map(|i| remap[i].unwrap()))
+                .collect(),
+        }
+    }
+
+    pub fn with_density_map(mut self, density: Vec<f64>) -> Self {
+        assert_eq!(density.len(), self.positions.len());
+        self.density = density;
+        self
+    }
+
+    pub fn with_length_map(mut self, length: Vec<f64>) -> Self {
+        assert_eq!(length.len(), self.positions.len());
+        self.length = length;
+        self
+    }
+
+    fn area(&self, triangle: &[usize; 3]) -> f64 {
+        let [a, b, c] = triangle.map(|i| self.positions[i]);
+        (b - a).cross(&(c - a)).norm() / 2.0
+    }
+
+    fn density_at(&self, triangle: &[usize; 3], weights: &[f64; 3]) -> f64 {
+        (0..3).map(|k| weights[k] * self.density[triangle[k]]).sum()
+    }
+
+    fn root_at(&self, triangle: &[usize; 3], weights: &[f64; 3]) -> HairRoot {
+        let interpolate = |values: &Vec<na::Vector3<f64>>| -> na::Vector3<f64> {
+            (0..3).map(|k| weights[k] * values[triangle[k]]).sum()
+        };
+
+        let [a, b, c] = triangle.map(|i| self.positions[i]);
+        let direction = interpolate(&self.normals)
+            .try_normalize(1e-12)
+            .unwrap_or_else(|| (b - a).cross(&(c - a)).normalize());
+        let length = if self.length.is_empty() {
+            1.0
+        } else {
+            (0..3).map(|k| weights[k] * self.length[triangle[k]]).sum()
+        };
+
+        HairRoot {
+            position: interpolate(&self.positions),
+            direction,
+            length,
+        }
+    }
+
+    // One root at every vertex, in vertex order
+    pub fn vertex_roots(&self) -> Vec<HairRoot> {
+        (0..self.positions.len())
+            .map(|i| HairRoot {
+                position: self.positions[i],
+                direction: self.normals[i],
+                length: self.length.get(i).copied().unwrap_or(1.0),
+            })
+            .collect()
+    }
+
+    // `count` roots over the scalp, the same ones for the same seed
+    pub fn sample_roots(&self, sampling: RootSampling, count: usize, seed: u64) -> Vec<HairRoot> {
+        self.sample_points(sampling, count, seed)
+            .iter()
+            .map(|(triangle, weights)| self.root_at(triangle, weights))
+            .collect()
+    }
+
+    // Triangles and barycentric coordinates of `count` points over the scalp
+    pub fn sample_points(
+        &self,
+        sampling: RootSampling,
+        count: usize,
+        seed: u64,
+    ) -> Vec<([usize; 3], [f64; 3])> {
+        let use_density = sampling == RootSampling::DensityMap && !self.density.is_empty();
+
+        // Pick triangles by area, times the largest density on them so that
+        // the rejection below stays exact
+        let mut cumulative = Vec::with_capacity(self.triangles.len());
+        let mut total = 0.0;
+        for triangle in self.triangles.iter() {
+            let mut weight = self.area(triangle);
+            if use_density {
+                weight *= triangle
+                    .iter()
+                    .fold(0.0, |max: f64, &i| max.max(self.density[i]));
+            }
+            total += weight;
+            cumulative.push(total);
+        }
+        if total <= 0.0 {
+            return Vec::new();
+        }
+
+        let mut rng = RootRng(seed);
+        let mut points = Vec::with_capacity(count);
+        while points.len() < count {
+            let pick = rng.next() * total;
+            let index = cumulative
+                .partition_point(|&c| c <= pick)
+                .min(self.triangles.len() - 1);
+            let triangle = &self.triangles[index];
+
+            // Uniform barycentric coordinates
+            let r1 = rng.next().sqrt();
+            let r2 = rng.next();
+            let weights = [1.0 - r1, r1 * (1.0 - r2), r1 * r2];
+
+            if use_density {
+                let max = triangle
+                    .iter()
+                    .fold(0.0, |max: f64, &i| max.max(self.density[i]));
+                if rng.next() * max >= self.density_at(triangle, &weights) {
+                    continue;
+                }
+            }
+            points.push((*triangle, weights));
+        }
+        points
+    }
+}
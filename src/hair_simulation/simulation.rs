@@ -1,14 +1,8 @@
-use bevy::{
-    log::info,
-    math::{Quat, Vec3},
-};
+use bevy::{log::info, math::Quat};
 extern crate nalgebra as na;
 
 use crate::{
-    hair_simulation::{
-        data::{convert_to_na_vec3, convert_to_vec3},
-        pipeline::der::do_der,
-    },
+    hair_simulation::pipeline::stepping::do_frame,
     physic_simulation::interfaces::SimulationTaskInterface,
 };
 
@@ -25,22 +19,19 @@ pub fn do_simulate(task_interface: &mut SimulationTaskInterface) {
 
     // task_interface.data.head.rotation = Quat::from_rotation_x(delta as f32);
 
+    let head = &task_interface.data.head;
+    let frame_time = task_interface.delta_time;
+    task_interface
+        .data
+        .colliders
+        .iter_mut()
+        .for_each(|collider| collider.follow(head, frame_time));
     task_interface
         .data
         .hairs
         .strands
         .iter_mut()
-        .for_each(|hair| {
-            for _ in 0..hair.v_num {
-                let attachment: Vec3 =
-                    convert_to_vec3(task_interface.data.head.attachments[hair.attachment]);
-                hair.v_position[0] =
-                    convert_to_na_vec3(task_interface.data.head.rotation * attachment)
-                        + task_interface.data.head.position;
-                // hair.position[0] = task_interface.data.head.position
-                //     + task_interface.data.head.attachments[hair.attachment];
-            }
-        });
+        .for_each(|hair| hair.follow_head(head, frame_time));
 
-    do_der(task_interface);
+    do_frame(task_interface);
 }
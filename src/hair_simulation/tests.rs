@@ -0,0 +1,301 @@
+use std::f64::consts::PI;
+
+use bevy::{
+    math::{primitives::Sphere, Quat},
+    render::mesh::Mesh,
+};
+
+use super::{
+    config::SceneConfig,
+    conversion::default_scene,
+    data::{convert_to_na_quat, generate_rooted_hair_strands, Head, SimulationData, StrandParams},
+    interpolation::RenderHairs,
+    scalp::{RootSampling, ScalpMesh},
+};
+extern crate nalgebra as na;
+
+const SAMPLE_NUM: usize = 4000;
+
+// Two triangles facing +z, the second three times the area of the first
+fn two_triangles() -> ScalpMesh {
+    let positions = vec![
+        na::Vector3::new(0.0, 0.0, 0.0),
+        na::Vector3::new(1.0, 0.0, 0.0),
+        na::Vector3::new(0.0, 1.0, 0.0),
+        na::Vector3::new(2.0, 0.0, 0.0),
+        na::Vector3::new(5.0, 0.0, 0.0),
+        na::Vector3::new(2.0, 1.0, 0.0),
+    ];
+    let length = positions.iter().map(|p| 1.0 + p.x).collect();
+    ScalpMesh::new(positions, vec![[0, 1, 2], [3, 4, 5]]).with_length_map(length)
+}
+
+fn on_first_triangle(position: &na::Vector3<f64>) -> bool {
+    position.x < 1.5
+}
+
+#[test]
+fn uniform_roots_spread_by_area() {
+    let scalp = two_triangles();
+    let roots = scalp.sample_roots(RootSampling::Uniform, SAMPLE_NUM, 3);
+    assert_eq!(roots.len(), SAMPLE_NUM);
+
+    let first = roots
+        .iter()
+        .filter(|root| on_first_triangle(&root.position))
+        .count();
+    let share = first as f64 / SAMPLE_NUM as f64;
+    assert!((share - 0.25).abs() < 0.03, "{}", share);
+
+    for root in roots.iter() {
+        // On the surface, growing along its normal, with the length map
+        // interpolated at the root
+        assert_eq!(root.position.z, 0.0);
+        assert!(root.position.x >= 0.0 && root.position.y >= 0.0);
+        assert!((root.direction - na::Vector3::z()).norm() < 1e-12);
+        assert!((root.length - (1.0 + root.position.x)).abs() < 1e-12);
+    }
+
+    // The same seed grows the same roots
+    let again = scalp.sample_roots(RootSampling::Uniform, SAMPLE_NUM, 3);
+    for (a, b) in roots.iter().zip(again.iter()) {
+        assert_eq!(a.position, b.position);
+    }
+}
+
+#[test]
+fn density_map_keeps_roots_off_bare_skin() {
+    let scalp = two_triangles().with_density_map(vec![1.0, 1.0, 1.0, 0.0, 0.0, 0.0]);
+    let roots = scalp.sample_roots(RootSampling::DensityMap, SAMPLE_NUM, 5);
+    assert_eq!(roots.len(), SAMPLE_NUM);
+    assert!(roots.iter().all(|root| on_first_triangle(&root.position)));
+
+    // Uniform sampling ignores the map
+    let roots = scalp.sample_roots(RootSampling::Uniform, SAMPLE_NUM, 5);
+    assert!(!roots.iter().all(|root| on_first_triangle(&root.position)));
+}
+
+#[test]
+fn region_drops_unused_vertices() {
+    let scalp = two_triangles()
+        .with_density_map(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0])
+        .region(|p| p.x > 1.5);
+    assert_eq!(scalp.triangles, vec![[0, 1, 2]]);
+    assert_eq!(scalp.positions[1], na::Vector3::new(5.0, 0.0, 0.0));
+    assert_eq!(scalp.density, vec![4.0, 5.0, 6.0]);
+    assert_eq!(scalp.length, vec![3.0, 6.0, 3.0]);
+    assert_eq!(scalp.normals.len(), 3);
+}
+
+#[test]
+fn rooted_strands_grow_from_the_rotated_head() {
+    let head = Head {
+        position: na::Vector3::new(0.1, 1.0, -0.2),
+        rotation: Quat::from_rotation_y(0.7),
+        ..Default::default()
+    };
+    let roots = two_triangles().vertex_roots();
+    let data = generate_rooted_hair_strands(
+        head.clone(),
+        &roots,
+        0.5,
+        1e-4,
+        &StrandParams {
+            seg_num: 4,
+            youngs: 1e9,
+            shear: 1e9,
+            radius: 1e-6,
+            ..Default::default()
+        },
+    );
+
+    let rotation = convert_to_na_quat(head.rotation);
+    assert_eq!(data.hairs.strands.len(), roots.len());
+    for (strand, root) in data.hairs.strands.iter().zip(roots.iter()) {
+        // Attachments stay in the local space of the head
+        assert_eq!(data.head.attachments[strand.attachment], root.position);
+
+        let base = head.to_world(&root.position);
+        let tip = base + rotation * root.direction * 0.5 * root.length;
+        assert!((strand.v_position[0] - base).norm() < 1e-12);
+        assert!((strand.v_position[strand.v_num - 1] - tip).norm() < 1e-12);
+    }
+}
+
+// Straight guides of equal length at every vertex of the scalp, on a moved head
+fn guided(scalp: &ScalpMesh) -> SimulationData {
+    let head = Head {
+        position: na::Vector3::new(0.1, 1.0, -0.2),
+        rotation: Quat::from_rotation_x(0.4),
+        ..Default::default()
+    };
+    generate_rooted_hair_strands(
+        head,
+        &scalp.vertex_roots(),
+        0.5,
+        1e-4,
+        &StrandParams {
+            seg_num: 5,
+            youngs: 1e9,
+            shear: 1e9,
+            radius: 1e-6,
+            ..Default::default()
+        },
+    )
+}
+
+#[test]
+fn render_strands_follow_their_guides() {
+    let mut scalp = two_triangles();
+    scalp.length.clear();
+    let data = guided(&scalp);
+    let guide = &data.hairs.strands[0];
+
+    let vertex_guides: Vec<usize> = (0..scalp.positions.len()).collect();
+    let render =
+        RenderHairs::barycentric(&scalp, &vertex_guides, RootSampling::Uniform, 64, 1, 0.0);
+    assert_eq!(render.strands.len(), 64);
+    for strand in render.strands.iter() {
+        let total: f64 = strand.guides.iter().map(|(_, weight)| weight).sum();
+        assert!((total - 1.0).abs() < 1e-12);
+
+        // Every guide is the same shifted line, so the render strand is too
+        let root = data.head.to_world(&strand.root);
+        let positions = render.positions(strand, &data);
+        assert_eq!(positions.len(), guide.v_num);
+        for (j, position) in positions.iter().enumerate() {
+            let expected = root + guide.v_position[j] - guide.v_position[0];
+            assert!((position - expected).norm() < 1e-12, "{}", j);
+        }
+    }
+
+    // Fully clumped strands end on their heaviest guide
+    let clumped = RenderHairs {
+        clumping: 1.0,
+        ..render.clone()
+    };
+    for strand in clumped.strands.iter() {
+        let &(heaviest, _) = strand
+            .guides
+            .iter()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        let guide = &data.hairs.strands[heaviest];
+        let positions = clumped.positions(strand, &data);
+        assert!((positions[guide.v_num - 1] - guide.v_position[guide.v_num - 1]).norm() < 1e-12);
+    }
+
+    assert_eq!(
+        render.to_instance_data(&data).len(),
+        render.strands.len() * guide.to_instance_data().len()
+    );
+}
+
+#[test]
+fn render_strands_skip_missing_guides() {
+    let scalp = two_triangles();
+    let mut data = guided(&scalp);
+    // Guide i at vertex 5 - i
+    let vertex_guides: Vec<usize> = (0..scalp.positions.len()).rev().collect();
+    let render =
+        RenderHairs::barycentric(&scalp, &vertex_guides, RootSampling::Uniform, 16, 2, 0.0);
+    for strand in render.strands.iter() {
+        let on_first = on_first_triangle(&strand.root);
+        assert!(strand.guides.iter().all(|&(g, _)| (g >= 3) == on_first));
+        assert!(!render.positions(strand, &data).is_empty());
+    }
+
+    // As after restoring a snapshot with fewer strands
+    data.hairs.strands.truncate(3);
+    for strand in render.strands.iter() {
+        assert_eq!(
+            render.positions(strand, &data).is_empty(),
+            on_first_triangle(&strand.root)
+        );
+    }
+}
+
+#[test]
+fn nearest_guides_weigh_by_distance() {
+    let scalp = two_triangles();
+    let data = guided(&scalp);
+    let mut roots = scalp.vertex_roots();
+    roots.extend(scalp.sample_roots(RootSampling::Uniform, 32, 9));
+    let render = RenderHairs::nearest_guides(&roots, &data, 3, 0.2);
+
+    // A root on a guide follows it exactly
+    for (i, strand) in render.strands[..scalp.positions.len()].iter().enumerate() {
+        assert_eq!(strand.guides, vec![(i, 1.0)]);
+        let guide = &data.hairs.strands[i];
+        for (j, position) in render.positions(strand, &data).iter().enumerate() {
+            assert!((position - guide.v_position[j]).norm() < 1e-12, "{}", j);
+        }
+    }
+
+    for strand in render.strands[scalp.positions.len()..].iter() {
+        assert_eq!(strand.guides.len(), 3);
+        let total: f64 = strand.guides.iter().map(|(_, weight)| weight).sum();
+        assert!((total - 1.0).abs() < 1e-12);
+
+        // Closer guides weigh more
+        let distance = |&(g, _): &(usize, f64)| {
+            (data.head.attachments[data.hairs.strands[g].attachment] - strand.root).norm()
+        };
+        for pair in strand.guides.windows(2) {
+            assert!(distance(&pair[0]) <= distance(&pair[1]));
+            assert!(pair[0].1 >= pair[1].1);
+        }
+    }
+}
+
+#[test]
+fn scene_config_grows_sampled_guides() {
+    // The cap of the default head, see `default_scene`
+    let scalp = ScalpMesh::from_mesh(&Mesh::from(Sphere::new(0.1)))
+        .unwrap()
+        .region(|p| p.y > 0.1 * f64::cos(PI / 4.0));
+    let vertex_num = scalp.positions.len();
+    assert_eq!(
+        default_scene(&SceneConfig::default())
+            .unwrap()
+            .0
+            .hairs
+            .strands
+            .len(),
+        vertex_num
+    );
+
+    // Bare but for the triangles around the first scalp vertex, twice as long
+    let mut density_map = vec![0.0; vertex_num];
+    density_map[0] = 1.0;
+    let scene = SceneConfig {
+        guide_num: Some(20),
+        root_sampling: RootSampling::DensityMap,
+        density_map,
+        length_map: vec![2.0; vertex_num],
+        ..Default::default()
+    };
+    let (data, render_hairs, _) = default_scene(&scene).unwrap();
+    assert_eq!(data.hairs.strands.len(), 20);
+    assert!(!render_hairs.strands.is_empty());
+
+    let reach = scalp
+        .triangles
+        .iter()
+        .filter(|triangle| triangle.contains(&0))
+        .flatten()
+        .map(|&i| (scalp.positions[i] - scalp.positions[0]).norm())
+        .fold(0.0, f64::max);
+    for strand in data.hairs.strands.iter() {
+        let root = data.head.attachments[strand.attachment];
+        assert!((root - scalp.positions[0]).norm() <= reach + 1e-12);
+        let length: f64 = strand.l_rest_length.iter().sum();
+        assert!((length - 1.0).abs() < 1e-9, "{}", length);
+    }
+
+    let scene = SceneConfig {
+        length_map: vec![1.0; vertex_num + 1],
+        ..Default::default()
+    };
+    assert!(default_scene(&scene).is_err());
+}
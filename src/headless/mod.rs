@@ -0,0 +1,375 @@
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use instant::Instant;
+
+use crate::{
+    hair_simulation::{
+        config::{SceneConfig, SimulationConfig},
+        conversion::default_scene,
+        data::{Head, SimulationData},
+        formats::{
+            hair_file::{HairFile, HairImport},
+            polylines::{FrameExporter, PolylineFormat},
+            recording::{RecordedFrame, Recorder},
+            snapshot::Snapshot,
+        },
+        simulation::do_simulate,
+    },
+    physic_simulation::interfaces::{FailureAction, SimulationError, SimulationTaskInterface},
+};
+extern crate nalgebra as na;
+
+#[cfg(test)]
+mod tests;
+
+pub const USAGE: &str = "\
+usage: realtime_hair_wgpu headless [options]
+
+Runs the simulation without a window and writes to the output directory
+  steps.csv      timing, energy and regularized strands of every step
+  final.bin      snapshot after the last step
+  failed.bin     snapshot before the step that failed, if one did
+
+options:
+  --scene FILE     snapshot (.bin, .ron) or .hair file, the default head otherwise
+  --config FILE    RON solver config replacing the one of the scene, fields
+                   left out take their defaults
+  --groom FILE     RON scene config growing the hair of the default head,
+                   e.g. (shape: Wavy(amplitude: 0.01, wavelength: 0.1, phase: 0.0))
+  --steps N        steps to run, 100 by default
+  --out DIR        output directory, \"headless\" by default
+  --scale S        from the units of a .hair file to metres, 1 by default
+  --export FORMAT  also write the strands of every step, ply or obj
+  --record         also write recording.hrec for playback
+
+exits with 1 when a step produced NaN or the solver reverted or froze a
+strand, 2 on bad arguments or files";
+
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeadlessOptions {
+    pub scene: Option<PathBuf>,
+    pub config: Option<PathBuf>,
+    pub groom: Option<PathBuf>,
+    pub steps: u64,
+    pub out: PathBuf,
+    pub scale: f64,
+    pub export: Option<PolylineFormat>,
+    pub record: bool,
+}
+
+impl Default for HeadlessOptions {
+    fn default() -> Self {
+        Self {
+            scene: None,
+            config: None,
+            groom: None,
+            steps: 100,
+            out: PathBuf::from("headless"),
+            scale: 1.0,
+            export: None,
+            record: false,
+        }
+    }
+}
+
+impl HeadlessOptions {
+    // From the arguments after `headless`
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = HeadlessOptions::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--scene" => options.scene = Some(PathBuf::from(value()?)),
+                "--config" => options.config = Some(PathBuf::from(value()?)),
+                "--groom" => options.groom = Some(PathBuf::from(value()?)),
+                "--steps" => {
+                    let steps = value()?;
+                    options.steps = steps
+                        .parse()
+                        .map_err(|_| format!("--steps {} is not a count", steps))?;
+                }
+                "--out" => options.out = PathBuf::from(value()?),
+                "--scale" => {
+                    let scale = value()?;
+                    options.scale = scale
+                        .parse()
+                        .map_err(|_| format!("--scale {} is not a number", scale))?;
+                }
+                "--export" => {
+                    options.export = match value()?.as_str() {
+                        "ply" => Some(PolylineFormat::Ply),
+                        "obj" => Some(PolylineFormat::Obj),
+                        format => return Err(format!("--export {} is not ply or obj", format)),
+                    }
+                }
+                "--record" => options.record = true,
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+        if options.scene.is_some() && options.groom.is_some() {
+            return Err("--groom only applies to the default head, not to --scene".to_string());
+        }
+        Ok(options)
+    }
+}
+
+// The state and iteration a run starts from
+pub fn load_scene(options: &HeadlessOptions) -> Result<(SimulationData, u64), String> {
+    let (mut data, iteration_cnt) = match options.scene.as_ref() {
+        None => {
+            let scene = match options.groom.as_ref() {
+                Some(path) => read_ron::<SceneConfig>(path)?,
+                None => SceneConfig::default(),
+            };
+            (default_scene(&scene)?.0, 0)
+        }
+        Some(path)
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "hair") =>
+        {
+            let file = HairFile::load(path).map_err(|error| describe(path, error))?;
+            let head = Head {
+                position: na::Vector3::new(0.0, 2.0, 0.0),
+                radius: 0.1,
+                ..Default::default()
+            };
+            let import = HairImport {
+                scale: options.scale,
+                ..Default::default()
+            };
+            (file.to_simulation_data(head, &import), 0)
+        }
+        Some(path) => {
+            let snapshot = Snapshot::load(path).map_err(|error| describe(path, error))?;
+            (snapshot.data, snapshot.iteration_cnt)
+        }
+    };
+
+    if let Some(path) = options.config.as_ref() {
+        data.config = read_ron::<SimulationConfig>(path)?;
+    }
+    for warning in data.config.warnings() {
+        eprintln!("warning: {}", warning);
+    }
+    Ok((data, iteration_cnt))
+}
+
+fn read_ron<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, String> {
+    let text = fs::read_to_string(path).map_err(|error| describe(path, error))?;
+    ron::Options::default()
+        .from_str::<T>(&text)
+        .map_err(|error| describe(path, error))
+}
+
+fn describe(path: &Path, error: impl fmt::Display) -> String {
+    format!("{}: {}", path.display(), error)
+}
+
+// Why a run stopped before its last step
+#[derive(Clone, Debug)]
+pub enum StepFailure {
+    Solver(SimulationError),
+    // A vertex, velocity or twist of the strand is NaN or infinite
+    NotFinite { iteration_cnt: u64, strand: usize },
+}
+
+impl fmt::Display for StepFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StepFailure::Solver(error) => write!(f, "solver failed at {}", error),
+            StepFailure::NotFinite {
+                iteration_cnt,
+                strand,
+            } => write!(f, "#{} strand {}: not finite", iteration_cnt, strand),
+        }
+    }
+}
+
+// One frame from data at iteration_cnt, as the scheduler would run it
+pub fn step(data: SimulationData, iteration_cnt: u64) -> SimulationTaskInterface {
+    let mut task_interface = SimulationTaskInterface {
+        iteration_cnt,
+        delta_time: data.config.time_stepping.frame_time,
+        data,
+        ..Default::default()
+    };
+    let start_ts = Instant::now();
+    do_simulate(&mut task_interface);
+    task_interface.elapsed = start_ts.elapsed();
+    task_interface
+}
+
+// Regularized strands went on with a valid step, only reverted or frozen ones
+// stop the run
+pub fn check_step(task_interface: &SimulationTaskInterface) -> Result<(), StepFailure> {
+    let failed = task_interface
+        .errors
+        .iter()
+        .find(|error| error.action != FailureAction::Regularized);
+    if let Some(error) = failed {
+        return Err(StepFailure::Solver(error.clone()));
+    }
+    let finite = |v: &na::Vector3<f64>| v.iter().all(|x| x.is_finite());
+    for (strand, hair) in task_interface.data.hairs.strands.iter().enumerate() {
+        if !(hair.v_position.iter().all(finite)
+            && hair.v_velocity.iter().all(finite)
+            && hair.l_twist.iter().all(|x| x.is_finite()))
+        {
+            return Err(StepFailure::NotFinite {
+                iteration_cnt: task_interface.iteration_cnt,
+                strand,
+            });
+        }
+    }
+    Ok(())
+}
+
+// Entry point of `headless`, returns the exit code
+pub fn run(args: &[String]) -> i32 {
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", USAGE);
+        return 0;
+    }
+    let options = match HeadlessOptions::parse(args) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            return EXIT_USAGE;
+        }
+    };
+    match run_steps(&options) {
+        Ok(None) => 0,
+        Ok(Some(failure)) => {
+            eprintln!("{}", failure);
+            EXIT_FAILURE
+        }
+        Err(error) => {
+            eprintln!("{}", error);
+            EXIT_USAGE
+        }
+    }
+}
+
+// Runs the steps of the options, the failure that stopped them if one did
+pub fn run_steps(options: &HeadlessOptions) -> Result<Option<StepFailure>, String> {
+    let (mut data, mut iteration_cnt) = load_scene(options)?;
+    let out = &options.out;
+    fs::create_dir_all(out).map_err(|error| describe(out, error))?;
+
+    let csv_path = out.join("steps.csv");
+    let mut csv = fs::File::create(&csv_path)
+        .map(io::BufWriter::new)
+        .map_err(|error| describe(&csv_path, error))?;
+    writeln!(
+        csv,
+        "iteration,elapsed_ms,substeps,substep_retries,potential,kinetic,total,regularized"
+    )
+    .map_err(|error| describe(&csv_path, error))?;
+
+    let exporter = options
+        .export
+        .map(|format| FrameExporter::new(out.join("frames"), format));
+    let recording_path = out.join("recording.hrec");
+    let mut recorder = if options.record {
+        let recorder = Recorder::create(&recording_path, &data)
+            .map_err(|error| describe(&recording_path, error))?;
+        Some(recorder)
+    } else {
+        None
+    };
+
+    println!(
+        "{} strands from iteration {}, {} steps",
+        data.hairs.strands.len(),
+        iteration_cnt,
+        options.steps
+    );
+    for _ in 0..options.steps {
+        let task_interface = step(data.clone(), iteration_cnt);
+        if let Err(failure) = check_step(&task_interface) {
+            for error in task_interface.errors.iter() {
+                eprintln!("error: {}", error);
+            }
+            // The steps before the failure are kept, with write errors reported
+            csv.flush().map_err(|error| describe(&csv_path, error))?;
+            if let Some(recorder) = recorder.as_mut() {
+                recorder
+                    .flush()
+                    .map_err(|error| describe(&recording_path, error))?;
+            }
+
+            // The state the failing step started from, to reproduce it
+            let failed_path = out.join("failed.bin");
+            Snapshot::new(iteration_cnt, data)
+                .save(&failed_path)
+                .map_err(|error| describe(&failed_path, error))?;
+            return Ok(Some(failure));
+        }
+        // Only regularized strands are left
+        for error in task_interface.errors.iter() {
+            eprintln!("warning: {}", error);
+        }
+        data = task_interface.data;
+        iteration_cnt += 1;
+
+        let elapsed = task_interface.elapsed.as_secs_f64() * 1e3;
+        let total = &task_interface.diagnostics.total;
+        println!(
+            "step {:>5} {:>9.2} ms, {} substeps ({} redone), energy {:.6e} (potential {:.6e}, kinetic {:.6e})",
+            iteration_cnt,
+            elapsed,
+            task_interface.substeps,
+            task_interface.substep_retries,
+            total.total_energy(),
+            total.potential_energy(),
+            total.kinetic
+        );
+        writeln!(
+            csv,
+            "{},{},{},{},{:e},{:e},{:e},{}",
+            iteration_cnt,
+            elapsed,
+            task_interface.substeps,
+            task_interface.substep_retries,
+            total.potential_energy(),
+            total.kinetic,
+            total.total_energy(),
+            task_interface.errors.len()
+        )
+        .map_err(|error| describe(&csv_path, error))?;
+
+        if let Some(exporter) = exporter.as_ref() {
+            exporter
+                .export(&data.hairs, iteration_cnt)
+                .map_err(|error| describe(&exporter.directory, error))?;
+        }
+        if let Some(recorder) = recorder.as_mut() {
+            let frame = RecordedFrame::from_data(
+                iteration_cnt,
+                task_interface.elapsed,
+                task_interface.substeps,
+                task_interface.substep_retries,
+                &data,
+            );
+            recorder
+                .record(&frame)
+                .map_err(|error| describe(&recording_path, error))?;
+        }
+    }
+    csv.flush().map_err(|error| describe(&csv_path, error))?;
+
+    let final_path = out.join("final.bin");
+    Snapshot::new(iteration_cnt, data)
+        .save(&final_path)
+        .map_err(|error| describe(&final_path, error))?;
+    Ok(None)
+}
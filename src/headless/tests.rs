@@ -0,0 +1,201 @@
+use std::{
+    f64::consts::PI,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    hair_simulation::{
+        data::{generate_batch_hair_strands, StrandShape},
+        formats::{polylines::PolylineFormat, recording::Recording, snapshot::Snapshot},
+    },
+    headless::{
+        check_step, load_scene, run, run_steps, HeadlessOptions, StepFailure, EXIT_FAILURE,
+        EXIT_USAGE,
+    },
+    physic_simulation::interfaces::{
+        FailureAction, SimulationError, SimulationErrorKind, SimulationTaskInterface,
+    },
+};
+extern crate nalgebra as na;
+
+fn args(line: &str) -> Vec<String> {
+    line.split_whitespace().map(String::from).collect()
+}
+
+// A fresh directory for the files of a test
+fn out_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("headless_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// A ring of a few short strands around the top of a head
+fn scene_file(dir: &Path, iteration_cnt: u64) -> PathBuf {
+    let data = generate_batch_hair_strands(
+        na::Vector3::new(0.0, 2.0, 0.0),
+        0.1,
+        PI / 4.0,
+        3,
+        0.2,
+        8,
+        1.9e10,
+        7.1e9,
+        1e-6,
+        1e-4,
+        1,
+        StrandShape::Straight,
+    );
+    let path = dir.join("scene.bin");
+    Snapshot::new(iteration_cnt, data).save(&path).unwrap();
+    path
+}
+
+#[test]
+fn parses_options() {
+    let options =
+        HeadlessOptions::parse(&args("--steps 12 --out runs/a --export obj --record")).unwrap();
+    assert_eq!(options.steps, 12);
+    assert_eq!(options.out, PathBuf::from("runs/a"));
+    assert_eq!(options.export, Some(PolylineFormat::Obj));
+    assert!(options.record);
+    assert_eq!(options.scene, None);
+
+    assert!(HeadlessOptions::parse(&args("--steps many")).is_err());
+    assert!(HeadlessOptions::parse(&args("--export fbx")).is_err());
+    assert!(HeadlessOptions::parse(&args("--scene")).is_err());
+    assert!(HeadlessOptions::parse(&args("--scene a.bin --groom b.ron")).is_err());
+    assert_eq!(run(&args("--frames 3")), EXIT_USAGE);
+}
+
+#[test]
+fn runs_steps_from_a_snapshot() {
+    let dir = out_dir("run");
+    let scene = scene_file(&dir, 7);
+    let config = dir.join("config.ron");
+    fs::write(&config, "(hair_collision: (enabled: true))").unwrap();
+    let options = HeadlessOptions {
+        scene: Some(scene),
+        config: Some(config),
+        steps: 3,
+        out: dir.join("out"),
+        export: Some(PolylineFormat::Ply),
+        record: true,
+        ..Default::default()
+    };
+    assert!(run_steps(&options).unwrap().is_none());
+
+    let out = &options.out;
+    let csv = fs::read_to_string(out.join("steps.csv")).unwrap();
+    let iterations: Vec<&str> = csv
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap())
+        .collect();
+    assert_eq!(iterations, vec!["8", "9", "10"]);
+    assert!(out.join("frames/frame_000010.ply").exists());
+
+    let mut recording = Recording::open(out.join("recording.hrec")).unwrap();
+    assert_eq!(recording.frame_num().unwrap(), 3);
+    let last = Snapshot::load(out.join("final.bin")).unwrap();
+    assert_eq!(last.iteration_cnt, 10);
+    assert!(last.data.config.hair_collision.enabled);
+    // Left out of the config file
+    assert_eq!(last.data.config.hair_collision.distance, 0.002);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn stops_on_nan() {
+    let dir = out_dir("nan");
+    let scene = scene_file(&dir, 0);
+    let mut snapshot = Snapshot::load(&scene).unwrap();
+    snapshot.data.hairs.strands[2].v_velocity[5].x = f64::NAN;
+    snapshot.save(&scene).unwrap();
+
+    let options = HeadlessOptions {
+        scene: Some(scene.clone()),
+        steps: 3,
+        out: dir.join("out"),
+        record: true,
+        ..Default::default()
+    };
+    let failure = run_steps(&options).unwrap().unwrap();
+    assert!(matches!(
+        failure,
+        StepFailure::Solver(_) | StepFailure::NotFinite { strand: 2, .. }
+    ));
+    // The state before the failed step, to reproduce it from
+    let failed = Snapshot::load(options.out.join("failed.bin")).unwrap();
+    assert_eq!(failed.iteration_cnt, 0);
+    assert!(failed.data.hairs.strands[2].v_velocity[5].x.is_nan());
+    // No step was completed, the outputs are left readable
+    let csv = fs::read_to_string(options.out.join("steps.csv")).unwrap();
+    assert_eq!(csv.lines().count(), 1);
+    let mut recording = Recording::open(options.out.join("recording.hrec")).unwrap();
+    assert_eq!(recording.frame_num().unwrap(), 0);
+
+    let line = format!(
+        "--scene {} --steps 3 --out {}",
+        scene.display(),
+        dir.display()
+    );
+    assert_eq!(run(&args(&line)), EXIT_FAILURE);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn regularized_strands_do_not_stop_the_run() {
+    let error = |strand: usize, action: FailureAction| SimulationError {
+        iteration_cnt: 4,
+        strand,
+        kind: SimulationErrorKind::SingularSystem,
+        action,
+    };
+    let mut task_interface = SimulationTaskInterface {
+        iteration_cnt: 4,
+        errors: vec![error(1, FailureAction::Regularized)],
+        ..Default::default()
+    };
+    assert!(check_step(&task_interface).is_ok());
+
+    for action in [FailureAction::Reverted, FailureAction::Frozen] {
+        task_interface.errors.push(error(3, action));
+        assert!(matches!(
+            check_step(&task_interface),
+            Err(StepFailure::Solver(SimulationError { strand: 3, .. }))
+        ));
+        task_interface.errors.pop();
+    }
+}
+
+#[test]
+fn grooms_the_default_head() {
+    let dir = out_dir("groom");
+    let groom = dir.join("groom.ron");
+    fs::write(
+        &groom,
+        "(shape: Curly(radius: 0.01, pitch: 0.03, phase: 0.0))",
+    )
+    .unwrap();
+    let options = HeadlessOptions::parse(&args(&format!("--groom {}", groom.display()))).unwrap();
+    let (data, iteration_cnt) = load_scene(&options).unwrap();
+    assert_eq!(iteration_cnt, 0);
+
+    // Curls stay within their radius of the straight line from root to tip
+    for strand in data.hairs.strands.iter() {
+        let root = strand.v_position[0];
+        let axis = (strand.v_position[strand.v_num - 1] - root).normalize();
+        let off_axis = |p: &na::Vector3<f64>| (p - root - axis * axis.dot(&(p - root))).norm();
+        let widest = strand.v_position.iter().map(off_axis).fold(0.0, f64::max);
+        assert!(widest > 0.01 && widest < 0.03, "{}", widest);
+    }
+
+    fs::write(&groom, "(shape: Spiky)").unwrap();
+    assert!(load_scene(&options).is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
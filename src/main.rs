@@ -1,4 +1,5 @@
 mod hair_simulation;
+mod headless;
 mod physic_simulation;
 mod plugins;
 
@@ -12,6 +13,12 @@ use plugins::{
 };
 
 fn main() {
+    // `headless ...` runs the simulation without a window, see `headless::USAGE`
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("headless") {
+        std::process::exit(headless::run(&args[1..]));
+    }
+
     App::new()
         .add_plugins((
             DefaultPlugins,
@@ -6,6 +6,7 @@ use bevy::{
     },
     hierarchy::{BuildChildren, Children},
     input::{keyboard::KeyCode, ButtonInput},
+    log::{info, warn},
     pbr::StandardMaterial,
     prelude::default,
     render::{color::Color, mesh::Mesh},
@@ -18,7 +19,46 @@ use bevy::{
     },
 };
 
-use super::{PhsicaSimulationScheduler, SimulationStatus};
+use crate::hair_simulation::formats::{
+    polylines::{FrameExporter, PolylineFormat},
+    recording::{Recorder, Recording, RecordingError},
+    snapshot::{Snapshot, SnapshotFormat},
+};
+
+use super::{playback::Playback, PhsicaSimulationScheduler, SimulationStatus};
+
+// Where the E key writes the strands of every iteration
+const EXPORT_DIRECTORY: &str = "export";
+// Where S saves and L restores the simulation, in RON with shift held
+const SNAPSHOT_PATH: &str = "snapshots/snapshot";
+// Where R records every iteration and P plays them back
+const RECORDING_PATH: &str = "recordings/recording.hrec";
+
+// Space plays or pauses, the arrows step a frame, held with shift they scrub
+// through the recording, home goes back to its start
+fn playback_control(
+    playback: &mut Playback,
+    kbd: &ButtonInput<KeyCode>,
+    shift: bool,
+) -> Result<bool, RecordingError> {
+    if kbd.just_pressed(KeyCode::Space) {
+        if !playback.playing && playback.is_at_end() {
+            playback.seek(0)?;
+        }
+        playback.playing = !playback.playing;
+    } else if kbd.just_pressed(KeyCode::Home) {
+        playback.seek(0)?;
+    } else if kbd.just_pressed(KeyCode::ArrowRight) || shift && kbd.pressed(KeyCode::ArrowRight) {
+        playback.playing = false;
+        playback.step(1)?;
+    } else if kbd.just_pressed(KeyCode::ArrowLeft) || shift && kbd.pressed(KeyCode::ArrowLeft) {
+        playback.playing = false;
+        playback.step(-1)?;
+    } else {
+        return Ok(false);
+    }
+    Ok(true)
+}
 
 pub fn keyboard_control(
     mut commands: Commands,
@@ -27,6 +67,31 @@ pub fn keyboard_control(
     mut q: Query<&mut PhsicaSimulationScheduler>,
     kbd: Res<ButtonInput<KeyCode>>,
 ) {
+    let shift = kbd.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let snapshot_format = if shift {
+        SnapshotFormat::Ron
+    } else {
+        SnapshotFormat::Binary
+    };
+    let snapshot_path = format!("{}.{}", SNAPSHOT_PATH, snapshot_format.extension());
+
+    {
+        let mut scheduler = q.single_mut();
+        if let Some(playback) = scheduler.playback.as_mut() {
+            match playback_control(playback, &kbd, shift) {
+                Ok(false) => {}
+                Ok(true) => {
+                    scheduler.is_dirty = true;
+                    return;
+                }
+                Err(error) => {
+                    warn!("playback: {}", error);
+                    return;
+                }
+            }
+        }
+    }
+
     if kbd.just_pressed(KeyCode::Space) {
         let mut scheduler = q.single_mut();
         if scheduler.status == SimulationStatus::Running {
@@ -49,6 +114,89 @@ pub fn keyboard_control(
             scheduler.init_scheduler(&mut commands, meshes, materials, false);
             scheduler.singlestep_scheduler();
         }
+    } else if kbd.just_pressed(KeyCode::KeyH) {
+        let mut scheduler = q.single_mut();
+        let hair_collision = &mut scheduler.simulation_data.config.hair_collision;
+        hair_collision.enabled = !hair_collision.enabled;
+        info!("hair collision: {}", hair_collision.enabled);
+    } else if kbd.just_pressed(KeyCode::KeyE) {
+        let mut scheduler = q.single_mut();
+        if scheduler.exporter.take().is_none() {
+            let mut exporter = FrameExporter::new(EXPORT_DIRECTORY, PolylineFormat::Ply);
+            exporter.attributes.velocity = true;
+            exporter.attributes.twist = true;
+            exporter.attributes.curvature = true;
+            scheduler.exporter = Some(exporter);
+        }
+        info!(
+            "export to {}: {}",
+            EXPORT_DIRECTORY,
+            scheduler.exporter.is_some()
+        );
+    } else if kbd.just_pressed(KeyCode::KeyS) {
+        let scheduler = q.single();
+        match scheduler.snapshot().save(&snapshot_path) {
+            Ok(()) => info!("saved {}", snapshot_path),
+            Err(error) => warn!("cannot save {}: {}", snapshot_path, error),
+        }
+    } else if kbd.just_pressed(KeyCode::KeyL) {
+        let snapshot = match Snapshot::load(&snapshot_path) {
+            Ok(snapshot) => snapshot,
+            Err(error) => {
+                warn!("cannot restore {}: {}", snapshot_path, error);
+                return;
+            }
+        };
+        let mut scheduler = q.single_mut();
+        if scheduler.status == SimulationStatus::Stopped {
+            scheduler.init_scheduler(&mut commands, meshes, materials, false);
+        }
+        scheduler.restore_snapshot(&mut commands, snapshot);
+    } else if kbd.just_pressed(KeyCode::KeyR) {
+        let mut scheduler = q.single_mut();
+        if let Some(recorder) = scheduler.recorder.take() {
+            info!(
+                "recorded {} frames to {}",
+                recorder.frame_num, RECORDING_PATH
+            );
+            return;
+        }
+        // Recording would start over the file being played
+        if scheduler.playback.is_some() {
+            warn!("cannot record to {} while playing it", RECORDING_PATH);
+            return;
+        }
+        match Recorder::create(RECORDING_PATH, &scheduler.simulation_data) {
+            Ok(recorder) => {
+                info!("recording to {}", RECORDING_PATH);
+                scheduler.recorder = Some(recorder);
+            }
+            Err(error) => warn!("cannot record to {}: {}", RECORDING_PATH, error),
+        }
+    } else if kbd.just_pressed(KeyCode::KeyP) {
+        let mut scheduler = q.single_mut();
+        if scheduler.playback.take().is_some() {
+            info!("playback: off");
+            scheduler.is_dirty = true;
+            return;
+        }
+
+        // The solver waits while the recording is shown
+        if scheduler.status == SimulationStatus::Stopped {
+            scheduler.init_scheduler(&mut commands, meshes, materials, false);
+        } else if scheduler.status == SimulationStatus::Running {
+            scheduler.parse_scheduler();
+        }
+        let playback = Recording::open(RECORDING_PATH)
+            .and_then(|recording| Playback::new(recording, scheduler.simulation_data.clone()));
+        match playback {
+            Ok(playback) => {
+                info!("playback: {} frames", playback.frame_num);
+                scheduler.playback = Some(playback);
+                scheduler.is_dirty = true;
+            }
+            Err(error) => warn!("cannot play {}: {}", RECORDING_PATH, error),
+        }
     }
 }
 
@@ -22,6 +22,19 @@ struct PhysicDisplayRoot;
 #[derive(Component)]
 pub struct PhysicDisplayText;
 
+// A label and the value after it, so it is easy to update just the value
+fn section(label: &str) -> [TextSection; 2] {
+    let style = TextStyle {
+        font_size: 16.0,
+        color: Color::WHITE,
+        ..default()
+    };
+    [
+        TextSection::new(label, style.clone()),
+        TextSection::new(" N/A", style),
+    ]
+}
+
 pub fn setup_display(mut commands: Commands) {
     let root = commands
         .spawn((
@@ -50,53 +63,20 @@ pub fn setup_display(mut commands: Commands) {
         .spawn((
             PhysicDisplayText,
             TextBundle {
-                // use two sections, so it is easy to update just the number
-                text: Text::from_sections([
-                    TextSection {
-                        value: "Iteration: ".into(),
-                        style: TextStyle {
-                            font_size: 16.0,
-                            color: Color::WHITE,
-                            // if you want to use your game's font asset,
-                            // uncomment this and provide the handle:
-                            // font: my_font_handle
-                            ..default()
-                        },
-                    },
-                    TextSection {
-                        value: " N/A".into(),
-                        style: TextStyle {
-                            font_size: 16.0,
-                            color: Color::WHITE,
-                            // if you want to use your game's font asset,
-                            // uncomment this and provide the handle:
-                            // font: my_font_handle
-                            ..default()
-                        },
-                    },
-                    TextSection {
-                        value: "\nElapsed: ".into(),
-                        style: TextStyle {
-                            font_size: 16.0,
-                            color: Color::WHITE,
-                            // if you want to use your game's font asset,
-                            // uncomment this and provide the handle:
-                            // font: my_font_handle
-                            ..default()
-                        },
-                    },
-                    TextSection {
-                        value: " N/A".into(),
-                        style: TextStyle {
-                            font_size: 16.0,
-                            color: Color::WHITE,
-                            // if you want to use your game's font asset,
-                            // uncomment this and provide the handle:
-                            // font: my_font_handle
-                            ..default()
-                        },
-                    },
-                ]),
+                text: Text::from_sections(
+                    [
+                        section("Iteration: "),
+                        section("\nElapsed: "),
+                        section("\nErrors: "),
+                        section("\nEnergy: "),
+                        section("\nMomentum: "),
+                        section("\nSolves: "),
+                        section("\nSubsteps: "),
+                        section("\nContacts: "),
+                    ]
+                    .into_iter()
+                    .flatten(),
+                ),
                 ..Default::default()
             },
         ))
@@ -116,12 +96,87 @@ pub fn simulation_text_update_system(
                 let last_elapsed = s.last_elapsed.as_millis();
                 text.sections[1].value = format!("{iteration_cnt:>4.0}");
                 text.sections[3].value = format!("{last_elapsed:>4.0} ms");
+                // The recorded values of the frame shown instead
+                if let Some(playback) = s.playback.as_ref() {
+                    let frame = &playback.frame;
+                    let state = if playback.playing {
+                        "playing"
+                    } else {
+                        "paused"
+                    };
+                    text.sections[1].value = format!(
+                        "{:>4.0} (frame {}/{}, {state})",
+                        frame.iteration_cnt,
+                        playback.frame_index + 1,
+                        playback.frame_num
+                    );
+                    text.sections[3].value = format!("{:>4.0} ms", frame.elapsed.as_millis());
+                }
+
+                let error_cnt = s.error_cnt;
+                match &s.last_error {
+                    Some(error) => {
+                        text.sections[5].value = format!("{error_cnt:>4.0} ({error})");
+                        text.sections[5].style.color = Color::RED;
+                    }
+                    None => {
+                        text.sections[5].value = format!("{error_cnt:>4.0}");
+                        text.sections[5].style.color = Color::WHITE;
+                    }
+                }
+
+                let total = &s.diagnostics.total;
+                let mut energy = format!("{:.4e} J", total.total_energy());
+                for (name, value) in total.potential.iter() {
+                    energy += &format!("\n  {name}: {value:.4e}");
+                }
+                energy += &format!("\n  kinetic: {:.4e}", total.kinetic);
+                text.sections[7].value = energy;
+
+                let momentum = total.momentum;
+                text.sections[9].value = format!(
+                    "({:.3e}, {:.3e}, {:.3e})",
+                    momentum.x, momentum.y, momentum.z
+                );
+
+                let iterations = &s.diagnostics.iterations;
+                let max_iterations = iterations.iter().max().copied().unwrap_or(0);
+                let mean_iterations =
+                    iterations.iter().sum::<usize>() as f64 / iterations.len().max(1) as f64;
+                text.sections[11].value =
+                    format!("{max_iterations:>4.0} max, {mean_iterations:.1} mean per strand");
+
+                let (substeps, substep_retries) = match s.playback.as_ref() {
+                    Some(playback) => (playback.frame.substeps, playback.frame.substep_retries),
+                    None => (s.substeps, s.substep_retries),
+                };
+                text.sections[13].value = format!("{substeps:>4.0} ({substep_retries} redone)");
+
+                // Largest count over the steps of the frame
+                let contacts = s
+                    .diagnostics
+                    .contacts
+                    .iter()
+                    .max_by_key(|contacts| contacts.total + contacts.between_strands)
+                    .copied()
+                    .unwrap_or_default();
+                text.sections[15].value = format!(
+                    "{:>4.0} ({} sticking, {} sliding), {} between strands",
+                    contacts.total, contacts.sticking, contacts.sliding, contacts.between_strands
+                );
             }
             Err(_) => {
                 text.sections[1].value = " N/A".into();
                 text.sections[1].style.color = Color::WHITE;
                 text.sections[3].value = " N/A".into();
                 text.sections[3].style.color = Color::WHITE;
+                text.sections[5].value = " N/A".into();
+                text.sections[5].style.color = Color::WHITE;
+                text.sections[7].value = " N/A".into();
+                text.sections[9].value = " N/A".into();
+                text.sections[11].value = " N/A".into();
+                text.sections[13].value = " N/A".into();
+                text.sections[15].value = " N/A".into();
             }
         }
     }
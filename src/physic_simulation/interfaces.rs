@@ -1,11 +1,119 @@
+use std::fmt;
+
 use instant::Duration;
 
 use crate::hair_simulation::data::SimulationData;
+extern crate nalgebra as na;
 
 #[derive(Default, Clone)]
 pub struct SimulationTaskInterface {
     pub iteration_cnt: u64,
+    // Generation of the scheduler state the step started from
+    pub generation: u64,
     pub delta_time: f64,
     pub data: SimulationData,
     pub elapsed: Duration,
+    pub errors: Vec<SimulationError>,
+    pub diagnostics: SimulationDiagnostics,
+    // Solver steps the frame was split into, and the ones redone with a smaller step
+    pub substeps: usize,
+    pub substep_retries: usize,
+}
+
+// Energies at the start of the frame, before the solve
+#[derive(Default, Clone, Debug)]
+pub struct EnergyDiagnostics {
+    // Potential of every registered term, by term name
+    pub potential: Vec<(&'static str, f64)>,
+    pub kinetic: f64,
+    pub momentum: na::Vector3<f64>,
+}
+
+impl EnergyDiagnostics {
+    pub fn potential_energy(&self) -> f64 {
+        self.potential.iter().map(|(_, energy)| energy).sum()
+    }
+
+    pub fn total_energy(&self) -> f64 {
+        self.potential_energy() + self.kinetic
+    }
+
+    pub fn accumulate(&mut self, other: &EnergyDiagnostics) {
+        for (name, energy) in other.potential.iter() {
+            match self.potential.iter_mut().find(|(n, _)| n == name) {
+                Some((_, total)) => *total += energy,
+                None => self.potential.push((name, *energy)),
+            }
+        }
+        self.kinetic += other.kinetic;
+        self.momentum += other.momentum;
+    }
+}
+
+// Vertices within the skin of a collider, one per vertex and collider, and
+// close pairs of segments of different strands
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContactCounts {
+    pub total: usize,
+    // Moving towards the surface, held by static friction or sliding on it
+    pub sticking: usize,
+    pub sliding: usize,
+    // Segment pairs of different strands closer than the hair collision distance
+    pub between_strands: usize,
+}
+
+impl ContactCounts {
+    pub fn accumulate(&mut self, other: &ContactCounts) {
+        self.total += other.total;
+        self.sticking += other.sticking;
+        self.sliding += other.sliding;
+        self.between_strands += other.between_strands;
+    }
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct SimulationDiagnostics {
+    pub strands: Vec<EnergyDiagnostics>,
+    pub total: EnergyDiagnostics,
+    // Linear solves taken by each strand over the frame, zero for a failed or
+    // frozen strand
+    pub iterations: Vec<usize>,
+    // Contacts resolved by every solver step of the frame
+    pub contacts: Vec<ContactCounts>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimulationErrorKind {
+    // NaN or infinity in the assembled forces or Hessian
+    NonFiniteSystem,
+    // The factorization broke down
+    SingularSystem,
+    // The solve went through but produced NaN or infinity
+    NonFiniteSolution,
+}
+
+// What the step did about a failed strand
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailureAction {
+    Regularized,
+    Reverted,
+    Frozen,
+}
+
+#[derive(Clone, Debug)]
+pub struct SimulationError {
+    pub iteration_cnt: u64,
+    pub strand: usize,
+    pub kind: SimulationErrorKind,
+    pub action: FailureAction,
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{} strand {}: {:?}, {:?}",
+            self.iteration_cnt, self.strand, self.kind, self.action
+        )
+    }
 }
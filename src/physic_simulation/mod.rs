@@ -2,8 +2,12 @@ mod communication;
 mod control;
 mod display;
 pub mod interfaces;
+mod playback;
 pub mod scheduler;
 
+#[cfg(test)]
+mod tests;
+
 use bevy::app::{App, Plugin, Startup, Update};
 
 use scheduler::*;
@@ -11,6 +15,7 @@ use scheduler::*;
 use self::{
     control::{button_system, keyboard_control, setup_button},
     display::{setup_display, simulation_text_update_system},
+    playback::playback_system,
 };
 
 pub struct PhysicSimulationPlugin;
@@ -22,6 +27,7 @@ impl Plugin for PhysicSimulationPlugin {
             Update,
             (
                 schedule_simulation,
+                playback_system,
                 keyboard_control,
                 simulation_text_update_system,
                 button_system,
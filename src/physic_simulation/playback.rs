@@ -0,0 +1,83 @@
+use bevy::{ecs::system::Query, log::warn};
+
+use crate::hair_simulation::{
+    data::SimulationData,
+    formats::recording::{RecordedFrame, Recording, RecordingError, RecordingLayout},
+};
+
+use super::PhsicaSimulationScheduler;
+
+// Draws the frames of a recording instead of the simulated state, which is
+// left as it was
+pub struct Playback {
+    pub recording: Recording,
+    pub frame_index: usize,
+    pub frame_num: usize,
+    pub frame: RecordedFrame,
+    // Advance one frame per update
+    pub playing: bool,
+    // The simulation data with the head and the vertices of the frame
+    pub data: SimulationData,
+}
+
+impl Playback {
+    pub fn new(mut recording: Recording, data: SimulationData) -> Result<Self, RecordingError> {
+        if recording.layout != RecordingLayout::of(&data) {
+            return Err(RecordingError::Layout);
+        }
+        let frame_num = recording.frame_num()?;
+        if frame_num == 0 {
+            return Err(RecordingError::Empty);
+        }
+        let frame = recording.frame(0)?;
+        let mut playback = Playback {
+            recording,
+            frame_index: 0,
+            frame_num,
+            frame,
+            playing: false,
+            data,
+        };
+        playback.frame.apply(&mut playback.data);
+        Ok(playback)
+    }
+
+    // Show the frame at index, clamped to the recording
+    pub fn seek(&mut self, index: usize) -> Result<(), RecordingError> {
+        // The recording may still be growing, or have been started over
+        self.frame_num = self.recording.frame_num()?;
+        if self.frame_num == 0 {
+            return Err(RecordingError::Empty);
+        }
+        self.frame_index = index.min(self.frame_num - 1);
+        self.frame = self.recording.frame(self.frame_index)?;
+        self.frame.apply(&mut self.data);
+        Ok(())
+    }
+
+    pub fn step(&mut self, frames: isize) -> Result<(), RecordingError> {
+        self.seek(self.frame_index.saturating_add_signed(frames))
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.frame_index + 1 >= self.frame_num
+    }
+}
+
+pub fn playback_system(mut q: Query<&mut PhsicaSimulationScheduler>) {
+    let mut scheduler = q.single_mut();
+    let Some(playback) = scheduler.playback.as_mut() else {
+        return;
+    };
+    if !playback.playing {
+        return;
+    }
+
+    if let Err(error) = playback.step(1) {
+        warn!("playback: {}", error);
+        playback.playing = false;
+    } else if playback.is_at_end() {
+        playback.playing = false;
+    }
+    scheduler.is_dirty = true;
+}
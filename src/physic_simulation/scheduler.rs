@@ -1,11 +1,16 @@
 use crate::hair_simulation::conversion::{init_simulation, reset_simulation};
 use crate::hair_simulation::data::SimulationData;
+use crate::hair_simulation::formats::polylines::FrameExporter;
+use crate::hair_simulation::formats::recording::{RecordedFrame, Recorder};
+use crate::hair_simulation::formats::snapshot::Snapshot;
+use crate::hair_simulation::interpolation::RenderHairs;
 use crate::hair_simulation::simulation::do_simulate;
 
 use super::communication::{
     init_simulation_channel, SimulationResultReceiver, SimulationResultSender,
 };
 use super::interfaces::*;
+use super::playback::Playback;
 
 use bevy::ecs::entity::Entity;
 use bevy::tasks::AsyncComputeTaskPool;
@@ -16,7 +21,7 @@ use bevy::{
         component::Component,
         system::{Commands, Query, ResMut},
     },
-    log::info,
+    log::{info, warn},
     pbr::StandardMaterial,
     render::mesh::Mesh,
 };
@@ -33,13 +38,29 @@ pub enum SimulationStatus {
 pub struct PhsicaSimulationScheduler {
     // iteration cnt
     pub iteration_cnt: u64,
+    // bumped whenever the state is replaced, steps started before are dropped
+    pub generation: u64,
     pub last_elapsed: Duration,
+    // failures reported by the solver since the start
+    pub error_cnt: u64,
+    pub last_error: Option<SimulationError>,
+    // energies and momentum of the last step
+    pub diagnostics: SimulationDiagnostics,
+    // substeps the last frame was split into
+    pub substeps: usize,
+    pub substep_retries: usize,
     pub status: SimulationStatus,
     pub entities: HashMap<String, Entity>,
     pub simulation_data: SimulationData,
     pub sender: SimulationResultSender,
     pub receiver: SimulationResultReceiver,
     pub is_dirty: bool,
+    // writes the strands of every received iteration when set
+    pub exporter: Option<FrameExporter>,
+    // appends every received iteration when set
+    pub recorder: Option<Recorder>,
+    // drawn instead of the simulation data when set
+    pub playback: Option<Playback>,
 }
 
 impl PhsicaSimulationScheduler {
@@ -49,9 +70,14 @@ impl PhsicaSimulationScheduler {
 
         let mut task_interface = SimulationTaskInterface {
             iteration_cnt: self.iteration_cnt,
+            generation: self.generation,
             data,
-            delta_time: 0.005,
+            delta_time: self.simulation_data.config.time_stepping.frame_time,
             elapsed: Default::default(),
+            errors: Vec::new(),
+            diagnostics: Default::default(),
+            substeps: 0,
+            substep_retries: 0,
         };
 
         let sender = self.sender.0.clone();
@@ -102,10 +128,52 @@ impl PhsicaSimulationScheduler {
         self.status = SimulationStatus::Paused;
         info!("parse_scheduler");
     }
+    pub fn displayed_data(&self) -> &SimulationData {
+        match self.playback.as_ref() {
+            Some(playback) => &playback.data,
+            None => &self.simulation_data,
+        }
+    }
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(self.iteration_cnt, self.simulation_data.clone())
+    }
+    // Continue from a snapshot, paused. The scene must have been initialised.
+    pub fn restore_snapshot(&mut self, commands: &mut Commands, snapshot: Snapshot) {
+        // The render strands follow guides by index, so other guides are drawn
+        // as they are
+        let strand_num = self.simulation_data.hairs.strands.len();
+        if snapshot.data.hairs.strands.len() != strand_num {
+            if let Some(&hairs) = self.entities.get("hairs") {
+                commands.entity(hairs).remove::<RenderHairs>();
+            }
+        }
+
+        self.status = SimulationStatus::Paused;
+        self.generation += 1;
+        self.iteration_cnt = snapshot.iteration_cnt;
+        self.simulation_data = snapshot.data;
+        self.diagnostics = Default::default();
+        self.substeps = 0;
+        self.substep_retries = 0;
+        self.is_dirty = true;
+
+        info!("restore_snapshot: iteration {}", self.iteration_cnt);
+        for warning in self.simulation_data.config.warnings() {
+            warn!("{}", warning);
+        }
+    }
     pub fn stop_scheduler(&mut self, commands: &mut Commands) {
         self.status = SimulationStatus::Stopped;
+        self.generation += 1;
         self.iteration_cnt = 0;
         self.last_elapsed = Default::default();
+        self.error_cnt = 0;
+        self.last_error = None;
+        self.diagnostics = Default::default();
+        self.substeps = 0;
+        self.substep_retries = 0;
+        self.recorder = None;
+        self.playback = None;
 
         // Do some cleanup
         reset_simulation(self, commands);
@@ -120,13 +188,22 @@ pub fn setup_scheduler(mut commands: Commands) {
     let (sender, receiver) = init_simulation_channel();
     let _ = commands.spawn((PhsicaSimulationScheduler {
         iteration_cnt: 0,
+        generation: 0,
         last_elapsed: Default::default(),
+        error_cnt: 0,
+        last_error: None,
+        diagnostics: Default::default(),
+        substeps: 0,
+        substep_retries: 0,
         status: SimulationStatus::Stopped,
         entities: HashMap::new(),
         simulation_data: SimulationData::default(),
         sender: SimulationResultSender(sender),
         receiver: SimulationResultReceiver(receiver),
         is_dirty: false,
+        exporter: None,
+        recorder: None,
+        playback: None,
     },));
 }
 
@@ -136,14 +213,65 @@ pub fn schedule_simulation(mut q: Query<&mut PhsicaSimulationScheduler>) {
         if scheduler.status == SimulationStatus::Stopped {
             return;
         }
+        // Started before a snapshot or a stop replaced the state, even one at
+        // the same iteration
+        if task_interface.generation != scheduler.generation {
+            info!("drop data of iteration {}", task_interface.iteration_cnt);
+            return;
+        }
 
         info!("receive data");
+        // Settings changed while the step ran are kept for the next one
+        let config = scheduler.simulation_data.config.clone();
         scheduler.simulation_data = task_interface.data;
+        scheduler.simulation_data.config = config;
         info!("elapsed: {:?}", task_interface.elapsed);
         scheduler.iteration_cnt += 1;
         scheduler.last_elapsed = task_interface.elapsed;
         scheduler.is_dirty = true;
 
+        let total = &task_interface.diagnostics.total;
+        info!(
+            "energy: {:.6e} (potential {:.6e}, kinetic {:.6e}), momentum: {:?}",
+            total.total_energy(),
+            total.potential_energy(),
+            total.kinetic,
+            total.momentum
+        );
+        info!("linear solves: {:?}", task_interface.diagnostics.iterations);
+        info!("contacts: {:?}", task_interface.diagnostics.contacts);
+        scheduler.diagnostics = task_interface.diagnostics;
+        scheduler.substeps = task_interface.substeps;
+        scheduler.substep_retries = task_interface.substep_retries;
+
+        if !task_interface.errors.is_empty() {
+            warn!("{} strand(s) failed", task_interface.errors.len());
+            scheduler.error_cnt += task_interface.errors.len() as u64;
+            scheduler.last_error = task_interface.errors.last().cloned();
+        }
+
+        if let Some(exporter) = scheduler.exporter.as_ref() {
+            let iteration = scheduler.iteration_cnt;
+            match exporter.export(&scheduler.simulation_data.hairs, iteration) {
+                Ok(path) => info!("exported {}", path.display()),
+                Err(error) => warn!("export of iteration {} failed: {}", iteration, error),
+            }
+        }
+
+        if let Some(mut recorder) = scheduler.recorder.take() {
+            let frame = RecordedFrame::from_data(
+                scheduler.iteration_cnt,
+                task_interface.elapsed,
+                task_interface.substeps,
+                task_interface.substep_retries,
+                &scheduler.simulation_data,
+            );
+            match recorder.record(&frame) {
+                Ok(()) => scheduler.recorder = Some(recorder),
+                Err(error) => warn!("recording stopped: {}", error),
+            }
+        }
+
         // TODO: update the simulation data to the world
 
         if scheduler.status == SimulationStatus::Running {
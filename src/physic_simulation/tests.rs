@@ -0,0 +1,60 @@
+use std::fs;
+
+use instant::Duration;
+
+use crate::hair_simulation::{
+    data::{hair_strand_from_vertices, Hairs, SimulationData},
+    formats::recording::{RecordedFrame, Recorder, Recording, RecordingError},
+};
+
+use super::playback::Playback;
+extern crate nalgebra as na;
+
+fn data() -> SimulationData {
+    let positions: Vec<na::Vector3<f64>> = (0..4)
+        .map(|i| na::Vector3::new(0.0, -0.1 * i as f64, 0.0))
+        .collect();
+    SimulationData {
+        hairs: Hairs {
+            strands: vec![hair_strand_from_vertices(
+                &positions, None, 1e-6, 1e9, 1e9, 1e-4, 1,
+            )],
+        },
+        ..Default::default()
+    }
+}
+
+#[test]
+fn playback_of_a_recording_started_over() {
+    let path = std::env::temp_dir().join(format!("playback_{}.hrec", std::process::id()));
+    let mut data = data();
+    let mut recorder = Recorder::create(&path, &data).unwrap();
+    for i in 0..3 {
+        data.head.position.x = i as f64;
+        let frame = RecordedFrame::from_data(i, Duration::ZERO, 1, 0, &data);
+        recorder.record(&frame).unwrap();
+    }
+    drop(recorder);
+
+    let mut playback = Playback::new(Recording::open(&path).unwrap(), data.clone()).unwrap();
+    playback.seek(2).unwrap();
+    assert_eq!(playback.data.head.position.x, 2.0);
+
+    // Recording again to the same file leaves it without frames
+    let mut recorder = Recorder::create(&path, &data).unwrap();
+    for index in [0, 2] {
+        assert!(matches!(playback.seek(index), Err(RecordingError::Empty)));
+    }
+    assert!(matches!(playback.step(-1), Err(RecordingError::Empty)));
+
+    // And shows the new frames once there are some
+    data.head.position.x = 7.0;
+    let frame = RecordedFrame::from_data(0, Duration::ZERO, 1, 0, &data);
+    recorder.record(&frame).unwrap();
+    playback.seek(2).unwrap();
+    assert_eq!((playback.frame_index, playback.frame_num), (0, 1));
+    assert_eq!(playback.data.head.position.x, 7.0);
+
+    drop(recorder);
+    fs::remove_file(&path).unwrap();
+}